@@ -24,23 +24,17 @@ pub fn main() {
     // connect to the adapter
     let central = get_central(&manager);
 
-    // Each adapter can only have one event receiver. We fetch it via
-    // event_receiver(), which will return an option. The first time the getter
-    // is called, it will return Some(Receiver<CentralEvent>). After that, it
-    // will only return None.
-    //
-    // While this API is awkward, is is done as not to disrupt the adapter
-    // retrieval system in btleplug v0.x while still allowing us to use event
-    // streams/channels instead of callbacks. In btleplug v1.x, we'll retrieve
-    // channels as part of adapter construction.
-    let event_receiver = central.event_receiver().unwrap();
+    // Each call to event_receiver() returns a new, independent receiver for the
+    // adapter's events. Use subscribe_events() instead to only receive events
+    // about some devices or of some kinds.
+    let event_receiver = central.event_receiver();
 
     // start scanning for devices
     central.start_scan().unwrap();
 
-    // Print based on whatever the event receiver outputs. Note that the event
-    // receiver blocks, so in a real program, this should be run in its own
-    // thread (not task, as this library does not yet use async channels).
+    // Print based on whatever the event receiver outputs. Note that recv()
+    // blocks, so in a real program, this should be run in its own thread, or
+    // the receiver should be used as a Stream from an async task.
     while let Ok(event) = event_receiver.recv() {
         match event {
             CentralEvent::DeviceDiscovered(bd_addr) => {
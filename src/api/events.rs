@@ -0,0 +1,442 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Subscriptions to [`CentralEvent`](../enum.CentralEvent.html)s.
+//!
+//! Any number of [`EventReceiver`]s can be subscribed to an adapter. Each one gets its own bounded
+//! queue, and only receives the events matching its [`EventFilter`].
+
+use super::{BDAddr, CentralEvent};
+use crate::common::queue::Queue;
+use futures::stream::Stream;
+use std::{
+    fmt::{self, Debug, Formatter},
+    pin::Pin,
+    sync::{
+        mpsc::{RecvError, RecvTimeoutError, TryRecvError},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// The kind of a [`CentralEvent`](../enum.CentralEvent.html), without its data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CentralEventKind {
+    DeviceDiscovered,
+    DeviceLost,
+    DeviceUpdated,
+    DeviceConnected,
+    DeviceDisconnected,
+    DeviceReconnected,
+    DeviceReconnectFailed,
+    ManufacturerDataAdvertisement,
+    ServiceDataAdvertisement,
+    ServicesAdvertisement,
+    DeviceBondChanged,
+    ServicesChanged,
+    CharacteristicsInvalidated,
+    SubscriptionChanged,
+    AdapterUpdated,
+    BluetoothServiceLost,
+    BluetoothServiceRestored,
+    Error,
+}
+
+impl CentralEvent {
+    /// Returns the kind of this event.
+    pub fn kind(&self) -> CentralEventKind {
+        match self {
+            CentralEvent::DeviceDiscovered(_) => CentralEventKind::DeviceDiscovered,
+            CentralEvent::DeviceLost(_) => CentralEventKind::DeviceLost,
+            CentralEvent::DeviceUpdated(_) => CentralEventKind::DeviceUpdated,
+            CentralEvent::DeviceConnected(_) => CentralEventKind::DeviceConnected,
+            CentralEvent::DeviceDisconnected(_) => CentralEventKind::DeviceDisconnected,
+            CentralEvent::DeviceReconnected(_) => CentralEventKind::DeviceReconnected,
+            CentralEvent::DeviceReconnectFailed { .. } => CentralEventKind::DeviceReconnectFailed,
+            CentralEvent::ManufacturerDataAdvertisement { .. } => {
+                CentralEventKind::ManufacturerDataAdvertisement
+            }
+            CentralEvent::ServiceDataAdvertisement { .. } => {
+                CentralEventKind::ServiceDataAdvertisement
+            }
+            CentralEvent::ServicesAdvertisement { .. } => CentralEventKind::ServicesAdvertisement,
+            CentralEvent::DeviceBondChanged { .. } => CentralEventKind::DeviceBondChanged,
+            CentralEvent::ServicesChanged(_) => CentralEventKind::ServicesChanged,
+            CentralEvent::CharacteristicsInvalidated { .. } => {
+                CentralEventKind::CharacteristicsInvalidated
+            }
+            CentralEvent::SubscriptionChanged { .. } => CentralEventKind::SubscriptionChanged,
+            CentralEvent::AdapterUpdated(_) => CentralEventKind::AdapterUpdated,
+            CentralEvent::BluetoothServiceLost => CentralEventKind::BluetoothServiceLost,
+            CentralEvent::BluetoothServiceRestored => CentralEventKind::BluetoothServiceRestored,
+            CentralEvent::Error { .. } => CentralEventKind::Error,
+        }
+    }
+
+    /// Returns the address of the peripheral this event is about, if any.
+    pub fn address(&self) -> Option<BDAddr> {
+        match self {
+            CentralEvent::DeviceDiscovered(address)
+            | CentralEvent::DeviceLost(address)
+            | CentralEvent::DeviceUpdated(address)
+            | CentralEvent::DeviceConnected(address)
+            | CentralEvent::DeviceDisconnected(address)
+            | CentralEvent::DeviceReconnected(address)
+            | CentralEvent::DeviceReconnectFailed { address, .. }
+            | CentralEvent::ServicesChanged(address)
+            | CentralEvent::CharacteristicsInvalidated { address, .. }
+            | CentralEvent::SubscriptionChanged { address, .. }
+            | CentralEvent::ManufacturerDataAdvertisement { address, .. }
+            | CentralEvent::ServiceDataAdvertisement { address, .. }
+            | CentralEvent::ServicesAdvertisement { address, .. }
+            | CentralEvent::DeviceBondChanged { address, .. } => Some(*address),
+            CentralEvent::Error { address, .. } => *address,
+            CentralEvent::AdapterUpdated(_)
+            | CentralEvent::BluetoothServiceLost
+            | CentralEvent::BluetoothServiceRestored => None,
+        }
+    }
+}
+
+/// Selects the events delivered to a subscriber. The default filter lets every event through.
+///
+/// ```
+/// use btleplug::api::{CentralEventKind, EventFilter};
+///
+/// let filter = EventFilter::default()
+///     .address("0A:0B:0C:0D:0E:0F".parse().unwrap())
+///     .kind(CentralEventKind::DeviceConnected)
+///     .kind(CentralEventKind::DeviceDisconnected);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+    /// Only deliver events about one of these peripherals. When empty, events about any
+    /// peripheral are delivered. Events that aren't about a peripheral are always delivered.
+    pub addresses: Vec<BDAddr>,
+    /// Only deliver events of one of these kinds. When empty, events of any kind are delivered.
+    pub kinds: Vec<CentralEventKind>,
+}
+
+impl EventFilter {
+    /// Adds a peripheral to the set of peripherals whose events are delivered.
+    pub fn address(mut self, address: BDAddr) -> Self {
+        self.addresses.push(address);
+        self
+    }
+
+    /// Adds a kind to the set of event kinds that are delivered.
+    pub fn kind(mut self, kind: CentralEventKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Returns true if `event` passes this filter.
+    pub fn matches(&self, event: &CentralEvent) -> bool {
+        let address_matches = match event.address() {
+            Some(address) => self.addresses.is_empty() || self.addresses.contains(&address),
+            None => true,
+        };
+        address_matches && (self.kinds.is_empty() || self.kinds.contains(&event.kind()))
+    }
+}
+
+/// What to do with a new event when a subscriber's queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued event to make room for the new one. The number of discarded
+    /// events is reported by [`EventReceiver::dropped`].
+    DropOldest,
+    /// Wait until the subscriber makes room. Events are emitted from the thread processing
+    /// messages from the Bluetooth stack, so a subscriber that stops reading will hold up every
+    /// other subscriber, and pending operations, until it is dropped.
+    Block,
+}
+
+/// The options of an event subscription, passed to
+/// [`Central::subscribe_events`](../trait.Central.html#tymethod.subscribe_events).
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventSubscription {
+    /// The events to deliver.
+    pub filter: EventFilter,
+    /// The maximum number of events queued for the subscriber. Must be at least 1.
+    pub capacity: usize,
+    /// What to do when the queue is full.
+    pub overflow: OverflowPolicy,
+}
+
+impl EventSubscription {
+    /// The capacity of a default subscription.
+    pub const DEFAULT_CAPACITY: usize = 256;
+
+    /// Sets the filter of the subscription.
+    pub fn filter(mut self, filter: EventFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Sets the queue capacity of the subscription.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the overflow policy of the subscription.
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+impl Default for EventSubscription {
+    fn default() -> Self {
+        EventSubscription {
+            filter: EventFilter::default(),
+            capacity: EventSubscription::DEFAULT_CAPACITY,
+            overflow: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+/// An event subscriber, with its own queue.
+#[derive(Debug)]
+struct Subscriber {
+    subscription: EventSubscription,
+    queue: Queue<CentralEvent>,
+}
+
+/// Distributes events to every subscriber of an adapter.
+#[derive(Debug, Default)]
+pub(crate) struct EventBus {
+    subscribers: Mutex<Vec<Arc<Subscriber>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self, subscription: EventSubscription) -> EventReceiver {
+        let subscriber = Arc::new(Subscriber {
+            queue: Queue::new(subscription.capacity, subscription.overflow),
+            subscription,
+        });
+        self.subscribers.lock().unwrap().push(subscriber.clone());
+        EventReceiver { subscriber }
+    }
+
+    /// Sends `event` to every interested subscriber, and forgets the ones that went away.
+    pub fn emit(&self, event: CentralEvent) {
+        // Delivery happens without holding the list, as a blocking subscriber could otherwise
+        // keep anyone from subscribing.
+        let subscribers = self.subscribers.lock().unwrap().clone();
+        let mut gone = vec![];
+        for subscriber in subscribers {
+            let alive = if subscriber.subscription.filter.matches(&event) {
+                subscriber.queue.push(event.clone())
+            } else {
+                !subscriber.queue.is_receiver_gone()
+            };
+            if !alive {
+                gone.push(subscriber);
+            }
+        }
+        if !gone.is_empty() {
+            self.subscribers
+                .lock()
+                .unwrap()
+                .retain(|s| !gone.iter().any(|g| Arc::ptr_eq(s, g)));
+        }
+    }
+
+    #[cfg(test)]
+    fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+impl Drop for EventBus {
+    fn drop(&mut self) {
+        for subscriber in self.subscribers.lock().unwrap().iter() {
+            subscriber.queue.close();
+        }
+    }
+}
+
+/// Receives the events of a subscription, see
+/// [`Central::subscribe_events`](../trait.Central.html#tymethod.subscribe_events).
+///
+/// Events can be read with the blocking `recv` methods, which mirror those of
+/// `std::sync::mpsc::Receiver`, or as a `futures::Stream`. Once the adapter has been dropped and
+/// all queued events have been read, `recv` returns an error and the stream ends.
+pub struct EventReceiver {
+    subscriber: Arc<Subscriber>,
+}
+
+impl EventReceiver {
+    /// Waits for the next event.
+    pub fn recv(&self) -> Result<CentralEvent, RecvError> {
+        self.subscriber.queue.recv()
+    }
+
+    /// Returns the next event if one is queued, without waiting.
+    pub fn try_recv(&self) -> Result<CentralEvent, TryRecvError> {
+        self.subscriber.queue.try_recv()
+    }
+
+    /// Waits up to `timeout` for the next event.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<CentralEvent, RecvTimeoutError> {
+        self.subscriber.queue.recv_timeout(timeout)
+    }
+
+    /// Returns an iterator that waits for events, and ends once the adapter is gone.
+    pub fn iter(&self) -> impl Iterator<Item = CentralEvent> + '_ {
+        std::iter::from_fn(move || self.recv().ok())
+    }
+
+    /// Returns an iterator over the events already queued, without waiting.
+    pub fn try_iter(&self) -> impl Iterator<Item = CentralEvent> + '_ {
+        std::iter::from_fn(move || self.try_recv().ok())
+    }
+
+    /// Returns the number of events discarded so far because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.subscriber.queue.dropped()
+    }
+}
+
+impl Stream for EventReceiver {
+    type Item = CentralEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<CentralEvent>> {
+        self.subscriber.queue.poll_next(cx)
+    }
+}
+
+impl Debug for EventReceiver {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("EventReceiver")
+            .field("subscription", &self.subscriber.subscription)
+            .finish()
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.subscriber.queue.close_receiver();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, StreamExt};
+    use std::thread;
+
+    fn address(last: u8) -> BDAddr {
+        BDAddr {
+            address: [last, 0, 0, 0, 0, 0],
+        }
+    }
+
+    #[test]
+    fn every_subscriber_gets_matching_events() {
+        let bus = EventBus::default();
+        let all = bus.subscribe(EventSubscription::default());
+        let connections = bus.subscribe(
+            EventSubscription::default().filter(
+                EventFilter::default()
+                    .kind(CentralEventKind::DeviceConnected)
+                    .kind(CentralEventKind::DeviceDisconnected),
+            ),
+        );
+        let one_device = bus.subscribe(
+            EventSubscription::default().filter(EventFilter::default().address(address(2))),
+        );
+
+        bus.emit(CentralEvent::DeviceDiscovered(address(1)));
+        bus.emit(CentralEvent::DeviceConnected(address(1)));
+        bus.emit(CentralEvent::DeviceConnected(address(2)));
+
+        assert_eq!(all.iter().take(3).count(), 3);
+        assert_eq!(all.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert_eq!(connections.try_recv().unwrap().address(), Some(address(1)));
+        assert_eq!(connections.try_recv().unwrap().address(), Some(address(2)));
+        assert!(connections.try_recv().is_err());
+        assert_eq!(
+            one_device.try_recv().unwrap().kind(),
+            CentralEventKind::DeviceConnected
+        );
+        assert!(one_device.try_recv().is_err());
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_events() {
+        let bus = EventBus::default();
+        let receiver = bus.subscribe(EventSubscription::default().capacity(2));
+        for i in 0..5 {
+            bus.emit(CentralEvent::DeviceUpdated(address(i)));
+        }
+        assert_eq!(receiver.dropped(), 3);
+        assert_eq!(receiver.try_recv().unwrap().address(), Some(address(3)));
+        assert_eq!(receiver.try_recv().unwrap().address(), Some(address(4)));
+    }
+
+    #[test]
+    fn block_waits_for_the_subscriber() {
+        let bus = Arc::new(EventBus::default());
+        let receiver = bus.subscribe(
+            EventSubscription::default()
+                .capacity(1)
+                .overflow(OverflowPolicy::Block),
+        );
+        let emitter = {
+            let bus = bus.clone();
+            thread::spawn(move || {
+                for i in 0..3 {
+                    bus.emit(CentralEvent::DeviceUpdated(address(i)));
+                }
+            })
+        };
+        for i in 0..3 {
+            let event = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(event.address(), Some(address(i)));
+        }
+        emitter.join().unwrap();
+        assert_eq!(receiver.dropped(), 0);
+    }
+
+    #[test]
+    fn dropped_subscribers_are_forgotten() {
+        let bus = Arc::new(EventBus::default());
+        let kept = bus.subscribe(EventSubscription::default());
+        let blocking = bus.subscribe(
+            EventSubscription::default()
+                .capacity(1)
+                .overflow(OverflowPolicy::Block),
+        );
+        bus.emit(CentralEvent::DeviceUpdated(address(0)));
+        let emitter = {
+            let bus = bus.clone();
+            // This blocks until the subscriber below goes away.
+            thread::spawn(move || bus.emit(CentralEvent::DeviceUpdated(address(1))))
+        };
+        thread::sleep(Duration::from_millis(50));
+        drop(blocking);
+        emitter.join().unwrap();
+
+        bus.emit(CentralEvent::DeviceUpdated(address(2)));
+        assert_eq!(bus.subscriber_count(), 1);
+        assert_eq!(kept.iter().take(3).count(), 3);
+    }
+
+    #[test]
+    fn streams_end_with_the_bus() {
+        let bus = EventBus::default();
+        let receiver = bus.subscribe(EventSubscription::default());
+        bus.emit(CentralEvent::DeviceLost(address(0)));
+        drop(bus);
+        let events: Vec<_> = block_on(receiver.collect());
+        assert_eq!(events.len(), 1);
+    }
+}
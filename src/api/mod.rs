@@ -13,20 +13,26 @@
 
 mod adapter_manager;
 pub mod bleuuid;
+pub mod events;
+pub mod notifications;
 
-use crate::{Error, Result};
+use crate::{common::blocking::block_on, Error, Result};
 pub use adapter_manager::AdapterManager;
+use async_trait::async_trait;
 use bitflags::bitflags;
+pub use events::{CentralEventKind, EventFilter, EventReceiver, EventSubscription, OverflowPolicy};
+pub use notifications::{NotificationHandlerId, NotificationReceiver};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
 use serde_cr as serde;
-use std::sync::mpsc::Receiver;
 use std::{
     collections::{BTreeSet, HashMap},
     convert::TryFrom,
     fmt::{self, Debug, Display, Formatter},
     str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 use thiserror::Error;
 use uuid::Uuid;
@@ -144,6 +150,109 @@ impl FromStr for BDAddr {
     }
 }
 
+/// An error code from the Attribute Protocol (ATT), returned by a device when a GATT operation
+/// fails. See the Bluetooth Core Specification, Vol 3, Part F, section 3.4.1.1.
+#[derive(Debug, Error, Clone, Copy, Eq, PartialEq)]
+pub enum AttErrorCode {
+    #[error("Invalid handle")]
+    InvalidHandle,
+    #[error("Read not permitted")]
+    ReadNotPermitted,
+    #[error("Write not permitted")]
+    WriteNotPermitted,
+    #[error("Invalid PDU")]
+    InvalidPdu,
+    #[error("Insufficient authentication")]
+    InsufficientAuthentication,
+    #[error("Request not supported")]
+    RequestNotSupported,
+    #[error("Invalid offset")]
+    InvalidOffset,
+    #[error("Insufficient authorization")]
+    InsufficientAuthorization,
+    #[error("Prepare queue full")]
+    PrepareQueueFull,
+    #[error("Attribute not found")]
+    AttributeNotFound,
+    #[error("Attribute not long")]
+    AttributeNotLong,
+    #[error("Insufficient encryption key size")]
+    InsufficientEncryptionKeySize,
+    #[error("Invalid attribute value length")]
+    InvalidAttributeValueLength,
+    #[error("Unlikely error")]
+    UnlikelyError,
+    #[error("Insufficient encryption")]
+    InsufficientEncryption,
+    #[error("Unsupported group type")]
+    UnsupportedGroupType,
+    #[error("Insufficient resources")]
+    InsufficientResources,
+    #[error("Database out of sync")]
+    DatabaseOutOfSync,
+    #[error("Value not allowed")]
+    ValueNotAllowed,
+    /// An error defined by the higher layer specification of the device (0x80 to 0x9F).
+    #[error("Application error 0x{:02x}", _0)]
+    Application(u8),
+    /// Any other code, including the common profile and service error codes (0xE0 to 0xFF).
+    #[error("ATT error 0x{:02x}", _0)]
+    Other(u8),
+}
+
+impl AttErrorCode {
+    pub fn from_u8(v: u8) -> AttErrorCode {
+        match v {
+            0x01 => AttErrorCode::InvalidHandle,
+            0x02 => AttErrorCode::ReadNotPermitted,
+            0x03 => AttErrorCode::WriteNotPermitted,
+            0x04 => AttErrorCode::InvalidPdu,
+            0x05 => AttErrorCode::InsufficientAuthentication,
+            0x06 => AttErrorCode::RequestNotSupported,
+            0x07 => AttErrorCode::InvalidOffset,
+            0x08 => AttErrorCode::InsufficientAuthorization,
+            0x09 => AttErrorCode::PrepareQueueFull,
+            0x0A => AttErrorCode::AttributeNotFound,
+            0x0B => AttErrorCode::AttributeNotLong,
+            0x0C => AttErrorCode::InsufficientEncryptionKeySize,
+            0x0D => AttErrorCode::InvalidAttributeValueLength,
+            0x0E => AttErrorCode::UnlikelyError,
+            0x0F => AttErrorCode::InsufficientEncryption,
+            0x10 => AttErrorCode::UnsupportedGroupType,
+            0x11 => AttErrorCode::InsufficientResources,
+            0x12 => AttErrorCode::DatabaseOutOfSync,
+            0x13 => AttErrorCode::ValueNotAllowed,
+            0x80..=0x9F => AttErrorCode::Application(v),
+            _ => AttErrorCode::Other(v),
+        }
+    }
+
+    pub fn num(&self) -> u8 {
+        match *self {
+            AttErrorCode::InvalidHandle => 0x01,
+            AttErrorCode::ReadNotPermitted => 0x02,
+            AttErrorCode::WriteNotPermitted => 0x03,
+            AttErrorCode::InvalidPdu => 0x04,
+            AttErrorCode::InsufficientAuthentication => 0x05,
+            AttErrorCode::RequestNotSupported => 0x06,
+            AttErrorCode::InvalidOffset => 0x07,
+            AttErrorCode::InsufficientAuthorization => 0x08,
+            AttErrorCode::PrepareQueueFull => 0x09,
+            AttErrorCode::AttributeNotFound => 0x0A,
+            AttErrorCode::AttributeNotLong => 0x0B,
+            AttErrorCode::InsufficientEncryptionKeySize => 0x0C,
+            AttErrorCode::InvalidAttributeValueLength => 0x0D,
+            AttErrorCode::UnlikelyError => 0x0E,
+            AttErrorCode::InsufficientEncryption => 0x0F,
+            AttErrorCode::UnsupportedGroupType => 0x10,
+            AttErrorCode::InsufficientResources => 0x11,
+            AttErrorCode::DatabaseOutOfSync => 0x12,
+            AttErrorCode::ValueNotAllowed => 0x13,
+            AttErrorCode::Application(v) | AttErrorCode::Other(v) => v,
+        }
+    }
+}
+
 /// A notification sent from a peripheral due to a change in a value.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ValueNotification {
@@ -152,8 +261,8 @@ pub struct ValueNotification {
     /// The handle that has changed. Only valid on Linux, will be None on all
     /// other platforms.
     pub handle: Option<u16>,
-    /// The new value of the handle.
-    pub value: Vec<u8>,
+    /// The new value of the handle, shared by every handler it is delivered to.
+    pub value: Arc<[u8]>,
 }
 
 pub type Callback<T> = Box<dyn Fn(Result<T>) + Send>;
@@ -217,6 +326,82 @@ impl Display for Characteristic {
     }
 }
 
+/// A GATT service. Services group related characteristics, and are identified by a UUID which may
+/// be standardized (like 0x180F for the battery service) or specific to a device. The standard set
+/// of services can be found [here](https://www.bluetooth.com/specifications/gatt/services).
+///
+/// A device may expose several services with the same UUID, or the same characteristic UUID under
+/// several services, so characteristics should be looked up through the service they belong to.
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+pub struct Service {
+    /// The handle of the service declaration. Only valid on Linux, will be 0 on all other
+    /// platforms.
+    pub handle: u16,
+    /// The UUID for this service. This identifies its type.
+    pub uuid: Uuid,
+    /// True for a primary service, false for a secondary service which is only meant to be
+    /// included by other services.
+    pub primary: bool,
+    /// The services included by this service. Their handles tell apart included services with
+    /// the same UUID.
+    pub included_services: Vec<IncludedService>,
+    /// The characteristics of this service.
+    pub characteristics: BTreeSet<Characteristic>,
+}
+
+impl Service {
+    /// Returns the characteristic of this service with the given UUID, if any.
+    pub fn characteristic(&self, uuid: Uuid) -> Option<&Characteristic> {
+        self.characteristics.iter().find(|c| c.uuid == uuid)
+    }
+}
+
+/// A service included by another one, which is also listed among the services of the device, with
+/// the same handle.
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone, Copy, Hash)]
+pub struct IncludedService {
+    /// The handle of the included service. Only valid on Linux, will be 0 on all other platforms.
+    pub handle: u16,
+    /// The UUID of the included service.
+    pub uuid: Uuid,
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "uuid: {:?}, primary: {}, characteristics: {}",
+            self.uuid,
+            self.primary,
+            self.characteristics.len()
+        )
+    }
+}
+
+/// A descriptor of a characteristic. Descriptors hold extra information about the characteristic
+/// they belong to, for example a human-readable description (0x2901) or the Client Characteristic
+/// Configuration (0x2902) which controls notifications and indications. Devices may also expose
+/// vendor-specific descriptors.
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+pub struct Descriptor {
+    /// The handle of the descriptor. Only valid on Linux, will be 0 on all other platforms.
+    pub handle: u16,
+    /// The UUID for this descriptor. This identifies its type.
+    pub uuid: Uuid,
+    /// The UUID of the characteristic this descriptor belongs to.
+    pub characteristic_uuid: Uuid,
+}
+
+impl Display for Descriptor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "uuid: {:?}, characteristic: {:?}",
+            self.uuid, self.characteristic_uuid
+        )
+    }
+}
+
 /// The properties of this peripheral, as determined by the advertising reports we've received for
 /// it.
 #[derive(Debug, Default, Clone)]
@@ -227,8 +412,12 @@ pub struct PeripheralProperties {
     pub address_type: AddressType,
     /// The local name. This is generally a human-readable string that identifies the type of device.
     pub local_name: Option<String>,
-    /// The transmission power level for the device
+    /// The transmission power level advertised by the device, in dBm
     pub tx_power_level: Option<i8>,
+    /// The signal strength of the most recent advertising report received from the device, in dBm
+    pub rssi: Option<i16>,
+    /// Statistics over the signal strength of the advertising reports received from the device
+    pub signal: SignalStats,
     /// Advertisement data specific to the device manufacturer. The keys of this map are
     /// 'manufacturer IDs', while the values are arbitrary data.
     pub manufacturer_data: HashMap<u16, Vec<u8>>,
@@ -243,6 +432,102 @@ pub struct PeripheralProperties {
     pub has_scan_response: bool,
 }
 
+/// Statistics over the received signal strength (RSSI) of a peripheral, in dBm.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SignalStats {
+    /// The most recent sample
+    pub last: Option<i16>,
+    /// The weakest sample seen
+    pub min: Option<i16>,
+    /// The strongest sample seen
+    pub max: Option<i16>,
+    /// An exponentially weighted moving average of the samples, which evens out the jitter of
+    /// individual reports. Each new sample contributes [`SignalStats::SMOOTHING_FACTOR`] of the
+    /// result.
+    pub smoothed: Option<f32>,
+    /// The number of samples seen
+    pub count: u32,
+}
+
+impl SignalStats {
+    /// The weight given to each new sample in [`SignalStats::smoothed`].
+    pub const SMOOTHING_FACTOR: f32 = 0.25;
+
+    /// Adds an RSSI sample.
+    pub(crate) fn record(&mut self, rssi: i16) {
+        self.last = Some(rssi);
+        self.min = Some(self.min.map_or(rssi, |min| min.min(rssi)));
+        self.max = Some(self.max.map_or(rssi, |max| max.max(rssi)));
+        self.smoothed = Some(match self.smoothed {
+            Some(smoothed) => smoothed + Self::SMOOTHING_FACTOR * (f32::from(rssi) - smoothed),
+            None => f32::from(rssi),
+        });
+        self.count = self.count.saturating_add(1);
+    }
+}
+
+/// What the host remembers about a peripheral between connections.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BondState {
+    /// The peripheral is bonded: it has been paired, and its keys are stored
+    pub paired: bool,
+    /// The peripheral may connect and use services without being authorized
+    pub trusted: bool,
+    /// Connections from the peripheral are refused
+    pub blocked: bool,
+    /// The peripheral only supports legacy pairing, with a PIN code
+    pub legacy_pairing: bool,
+}
+
+/// A snapshot of the configuration of an adapter. Fields a platform doesn't report are `None`, or
+/// empty.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AdapterInfo {
+    /// The address of the adapter
+    pub address: Option<BDAddr>,
+    /// The type of address the adapter uses
+    pub address_type: Option<AddressType>,
+    /// The system name of the adapter
+    pub name: Option<String>,
+    /// The name the adapter is known as to remote devices, which may be changed by the user
+    pub alias: Option<String>,
+    /// The Bluetooth class of device
+    pub class: Option<u32>,
+    /// Whether the adapter is switched on
+    pub powered: Option<bool>,
+    /// Whether the adapter can be discovered by remote devices
+    pub discoverable: Option<bool>,
+    /// How long the adapter stays discoverable once made so, or `None` if it stays discoverable
+    pub discoverable_timeout: Option<Duration>,
+    /// Whether remote devices can pair with the adapter
+    pub pairable: Option<bool>,
+    /// How long the adapter stays pairable once made so, or `None` if it stays pairable
+    pub pairable_timeout: Option<Duration>,
+    /// Whether the adapter is scanning for devices
+    pub discovering: Option<bool>,
+    /// The services the adapter offers
+    pub services: Vec<Uuid>,
+    /// The vendor, product and version of the adapter, in modalias format
+    pub modalias: Option<String>,
+}
+
+/// A property of an adapter that changed, along with its new value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdapterProperty {
+    Name(String),
+    Alias(String),
+    Class(u32),
+    Powered(bool),
+    Discoverable(bool),
+    /// `None` when the adapter stays discoverable
+    DiscoverableTimeout(Option<Duration>),
+    Pairable(bool),
+    /// `None` when the adapter stays pairable
+    PairableTimeout(Option<Duration>),
+    Discovering(bool),
+    Services(Vec<Uuid>),
+}
+
 /// The type of write operation to use.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum WriteType {
@@ -256,6 +541,10 @@ pub enum WriteType {
 /// Peripheral is the device that you would like to communicate with (the "server" of BLE). This
 /// struct contains both the current state of the device (its properties, characteristics, etc.)
 /// as well as functions for communication.
+///
+/// Operations that talk to the device block the calling thread until they are done. They don't
+/// need an executor, so they can be called from async code too, where they hold up the thread
+/// running it; [`AsyncPeripheral`](trait.AsyncPeripheral.html) is meant for that.
 pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Returns the address of the peripheral.
     fn address(&self) -> BDAddr;
@@ -268,6 +557,34 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// `discover_characteristics` is called.
     fn characteristics(&self) -> BTreeSet<Characteristic>;
 
+    /// The set of services we've discovered for this device, with their characteristics. This will
+    /// be empty until `discover_characteristics` is called.
+    fn services(&self) -> BTreeSet<Service>;
+
+    /// Returns the service with the given UUID, if it has been discovered. If the device has
+    /// several services with this UUID, the first one is returned.
+    fn service(&self, uuid: Uuid) -> Option<Service> {
+        self.services().into_iter().find(|s| s.uuid == uuid)
+    }
+
+    /// Returns the characteristic with the given UUID in the service with the given UUID, if both
+    /// have been discovered. Unlike searching through `characteristics()`, this tells apart
+    /// characteristics with the same UUID in different services.
+    fn characteristic(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) -> Option<Characteristic> {
+        self.services()
+            .into_iter()
+            .filter(|s| s.uuid == service_uuid)
+            .find_map(|s| s.characteristic(characteristic_uuid).cloned())
+    }
+
+    /// The set of descriptors we've discovered for the given characteristic. This will be empty
+    /// until `discover_characteristics` is called.
+    fn descriptors(&self, characteristic: &Characteristic) -> BTreeSet<Descriptor>;
+
     /// Returns true iff we are currently connected to the device.
     fn is_connected(&self) -> bool;
 
@@ -279,6 +596,11 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Terminates a connection to the device. This is a synchronous operation.
     fn disconnect(&self) -> Result<()>;
 
+    /// Pairs with the device, connecting to it first if needed. Any interaction needed, such as
+    /// entering a passkey, goes through the pairing agent of the platform. This is a synchronous
+    /// operation.
+    fn pair(&self) -> Result<()>;
+
     /// Discovers all characteristics for the device. This is a synchronous operation.
     fn discover_characteristics(&self) -> Result<Vec<Characteristic>>;
 
@@ -301,6 +623,14 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Synchronously returns either an error or the device response.
     fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>>;
 
+    /// Sends a read request for the descriptor to the device. Synchronously returns either an
+    /// error if the request was not accepted or the value of the descriptor.
+    fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+
+    /// Writes some data to the descriptor. Returns an error if the write couldn't be sent or if
+    /// the device returns an error.
+    fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()>;
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
     fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
@@ -310,9 +640,237 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
 
     /// Registers a handler that will be called when value notification messages are received from
-    /// the device. This method should only be used after a connection has been established. Note
-    /// that the handler will be called in a common thread, so it should not block.
-    fn on_notification(&self, handler: NotificationHandler);
+    /// the device. This method should only be used after a connection has been established. The
+    /// handlers of a peripheral are called one after the other from a thread of their own, so a
+    /// handler that blocks holds up the others, but nothing else. Returns the ID to remove the
+    /// handler with.
+    fn on_notification(&self, handler: NotificationHandler) -> NotificationHandlerId;
+
+    /// Removes a handler registered with `on_notification`. Returns false if there is no handler
+    /// with this ID.
+    fn remove_notification_handler(&self, id: NotificationHandlerId) -> bool;
+
+    /// Returns a receiver for the notifications of `characteristic` alone, which stops receiving
+    /// them when dropped. The characteristic still has to be subscribed to.
+    fn notifications(&self, characteristic: &Characteristic) -> NotificationReceiver;
+
+    /// Changes how many notifications can wait for the handlers, 256 by default, and what
+    /// happens to the next one when that many are waiting. Notifications are queued from the
+    /// thread receiving them from the Bluetooth stack, which must not wait for the handlers, so
+    /// `OverflowPolicy::Block` is rejected with `Error::NotSupported`.
+    fn set_notification_queue(&self, capacity: usize, overflow: OverflowPolicy) -> Result<()>;
+}
+
+/// The asynchronous counterpart of [`Peripheral`](trait.Peripheral.html). Operations that talk to
+/// the device return futures instead of blocking the calling thread, so a single task can drive
+/// many peripherals. The futures don't depend on any particular async runtime.
+///
+/// Every `AsyncPeripheral` is also a `Peripheral`, whose methods wait for these futures on the
+/// calling thread. As both traits have methods with the same names, only import the one you use.
+#[async_trait]
+pub trait AsyncPeripheral: Send + Sync + Clone + Debug {
+    /// Returns the address of the peripheral.
+    fn address(&self) -> BDAddr;
+
+    /// Returns the set of properties associated with the peripheral. These may be updated over time
+    /// as additional advertising reports are received.
+    fn properties(&self) -> PeripheralProperties;
+
+    /// The set of characteristics we've discovered for this device. This will be empty until
+    /// `discover_characteristics` is called.
+    fn characteristics(&self) -> BTreeSet<Characteristic>;
+
+    /// The set of services we've discovered for this device, with their characteristics. This will
+    /// be empty until `discover_characteristics` is called.
+    fn services(&self) -> BTreeSet<Service>;
+
+    /// Returns the service with the given UUID, if it has been discovered. If the device has
+    /// several services with this UUID, the first one is returned.
+    fn service(&self, uuid: Uuid) -> Option<Service> {
+        self.services().into_iter().find(|s| s.uuid == uuid)
+    }
+
+    /// Returns the characteristic with the given UUID in the service with the given UUID, if both
+    /// have been discovered.
+    fn characteristic(
+        &self,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) -> Option<Characteristic> {
+        self.services()
+            .into_iter()
+            .filter(|s| s.uuid == service_uuid)
+            .find_map(|s| s.characteristic(characteristic_uuid).cloned())
+    }
+
+    /// The set of descriptors we've discovered for the given characteristic. This will be empty
+    /// until `discover_characteristics` is called.
+    fn descriptors(&self, characteristic: &Characteristic) -> BTreeSet<Descriptor>;
+
+    /// Returns true iff we are currently connected to the device.
+    fn is_connected(&self) -> bool;
+
+    /// Creates a connection to the device. The future resolves once the connection has been
+    /// established.
+    async fn connect(&self) -> Result<()>;
+
+    /// Terminates a connection to the device.
+    async fn disconnect(&self) -> Result<()>;
+
+    /// Pairs with the device, connecting to it first if needed. Any interaction needed, such as
+    /// entering a passkey, goes through the pairing agent of the platform. The future resolves
+    /// once pairing has completed.
+    async fn pair(&self) -> Result<()> {
+        Err(Error::NotSupported("Pairing".to_string()))
+    }
+
+    /// Discovers all characteristics for the device.
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>>;
+
+    /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
+    /// the case of a write-with-response) if the device returns an error.
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()>;
+
+    /// Sends a request (read) to the device. Returns either an error if the request was not
+    /// accepted or the response from the device.
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
+
+    /// Sends a read-by-type request to device for the range of handles covered by the
+    /// characteristic and for the specified declaration UUID.
+    async fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>>;
+
+    /// Sends a read request for the descriptor to the device.
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+
+    /// Writes some data to the descriptor.
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()>;
+
+    /// Enables either notify or indicate (depending on support) for the specified characteristic.
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
+
+    /// Disables either notify or indicate (depending on support) for the specified characteristic.
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
+
+    /// Registers a handler that will be called when value notification messages are received from
+    /// the device. The handlers of a peripheral are called one after the other from a thread of
+    /// their own, so a handler that blocks holds up the others, but nothing else. Returns the ID
+    /// to remove the handler with.
+    fn on_notification(&self, handler: NotificationHandler) -> NotificationHandlerId;
+
+    /// Removes a handler registered with `on_notification`. Returns false if there is no handler
+    /// with this ID.
+    fn remove_notification_handler(&self, id: NotificationHandlerId) -> bool;
+
+    /// Returns a receiver for the notifications of `characteristic` alone, which stops receiving
+    /// them when dropped. The characteristic still has to be subscribed to.
+    fn notifications(&self, characteristic: &Characteristic) -> NotificationReceiver;
+
+    /// Changes how many notifications can wait for the handlers, 256 by default, and what
+    /// happens to the next one when that many are waiting. Notifications are queued from the
+    /// thread receiving them from the Bluetooth stack, which must not wait for the handlers, so
+    /// `OverflowPolicy::Block` is rejected with `Error::NotSupported`.
+    fn set_notification_queue(&self, capacity: usize, overflow: OverflowPolicy) -> Result<()>;
+}
+
+impl<P: AsyncPeripheral> Peripheral for P {
+    fn address(&self) -> BDAddr {
+        AsyncPeripheral::address(self)
+    }
+
+    fn properties(&self) -> PeripheralProperties {
+        AsyncPeripheral::properties(self)
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        AsyncPeripheral::characteristics(self)
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        AsyncPeripheral::services(self)
+    }
+
+    fn descriptors(&self, characteristic: &Characteristic) -> BTreeSet<Descriptor> {
+        AsyncPeripheral::descriptors(self, characteristic)
+    }
+
+    fn is_connected(&self) -> bool {
+        AsyncPeripheral::is_connected(self)
+    }
+
+    fn connect(&self) -> Result<()> {
+        block_on(AsyncPeripheral::connect(self))
+    }
+
+    fn disconnect(&self) -> Result<()> {
+        block_on(AsyncPeripheral::disconnect(self))
+    }
+
+    fn pair(&self) -> Result<()> {
+        block_on(AsyncPeripheral::pair(self))
+    }
+
+    fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        block_on(AsyncPeripheral::discover_characteristics(self))
+    }
+
+    fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        block_on(AsyncPeripheral::write(
+            self,
+            characteristic,
+            data,
+            write_type,
+        ))
+    }
+
+    fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        block_on(AsyncPeripheral::read(self, characteristic))
+    }
+
+    fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>> {
+        block_on(AsyncPeripheral::read_by_type(self, characteristic, uuid))
+    }
+
+    fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        block_on(AsyncPeripheral::read_descriptor(self, descriptor))
+    }
+
+    fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        block_on(AsyncPeripheral::write_descriptor(self, descriptor, data))
+    }
+
+    fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        block_on(AsyncPeripheral::subscribe(self, characteristic))
+    }
+
+    fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        block_on(AsyncPeripheral::unsubscribe(self, characteristic))
+    }
+
+    fn on_notification(&self, handler: NotificationHandler) -> NotificationHandlerId {
+        AsyncPeripheral::on_notification(self, handler)
+    }
+
+    fn remove_notification_handler(&self, id: NotificationHandlerId) -> bool {
+        AsyncPeripheral::remove_notification_handler(self, id)
+    }
+
+    fn notifications(&self, characteristic: &Characteristic) -> NotificationReceiver {
+        AsyncPeripheral::notifications(self, characteristic)
+    }
+
+    fn set_notification_queue(&self, capacity: usize, overflow: OverflowPolicy) -> Result<()> {
+        AsyncPeripheral::set_notification_queue(self, capacity, overflow)
+    }
 }
 
 #[cfg_attr(
@@ -327,6 +885,15 @@ pub enum CentralEvent {
     DeviceUpdated(BDAddr),
     DeviceConnected(BDAddr),
     DeviceDisconnected(BDAddr),
+    /// Emitted when a peripheral with a reconnect policy is connected again after a disconnection
+    /// it didn't ask for, once its services are resolved and its subscriptions restored.
+    DeviceReconnected(BDAddr),
+    /// Emitted when a peripheral with a reconnect policy couldn't be reconnected within the
+    /// attempts it allows. The peripheral is dropped, as with `DeviceDisconnected`.
+    DeviceReconnectFailed {
+        address: BDAddr,
+        attempts: u32,
+    },
     /// Emitted when a Manufacturer Data advertisement has been received from a device
     ManufacturerDataAdvertisement {
         address: BDAddr,
@@ -344,17 +911,63 @@ pub enum CentralEvent {
         address: BDAddr,
         services: Vec<Uuid>,
     },
+    /// Emitted when a device is paired, trusted or blocked, or stops being so
+    DeviceBondChanged {
+        address: BDAddr,
+        bond: BondState,
+    },
+    /// Emitted when services, characteristics or descriptors of a connected device are added or
+    /// removed, such as after a firmware update. `services()` and `characteristics()` return the
+    /// new ones.
+    ServicesChanged(BDAddr),
+    /// Emitted before `ServicesChanged` when characteristics of a device are removed. Operations
+    /// on them fail with `Error::CharacteristicNotFound` from then on.
+    CharacteristicsInvalidated {
+        address: BDAddr,
+        characteristics: Vec<Characteristic>,
+    },
+    /// Emitted when the device confirms that notifications or indications of a characteristic
+    /// are enabled, or when they stop, whether through `unsubscribe`, another client or a
+    /// disconnection.
+    SubscriptionChanged {
+        address: BDAddr,
+        characteristic: Characteristic,
+        subscribed: bool,
+    },
+    /// Emitted when a property of the adapter itself changes
+    AdapterUpdated(AdapterProperty),
+    /// Emitted when the system's Bluetooth service, such as `bluetoothd` on Linux, stops or
+    /// crashes. Every peripheral is lost, and the adapter can't be used until the service is back.
+    BluetoothServiceLost,
+    /// Emitted when the system's Bluetooth service is running again. Known peripherals are
+    /// discovered again, and a scan that was running is resumed, once the service has registered
+    /// the adapter.
+    BluetoothServiceRestored,
+    /// Emitted when an event from the system couldn't be handled, such as an object with an
+    /// unexpected path, which is skipped. `address` is the peripheral it was about, if known.
+    Error {
+        address: Option<BDAddr>,
+        message: String,
+    },
 }
 
 /// Central is the "client" of BLE. It's able to scan for and establish connections to peripherals.
+///
+/// As with [`Peripheral`](trait.Peripheral.html), operations block the calling thread without
+/// needing an executor, and [`AsyncCentral`](trait.AsyncCentral.html) is meant for async code.
 pub trait Central<P: Peripheral>: Send + Sync + Clone {
-    /// Retreive the Event [Receiver] for the event channel. This channel
-    /// receiver will receive notifications when events occur for this Central
-    /// module. As this uses an std::channel which cannot be cloned, after the
-    /// first call (which will contain Some<Receiver<CentralEvent>>), all
-    /// subsequent calls will return None. See [`Event`](enum.CentralEvent.html)
-    /// for the full set of events returned.
-    fn event_receiver(&self) -> Option<Receiver<CentralEvent>>;
+    /// Subscribes to every event of this Central module, with the default queue capacity and
+    /// overflow policy. Each call returns a new, independent receiver, which gets the events
+    /// emitted after it was created. See [`Event`](enum.CentralEvent.html) for the full set of
+    /// events returned.
+    fn event_receiver(&self) -> EventReceiver {
+        self.subscribe_events(EventSubscription::default())
+    }
+
+    /// Subscribes to the events of this Central module matching `subscription.filter`. The
+    /// receiver queues up to `subscription.capacity` events, and handles any more according to
+    /// `subscription.overflow`. Dropping the receiver ends the subscription.
+    fn subscribe_events(&self, subscription: EventSubscription) -> EventReceiver;
 
     /// Starts a scan for BLE devices. This scan will generally continue until explicitly stopped,
     /// although this may depend on your bluetooth adapter. Discovered devices will be announced
@@ -363,13 +976,15 @@ pub trait Central<P: Peripheral>: Send + Sync + Clone {
 
     /// Control whether to use active or passive scan mode to find BLE devices. Active mode scan
     /// notifies advertises about the scan, whereas passive scan only receives data from the
-    /// advertiser. Defaults to use active mode.
-    fn active(&self, enabled: bool);
+    /// advertiser. Defaults to use active mode. Fails with `Error::NotSupported` on platforms that
+    /// can't scan passively.
+    fn active(&self, enabled: bool) -> Result<()>;
 
     /// Control whether to filter multiple advertisements by the same peer device. Receving
     /// can be useful for some applications. E.g. when using scan to collect information from
-    /// beacons that update data frequently. Defaults to filter duplicate advertisements.
-    fn filter_duplicates(&self, enabled: bool);
+    /// beacons that update data frequently. Defaults to filter duplicate advertisements. Fails if
+    /// the setting couldn't be applied to a running scan.
+    fn filter_duplicates(&self, enabled: bool) -> Result<()>;
 
     /// Stops scanning for BLE devices.
     fn stop_scan(&self) -> Result<()>;
@@ -381,6 +996,91 @@ pub trait Central<P: Peripheral>: Send + Sync + Clone {
     /// Returns a particular [`Peripheral`](trait.Peripheral.html) by its address if it has been
     /// discovered.
     fn peripheral(&self, address: BDAddr) -> Option<P>;
+
+    /// Returns a snapshot of the configuration of the adapter, such as its address, name and
+    /// whether it is powered. Fails with `Error::NotSupported` on platforms that can't report it.
+    fn adapter_info(&self) -> Result<AdapterInfo>;
+}
+
+/// The asynchronous counterpart of [`Central`](trait.Central.html). Every `AsyncCentral` is also a
+/// `Central`, whose methods wait for these futures on the calling thread.
+#[async_trait]
+pub trait AsyncCentral<P: AsyncPeripheral>: Send + Sync + Clone {
+    /// Subscribes to every event of this Central module. See
+    /// [`Central::event_receiver`](trait.Central.html#method.event_receiver).
+    fn event_receiver(&self) -> EventReceiver {
+        self.subscribe_events(EventSubscription::default())
+    }
+
+    /// Subscribes to the events matching `subscription.filter`. See
+    /// [`Central::subscribe_events`](trait.Central.html#tymethod.subscribe_events).
+    fn subscribe_events(&self, subscription: EventSubscription) -> EventReceiver;
+
+    /// Starts a scan for BLE devices. This scan will generally continue until explicitly stopped,
+    /// although this may depend on your bluetooth adapter. Discovered devices will be announced
+    /// to subscribers of `on_event` and will be available via `peripherals()`.
+    async fn start_scan(&self) -> Result<()>;
+
+    /// Control whether to use active or passive scan mode to find BLE devices. See
+    /// [`Central::active`](trait.Central.html#tymethod.active).
+    fn active(&self, enabled: bool) -> Result<()>;
+
+    /// Control whether to filter multiple advertisements by the same peer device. See
+    /// [`Central::filter_duplicates`](trait.Central.html#tymethod.filter_duplicates).
+    fn filter_duplicates(&self, enabled: bool) -> Result<()>;
+
+    /// Stops scanning for BLE devices.
+    async fn stop_scan(&self) -> Result<()>;
+
+    /// Returns the list of peripherals that have been discovered so far. Note that this list may
+    /// contain peripherals that are no longer available.
+    fn peripherals(&self) -> Vec<P>;
+
+    /// Returns a particular peripheral by its address if it has been discovered.
+    fn peripheral(&self, address: BDAddr) -> Option<P>;
+
+    /// Returns a snapshot of the configuration of the adapter.
+    async fn adapter_info(&self) -> Result<AdapterInfo> {
+        Err(Error::NotSupported("Adapter info".to_string()))
+    }
+}
+
+impl<P: AsyncPeripheral, C: AsyncCentral<P>> Central<P> for C {
+    fn event_receiver(&self) -> EventReceiver {
+        AsyncCentral::event_receiver(self)
+    }
+
+    fn subscribe_events(&self, subscription: EventSubscription) -> EventReceiver {
+        AsyncCentral::subscribe_events(self, subscription)
+    }
+
+    fn start_scan(&self) -> Result<()> {
+        block_on(AsyncCentral::start_scan(self))
+    }
+
+    fn active(&self, enabled: bool) -> Result<()> {
+        AsyncCentral::active(self, enabled)
+    }
+
+    fn filter_duplicates(&self, enabled: bool) -> Result<()> {
+        AsyncCentral::filter_duplicates(self, enabled)
+    }
+
+    fn stop_scan(&self) -> Result<()> {
+        block_on(AsyncCentral::stop_scan(self))
+    }
+
+    fn peripherals(&self) -> Vec<P> {
+        AsyncCentral::peripherals(self)
+    }
+
+    fn peripheral(&self, address: BDAddr) -> Option<P> {
+        AsyncCentral::peripheral(self, address)
+    }
+
+    fn adapter_info(&self) -> Result<AdapterInfo> {
+        block_on(AsyncCentral::adapter_info(self))
+    }
 }
 
 #[cfg(test)]
@@ -409,4 +1109,19 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn signal_stats() {
+        let mut stats = SignalStats::default();
+        assert_eq!(stats.smoothed, None);
+
+        for rssi in &[-60, -80, -40] {
+            stats.record(*rssi);
+        }
+        assert_eq!(stats.last, Some(-40));
+        assert_eq!(stats.min, Some(-80));
+        assert_eq!(stats.max, Some(-40));
+        assert_eq!(stats.count, 3);
+        // -60, then -60 + 0.25 * -20 = -65, then -65 + 0.25 * 25 = -58.75.
+        assert_eq!(stats.smoothed, Some(-58.75));
+    }
 }
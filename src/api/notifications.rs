@@ -0,0 +1,386 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Handlers and receivers of [`ValueNotification`](../struct.ValueNotification.html)s.
+//!
+//! Every handler registered on a peripheral gets an ID it can be removed with. A
+//! [`NotificationReceiver`] only gets the notifications of one characteristic, and removes its
+//! handler when dropped. Handlers are called from a thread of their own, through a bounded queue.
+
+use super::{Characteristic, NotificationHandler, OverflowPolicy, ValueNotification};
+use crate::{common::queue::Queue, Error};
+use futures::stream::Stream;
+use std::{
+    fmt::{self, Debug, Formatter},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{RecvError, RecvTimeoutError, TryRecvError},
+        Arc, Mutex, Once, Weak,
+    },
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+
+/// Identifies a handler registered with
+/// [`Peripheral::on_notification`](../trait.Peripheral.html#tymethod.on_notification), to remove
+/// it later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NotificationHandlerId(u64);
+
+type HandlerList = Mutex<Vec<(NotificationHandlerId, Arc<Mutex<NotificationHandler>>)>>;
+
+/// The notification handlers of a peripheral.
+///
+/// Handlers are called from a thread of their own, which is only started once the first one is
+/// registered. Notifications are queued for it, so a slow handler doesn't hold up the thread
+/// receiving them from the Bluetooth stack, and handlers can call back into the peripheral.
+pub(crate) struct NotificationHandlers {
+    handlers: Arc<HandlerList>,
+    queue: Arc<Queue<ValueNotification>>,
+    delivery: Once,
+    next_id: AtomicU64,
+}
+
+impl Default for NotificationHandlers {
+    fn default() -> Self {
+        NotificationHandlers {
+            handlers: Arc::new(Mutex::new(Vec::new())),
+            queue: Arc::new(Queue::new(
+                NotificationHandlers::DEFAULT_CAPACITY,
+                OverflowPolicy::DropOldest,
+            )),
+            delivery: Once::new(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl NotificationHandlers {
+    /// The number of notifications queued for the handlers by default.
+    pub const DEFAULT_CAPACITY: usize = 256;
+
+    pub fn add(&self, handler: NotificationHandler) -> NotificationHandlerId {
+        let id = NotificationHandlerId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.handlers
+            .lock()
+            .unwrap()
+            .push((id, Arc::new(Mutex::new(handler))));
+        self.delivery.call_once(|| {
+            let handlers = self.handlers.clone();
+            let queue = self.queue.clone();
+            thread::spawn(move || deliver(&handlers, &queue));
+        });
+        id
+    }
+
+    /// Removes a handler, returns false if there is none with this ID.
+    pub fn remove(&self, id: NotificationHandlerId) -> bool {
+        let mut handlers = self.handlers.lock().unwrap();
+        let count = handlers.len();
+        handlers.retain(|(i, _h)| *i != id);
+        handlers.len() != count
+    }
+
+    /// Changes how many notifications can wait for the handlers, and what happens to the next one
+    /// when that many are waiting. Blocking isn't allowed, as that would hold up the thread
+    /// receiving notifications, and with it any operation a handler waits for.
+    pub fn set_queue(&self, capacity: usize, overflow: OverflowPolicy) -> crate::Result<()> {
+        if overflow == OverflowPolicy::Block {
+            return Err(Error::NotSupported(
+                "Blocking when the notification queue is full".to_string(),
+            ));
+        }
+        self.queue.set_limits(capacity, overflow);
+        Ok(())
+    }
+
+    /// Queues `notification` for every handler. Nothing is queued until a handler is registered.
+    pub fn notify(&self, notification: ValueNotification) {
+        if self.delivery.is_completed() {
+            self.queue.push(notification);
+        }
+    }
+}
+
+/// Calls the handlers with each queued notification, until the peripheral is gone. The list isn't
+/// held meanwhile, so handlers can add and remove handlers, and a handler removed by another
+/// thread may still be called once.
+fn deliver(handlers: &HandlerList, queue: &Queue<ValueNotification>) {
+    while let Ok(notification) = queue.recv() {
+        let current: Vec<_> = handlers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_i, h)| h.clone())
+            .collect();
+        for handler in current {
+            (*handler.lock().unwrap())(notification.clone());
+        }
+    }
+}
+
+impl Debug for NotificationHandlers {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("NotificationHandlers")
+            .field("count", &self.handlers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Drop for NotificationHandlers {
+    fn drop(&mut self) {
+        // The delivery thread stops once the queued notifications are delivered.
+        self.queue.close();
+    }
+}
+
+/// Closes the queue of a receiver once its handler is dropped, along with the peripheral.
+struct Sender(Arc<Queue<ValueNotification>>);
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Receives the notifications of one characteristic, see
+/// [`Peripheral::notifications`](../trait.Peripheral.html#tymethod.notifications).
+///
+/// Notifications can be read with the blocking `recv` methods, or as a `futures::Stream`, as with
+/// an [`EventReceiver`](../struct.EventReceiver.html). Up to
+/// [`CAPACITY`](#associatedconstant.CAPACITY) notifications are queued, after which the oldest
+/// ones are discarded. Once the peripheral has been dropped and all queued notifications have
+/// been read, `recv` returns an error and the stream ends.
+pub struct NotificationReceiver {
+    queue: Arc<Queue<ValueNotification>>,
+    handlers: Weak<NotificationHandlers>,
+    id: NotificationHandlerId,
+}
+
+impl NotificationReceiver {
+    /// The number of notifications queued before the oldest ones are discarded.
+    pub const CAPACITY: usize = 256;
+
+    /// Registers a receiver for the notifications of `characteristic` with `handlers`.
+    pub(crate) fn new(
+        handlers: &Arc<NotificationHandlers>,
+        characteristic: &Characteristic,
+    ) -> Self {
+        let queue = Arc::new(Queue::new(
+            NotificationReceiver::CAPACITY,
+            OverflowPolicy::DropOldest,
+        ));
+        let sender = Sender(queue.clone());
+        let (uuid, handle) = (characteristic.uuid, characteristic.value_handle);
+        // Only Linux reports the handle, which tells apart characteristics with the same UUID.
+        let id = handlers.add(Box::new(move |notification: ValueNotification| {
+            if notification.uuid == uuid && !matches!(notification.handle, Some(h) if h != handle) {
+                sender.0.push(notification);
+            }
+        }));
+        NotificationReceiver {
+            queue,
+            handlers: Arc::downgrade(handlers),
+            id,
+        }
+    }
+
+    /// Waits for the next notification.
+    pub fn recv(&self) -> Result<ValueNotification, RecvError> {
+        self.queue.recv()
+    }
+
+    /// Returns the next notification if one is queued, without waiting.
+    pub fn try_recv(&self) -> Result<ValueNotification, TryRecvError> {
+        self.queue.try_recv()
+    }
+
+    /// Waits up to `timeout` for the next notification.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<ValueNotification, RecvTimeoutError> {
+        self.queue.recv_timeout(timeout)
+    }
+
+    /// Returns an iterator that waits for notifications, and ends once the peripheral is gone.
+    pub fn iter(&self) -> impl Iterator<Item = ValueNotification> + '_ {
+        std::iter::from_fn(move || self.recv().ok())
+    }
+
+    /// Returns an iterator over the notifications already queued, without waiting.
+    pub fn try_iter(&self) -> impl Iterator<Item = ValueNotification> + '_ {
+        std::iter::from_fn(move || self.try_recv().ok())
+    }
+
+    /// Returns the number of notifications discarded so far because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped()
+    }
+}
+
+impl Stream for NotificationReceiver {
+    type Item = ValueNotification;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ValueNotification>> {
+        self.queue.poll_next(cx)
+    }
+}
+
+impl Debug for NotificationReceiver {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("NotificationReceiver")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl Drop for NotificationReceiver {
+    fn drop(&mut self) {
+        self.queue.close_receiver();
+        if let Some(handlers) = self.handlers.upgrade() {
+            handlers.remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{bleuuid::uuid_from_u16, CharPropFlags};
+    use futures::{executor::block_on, StreamExt};
+    use std::sync::mpsc::channel;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn characteristic(handle: u16, uuid: u16) -> Characteristic {
+        Characteristic {
+            start_handle: handle,
+            end_handle: handle,
+            value_handle: handle,
+            uuid: uuid_from_u16(uuid),
+            properties: CharPropFlags::NOTIFY,
+        }
+    }
+
+    fn notification(characteristic: &Characteristic, handle: Option<u16>) -> ValueNotification {
+        ValueNotification {
+            uuid: characteristic.uuid,
+            handle,
+            value: vec![characteristic.value_handle as u8].into(),
+        }
+    }
+
+    #[test]
+    fn handlers_can_be_removed() {
+        let handlers = NotificationHandlers::default();
+        let (sender, calls) = channel();
+        let ids: Vec<_> = (0..2)
+            .map(|i| {
+                let sender = Mutex::new(sender.clone());
+                handlers.add(Box::new(move |_n| sender.lock().unwrap().send(i).unwrap()))
+            })
+            .collect();
+        let level = characteristic(0x11, 0x2A19);
+        handlers.notify(notification(&level, None));
+        assert_eq!(calls.recv_timeout(TIMEOUT).unwrap(), 0);
+        assert_eq!(calls.recv_timeout(TIMEOUT).unwrap(), 1);
+        assert!(handlers.remove(ids[0]));
+        assert!(!handlers.remove(ids[0]));
+        handlers.notify(notification(&level, None));
+        assert_eq!(calls.recv_timeout(TIMEOUT).unwrap(), 1);
+    }
+
+    #[test]
+    fn slow_handlers_only_hold_up_their_queue() {
+        let handlers = NotificationHandlers::default();
+        handlers.set_queue(2, OverflowPolicy::DropOldest).unwrap();
+        let (started, handling) = channel();
+        let (release, released) = channel::<()>();
+        let (sender, values) = channel();
+        let (started, released, sender) = (
+            Mutex::new(started),
+            Mutex::new(released),
+            Mutex::new(sender),
+        );
+        handlers.add(Box::new(move |n: ValueNotification| {
+            started.lock().unwrap().send(()).unwrap();
+            let _ = released.lock().unwrap().recv_timeout(TIMEOUT);
+            sender.lock().unwrap().send(n.value[0]).unwrap();
+        }));
+
+        let level = characteristic(0x11, 0x2A19);
+        let notify = |value: u8| {
+            handlers.notify(ValueNotification {
+                value: vec![value].into(),
+                ..notification(&level, None)
+            })
+        };
+        notify(1);
+        handling.recv_timeout(TIMEOUT).unwrap();
+        // These don't wait for the handler, and only the latest ones are kept.
+        (2..=5).for_each(notify);
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        let delivered: Vec<u8> = (0..3)
+            .map(|_| values.recv_timeout(TIMEOUT).unwrap())
+            .collect();
+        assert_eq!(delivered, vec![1, 4, 5]);
+        assert_eq!(handlers.queue.dropped(), 2);
+    }
+
+    #[test]
+    fn handler_queues_dont_block() {
+        let handlers = NotificationHandlers::default();
+        assert!(matches!(
+            handlers.set_queue(1, OverflowPolicy::Block),
+            Err(Error::NotSupported(_))
+        ));
+        let (sender, values) = channel();
+        let sender = Mutex::new(sender);
+        handlers.add(Box::new(move |n: ValueNotification| {
+            sender.lock().unwrap().send(n.value[0]).unwrap()
+        }));
+        let level = characteristic(0x11, 0x2A19);
+        handlers.notify(notification(&level, None));
+        assert_eq!(values.recv_timeout(TIMEOUT).unwrap(), 0x11);
+    }
+
+    #[test]
+    fn receivers_get_the_notifications_of_their_characteristic() {
+        let handlers = Arc::new(NotificationHandlers::default());
+        let level = characteristic(0x11, 0x2A19);
+        let other_level = characteristic(0x21, 0x2A19);
+        let receiver = NotificationReceiver::new(&handlers, &level);
+        let other_receiver = NotificationReceiver::new(&handlers, &other_level);
+
+        handlers.notify(notification(&level, Some(0x11)));
+        handlers.notify(notification(&other_level, Some(0x21)));
+        handlers.notify(notification(&characteristic(0x31, 0x2A37), Some(0x31)));
+        // Without handles, the UUID is all there is to go by.
+        handlers.notify(notification(&level, None));
+        let next = |receiver: &NotificationReceiver| receiver.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(next(&receiver).handle, Some(0x11));
+        assert_eq!(next(&receiver).handle, None);
+        assert_eq!(next(&other_receiver).handle, Some(0x21));
+        assert_eq!(next(&other_receiver).handle, None);
+
+        drop(other_receiver);
+        assert_eq!(handlers.handlers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn receivers_end_with_the_peripheral() {
+        let handlers = Arc::new(NotificationHandlers::default());
+        let level = characteristic(0x11, 0x2A19);
+        let receiver = NotificationReceiver::new(&handlers, &level);
+        handlers.notify(notification(&level, Some(0x11)));
+        drop(handlers);
+        let notifications: Vec<_> = block_on(receiver.collect());
+        assert_eq!(notifications.len(), 1);
+    }
+}
@@ -0,0 +1,89 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Conversions from the properties of `org.bluez.Adapter1` to their API types.
+
+use crate::{
+    api::{AdapterInfo, AdapterProperty, AddressType},
+    bluez::bluez_dbus::adapter::OrgBluezAdapter1Properties,
+};
+use std::{convert::TryFrom, time::Duration};
+use uuid::Uuid;
+
+/// Converts a discoverable or pairable timeout, in seconds, where 0 means there is no timeout.
+pub(crate) fn from_timeout(seconds: u32) -> Option<Duration> {
+    match seconds {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds.into())),
+    }
+}
+
+/// The inverse of [from_timeout], rounding down to whole seconds.
+pub(crate) fn to_timeout(timeout: Option<Duration>) -> u32 {
+    timeout.map_or(0, |t| u32::try_from(t.as_secs()).unwrap_or(u32::MAX))
+}
+
+pub(crate) fn to_uuids(uuids: &[String]) -> Vec<Uuid> {
+    uuids.iter().filter_map(|uuid| uuid.parse().ok()).collect()
+}
+
+pub(crate) fn adapter_info(properties: OrgBluezAdapter1Properties) -> AdapterInfo {
+    AdapterInfo {
+        address: properties.address().and_then(|a| a.parse().ok()),
+        address_type: properties
+            .address_type()
+            .and_then(|t| AddressType::from_str(t)),
+        name: properties.name().cloned(),
+        alias: properties.alias().cloned(),
+        class: properties.class(),
+        powered: properties.powered(),
+        discoverable: properties.discoverable(),
+        discoverable_timeout: properties.discoverable_timeout().and_then(from_timeout),
+        pairable: properties.pairable(),
+        pairable_timeout: properties.pairable_timeout().and_then(from_timeout),
+        discovering: properties.discovering(),
+        services: properties.uuids().map_or_else(Vec::new, |u| to_uuids(u)),
+        modalias: properties.modalias().cloned(),
+    }
+}
+
+/// Returns the properties that are set in `properties`, which generally holds the changes
+/// reported by `PropertiesChanged`.
+pub(crate) fn adapter_properties(properties: OrgBluezAdapter1Properties) -> Vec<AdapterProperty> {
+    let mut changed = Vec::new();
+    if let Some(name) = properties.name() {
+        changed.push(AdapterProperty::Name(name.clone()));
+    }
+    if let Some(alias) = properties.alias() {
+        changed.push(AdapterProperty::Alias(alias.clone()));
+    }
+    if let Some(class) = properties.class() {
+        changed.push(AdapterProperty::Class(class));
+    }
+    if let Some(powered) = properties.powered() {
+        changed.push(AdapterProperty::Powered(powered));
+    }
+    if let Some(discoverable) = properties.discoverable() {
+        changed.push(AdapterProperty::Discoverable(discoverable));
+    }
+    if let Some(timeout) = properties.discoverable_timeout() {
+        changed.push(AdapterProperty::DiscoverableTimeout(from_timeout(timeout)));
+    }
+    if let Some(pairable) = properties.pairable() {
+        changed.push(AdapterProperty::Pairable(pairable));
+    }
+    if let Some(timeout) = properties.pairable_timeout() {
+        changed.push(AdapterProperty::PairableTimeout(from_timeout(timeout)));
+    }
+    if let Some(discovering) = properties.discovering() {
+        changed.push(AdapterProperty::Discovering(discovering));
+    }
+    if let Some(uuids) = properties.uuids() {
+        changed.push(AdapterProperty::Services(to_uuids(uuids)));
+    }
+    changed
+}
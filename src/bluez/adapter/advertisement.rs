@@ -0,0 +1,466 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! LE advertisements, exported to BlueZ as `org.bluez.LEAdvertisement1` objects and registered
+//! through `org.bluez.LEAdvertisingManager1.RegisterAdvertisement`.
+
+use crate::{
+    bluez::{
+        async_connection::AsyncConnection,
+        bluez_dbus::adapter::ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME,
+        export::{properties_reply, unknown_method, variant, Export, Interfaces},
+        DEFAULT_TIMEOUT,
+    },
+    Error, Result,
+};
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::SyncConnection,
+    Message, Path,
+};
+use log::{debug, warn};
+use parking_lot::ReentrantMutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use uuid::Uuid;
+
+const ORG_BLUEZ_LEADVERTISEMENT1_NAME: &str = "org.bluez.LEAdvertisement1";
+
+static ADVERTISEMENT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The content of an LE advertisement. See the
+/// [BlueZ documentation](https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/advertising-api.txt)
+/// for how each field ends up in the advertising data.
+///
+/// Everything is optional, BlueZ fills in the flags itself.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Advertisement {
+    broadcast: bool,
+    local_name: Option<String>,
+    service_uuids: Vec<Uuid>,
+    manufacturer_data: BTreeMap<u16, Vec<u8>>,
+    service_data: BTreeMap<Uuid, Vec<u8>>,
+    appearance: Option<u16>,
+    include_tx_power: bool,
+    discoverable: Option<bool>,
+    timeout: Option<Duration>,
+}
+
+impl Advertisement {
+    /// Creates an empty, connectable advertisement.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advertises as a non-connectable broadcaster instead.
+    pub fn broadcast(mut self) -> Self {
+        self.broadcast = true;
+        self
+    }
+
+    /// Sets the local name included in the advertisement.
+    pub fn local_name(mut self, name: &str) -> Self {
+        self.local_name = Some(name.to_string());
+        self
+    }
+
+    /// Adds a service UUID to the advertised list.
+    pub fn service_uuid(mut self, uuid: Uuid) -> Self {
+        self.service_uuids.push(uuid);
+        self
+    }
+
+    /// Adds manufacturer specific data, for the given company identifier.
+    pub fn manufacturer_data(mut self, company_id: u16, data: Vec<u8>) -> Self {
+        self.manufacturer_data.insert(company_id, data);
+        self
+    }
+
+    /// Adds data for a service.
+    pub fn service_data(mut self, uuid: Uuid, data: Vec<u8>) -> Self {
+        self.service_data.insert(uuid, data);
+        self
+    }
+
+    /// Sets the external appearance of the device, as defined by the Bluetooth assigned numbers.
+    pub fn appearance(mut self, appearance: u16) -> Self {
+        self.appearance = Some(appearance);
+        self
+    }
+
+    /// Includes the TX power of the adapter in the advertisement.
+    pub fn include_tx_power(mut self, include: bool) -> Self {
+        self.include_tx_power = include;
+        self
+    }
+
+    /// Sets the General Discoverable flag. When left unset, BlueZ decides based on whether the
+    /// adapter itself is discoverable.
+    pub fn discoverable(mut self, discoverable: bool) -> Self {
+        self.discoverable = Some(discoverable);
+        self
+    }
+
+    /// Stops advertising after `timeout`, which BlueZ handles with a one second resolution.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Converts the advertisement into the properties of a `LEAdvertisement1` object.
+    pub(crate) fn to_prop_map(&self) -> PropMap {
+        let mut map = PropMap::new();
+        let kind = if self.broadcast {
+            "broadcast"
+        } else {
+            "peripheral"
+        };
+        map.insert("Type".to_string(), variant(kind.to_string()));
+        if let Some(name) = &self.local_name {
+            map.insert("LocalName".to_string(), variant(name.clone()));
+        }
+        if !self.service_uuids.is_empty() {
+            let uuids: Vec<String> = self.service_uuids.iter().map(|u| u.to_string()).collect();
+            map.insert("ServiceUUIDs".to_string(), variant(uuids));
+        }
+        if !self.manufacturer_data.is_empty() {
+            let data: HashMap<u16, Variant<Box<dyn RefArg>>> = self
+                .manufacturer_data
+                .iter()
+                .map(|(id, data)| (*id, variant(data.clone())))
+                .collect();
+            map.insert("ManufacturerData".to_string(), variant(data));
+        }
+        if !self.service_data.is_empty() {
+            let data: HashMap<String, Variant<Box<dyn RefArg>>> = self
+                .service_data
+                .iter()
+                .map(|(uuid, data)| (uuid.to_string(), variant(data.clone())))
+                .collect();
+            map.insert("ServiceData".to_string(), variant(data));
+        }
+        if let Some(appearance) = self.appearance {
+            map.insert("Appearance".to_string(), variant(appearance));
+        }
+        if self.include_tx_power {
+            map.insert(
+                "Includes".to_string(),
+                variant(vec!["tx-power".to_string()]),
+            );
+        }
+        if let Some(discoverable) = self.discoverable {
+            map.insert("Discoverable".to_string(), variant(discoverable));
+        }
+        if let Some(timeout) = self.timeout {
+            let seconds = timeout.as_secs().min(u64::from(u16::MAX)) as u16;
+            map.insert("Timeout".to_string(), variant(seconds));
+        }
+        map
+    }
+}
+
+/// An advertisement registered with BlueZ. It is advertised until it is unregistered, dropped,
+/// or it times out.
+pub struct RegisteredAdvertisement {
+    path: String,
+    calls: AsyncConnection,
+    adapter_path: String,
+    released: Arc<AtomicBool>,
+    export: Option<Export>,
+}
+
+impl RegisteredAdvertisement {
+    pub(crate) fn register(
+        listener: Arc<ReentrantMutex<SyncConnection>>,
+        calls: AsyncConnection,
+        adapter_path: &str,
+        advertisement: &Advertisement,
+    ) -> Result<RegisteredAdvertisement> {
+        if calls.is_dispatcher_thread() {
+            return Err(Error::NotSupported(
+                "Advertisements can't be registered from the thread dispatching BlueZ events"
+                    .to_string(),
+            ));
+        }
+        let path = format!(
+            "/org/btleplug/advertisement{}",
+            ADVERTISEMENT_COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let released = Arc::new(AtomicBool::new(false));
+        let export = {
+            let advertisement = advertisement.clone();
+            let released = released.clone();
+            let served = path.clone();
+            Export::new(listener, calls.clone(), &path, move |msg| {
+                handle(msg, &served, &advertisement, &released)
+            })
+        };
+        calls.call_blocking::<_, ()>(
+            adapter_path,
+            ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME,
+            "RegisterAdvertisement",
+            (Path::from(path.clone()), PropMap::new()),
+            DEFAULT_TIMEOUT,
+        )?;
+        debug!("Registered advertisement {}", path);
+        Ok(RegisteredAdvertisement {
+            path,
+            calls,
+            adapter_path: adapter_path.to_string(),
+            released,
+            export: Some(export),
+        })
+    }
+
+    /// The D-Bus object path the advertisement is exported at.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Whether BlueZ still advertises this advertisement. BlueZ releases advertisements once
+    /// their timeout expires, or when the adapter is powered off.
+    pub fn is_active(&self) -> bool {
+        !self.released.load(Ordering::SeqCst)
+    }
+
+    /// Stops advertising.
+    pub fn unregister(mut self) -> Result<()> {
+        self.unregister_advertisement()
+    }
+
+    fn unregister_advertisement(&mut self) -> Result<()> {
+        let export = match self.export.take() {
+            Some(export) => export,
+            None => return Ok(()),
+        };
+        if !self.is_active() {
+            // BlueZ already forgot about it.
+            return Ok(());
+        }
+        let result = self.calls.call_blocking::<_, ()>(
+            &self.adapter_path,
+            ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME,
+            "UnregisterAdvertisement",
+            (Path::from(self.path.clone()),),
+            DEFAULT_TIMEOUT,
+        );
+        drop(export);
+        debug!("Unregistered advertisement {}", self.path);
+        Ok(result?)
+    }
+}
+
+impl Drop for RegisteredAdvertisement {
+    fn drop(&mut self) {
+        if let Err(e) = self.unregister_advertisement() {
+            warn!("Could not unregister advertisement {}: {}", self.path, e);
+        }
+    }
+}
+
+impl fmt::Debug for RegisteredAdvertisement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegisteredAdvertisement")
+            .field("path", &self.path)
+            .field("adapter", &self.adapter_path)
+            .field("active", &self.is_active())
+            .finish()
+    }
+}
+
+/// Builds the reply to a method call made by BlueZ on an advertisement.
+fn handle(
+    msg: &Message,
+    path: &str,
+    advertisement: &Advertisement,
+    released: &AtomicBool,
+) -> Message {
+    let interface = msg.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+    match (interface.as_str(), member.as_str()) {
+        ("org.freedesktop.DBus.Properties", _) => {
+            let interfaces: Interfaces = vec![(
+                ORG_BLUEZ_LEADVERTISEMENT1_NAME.to_string(),
+                advertisement.to_prop_map(),
+            )]
+            .into_iter()
+            .collect();
+            properties_reply(msg, interfaces)
+        }
+        (ORG_BLUEZ_LEADVERTISEMENT1_NAME, "Release") => {
+            debug!("Advertisement {} released by BlueZ", path);
+            released.store(true, Ordering::SeqCst);
+            msg.method_return()
+        }
+        _ => unknown_method(msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::bleuuid::uuid_from_u16,
+        bluez::{
+            fake_bluez::{FakeBluez, PrivateBus},
+            manager::Manager,
+        },
+    };
+    use dbus::arg::prop_cast;
+    use futures::executor::block_on;
+    use std::sync::Mutex;
+
+    #[test]
+    fn empty_advertisement_is_connectable() {
+        let map = Advertisement::new().to_prop_map();
+        assert_eq!(prop_cast::<String>(&map, "Type").unwrap(), "peripheral");
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn advertisement_to_prop_map() {
+        let map = Advertisement::new()
+            .broadcast()
+            .local_name("Thermometer")
+            .service_uuid(uuid_from_u16(0x1809))
+            .manufacturer_data(0x004C, vec![1, 2])
+            .service_data(uuid_from_u16(0x1809), vec![3])
+            .appearance(0x0300)
+            .include_tx_power(true)
+            .discoverable(true)
+            .timeout(Duration::from_secs(30))
+            .to_prop_map();
+        assert_eq!(prop_cast::<String>(&map, "Type").unwrap(), "broadcast");
+        assert_eq!(
+            prop_cast::<String>(&map, "LocalName").unwrap(),
+            "Thermometer"
+        );
+        assert_eq!(
+            prop_cast::<Vec<String>>(&map, "ServiceUUIDs"),
+            Some(&vec![uuid_from_u16(0x1809).to_string()])
+        );
+        let manufacturer_data =
+            prop_cast::<HashMap<u16, Variant<Box<dyn RefArg>>>>(&map, "ManufacturerData").unwrap();
+        assert_eq!(
+            dbus::arg::cast::<Vec<u8>>(&manufacturer_data[&0x004C].0),
+            Some(&vec![1, 2])
+        );
+        let service_data =
+            prop_cast::<HashMap<String, Variant<Box<dyn RefArg>>>>(&map, "ServiceData").unwrap();
+        assert_eq!(
+            dbus::arg::cast::<Vec<u8>>(&service_data[&uuid_from_u16(0x1809).to_string()].0),
+            Some(&vec![3])
+        );
+        assert_eq!(prop_cast::<u16>(&map, "Appearance"), Some(&0x0300));
+        assert_eq!(
+            prop_cast::<Vec<String>>(&map, "Includes"),
+            Some(&vec!["tx-power".to_string()])
+        );
+        assert_eq!(prop_cast::<bool>(&map, "Discoverable"), Some(&true));
+        assert_eq!(prop_cast::<u16>(&map, "Timeout"), Some(&30));
+    }
+
+    #[test]
+    fn advertisements_are_registered_with_bluez() {
+        let bus = PrivateBus::start();
+        let bluez = FakeBluez::new(&bus);
+        let adapter_path = bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        bluez.add_object(
+            &adapter_path,
+            ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME,
+            vec![
+                ("ActiveInstances", Box::new(0u8)),
+                ("SupportedInstances", Box::new(4u8)),
+            ],
+        );
+        // Like BlueZ, fetch the properties of each advertisement as it is registered.
+        let registered = Arc::new(Mutex::new(Vec::new()));
+        let fetched = registered.clone();
+        bluez.on_method(
+            ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME,
+            "RegisterAdvertisement",
+            move |msg, bluez| {
+                let sender = msg.sender().unwrap().to_string();
+                let path: Path = msg.read1().unwrap();
+                let call = Message::new_method_call(
+                    &sender,
+                    path.clone(),
+                    "org.freedesktop.DBus.Properties",
+                    "GetAll",
+                )
+                .unwrap()
+                .append1(ORG_BLUEZ_LEADVERTISEMENT1_NAME);
+                let properties: PropMap = bluez.call(call).unwrap().read1().unwrap();
+                fetched
+                    .lock()
+                    .unwrap()
+                    .push((sender, path.to_string(), properties));
+                msg.method_return()
+            },
+        );
+        let manager = Manager::with_bus_address(bus.address()).unwrap();
+        let adapter = manager.adapters().unwrap().remove(0);
+        assert_eq!(adapter.supported_advertisement_instances().unwrap(), 4);
+
+        let first = adapter
+            .advertise(&Advertisement::new().local_name("First"))
+            .unwrap();
+        let second = adapter
+            .advertise(&Advertisement::new().local_name("Second"))
+            .unwrap();
+        let sender = {
+            let registered = registered.lock().unwrap();
+            assert_eq!(
+                registered
+                    .iter()
+                    .map(|(_, path, properties)| (
+                        path.clone(),
+                        prop_cast::<String>(properties, "LocalName")
+                            .unwrap()
+                            .clone()
+                    ))
+                    .collect::<Vec<_>>(),
+                vec![
+                    (first.path().to_string(), "First".to_string()),
+                    (second.path().to_string(), "Second".to_string())
+                ]
+            );
+            registered[0].0.clone()
+        };
+
+        // BlueZ releasing an advertisement makes it inactive, and it isn't unregistered anymore.
+        let release = Message::new_method_call(
+            &sender,
+            first.path(),
+            ORG_BLUEZ_LEADVERTISEMENT1_NAME,
+            "Release",
+        )
+        .unwrap();
+        bluez.call(release).unwrap();
+        assert!(!first.is_active());
+        assert!(second.is_active());
+        drop(first);
+        let second_path = second.path().to_string();
+        // Unregistering doesn't need an executor, so advertisements can be dropped within one.
+        block_on(async move { drop(second) });
+        assert_eq!(
+            bluez
+                .calls("UnregisterAdvertisement")
+                .iter()
+                .map(|m| m.read1::<Path>().unwrap().to_string())
+                .collect::<Vec<_>>(),
+            vec![second_path]
+        );
+    }
+}
@@ -0,0 +1,577 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Pairing agents, exported to BlueZ as `org.bluez.Agent1` objects and registered through
+//! `org.bluez.AgentManager1.RegisterAgent`.
+
+use crate::{
+    api::BDAddr,
+    bluez::{
+        async_connection::AsyncConnection,
+        bluez_dbus::manager::ORG_BLUEZ_AGENT_MANAGER1_NAME,
+        export::{device_address, error_reply, unknown_method, Export},
+        DEFAULT_TIMEOUT,
+    },
+    Error, Result,
+};
+use dbus::{blocking::SyncConnection, Message, Path};
+use log::{debug, warn};
+use parking_lot::ReentrantMutex;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use uuid::Uuid;
+
+const ORG_BLUEZ_AGENT1_NAME: &str = "org.bluez.Agent1";
+
+static AGENT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The input and output capabilities of an [Agent], which decide the pairing method used with
+/// each device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgentCapability {
+    DisplayOnly,
+    DisplayYesNo,
+    KeyboardOnly,
+    NoInputNoOutput,
+    KeyboardDisplay,
+}
+
+impl AgentCapability {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AgentCapability::DisplayOnly => "DisplayOnly",
+            AgentCapability::DisplayYesNo => "DisplayYesNo",
+            AgentCapability::KeyboardOnly => "KeyboardOnly",
+            AgentCapability::NoInputNoOutput => "NoInputNoOutput",
+            AgentCapability::KeyboardDisplay => "KeyboardDisplay",
+        }
+    }
+}
+
+/// Handles the interactions needed to pair with devices, such as entering or confirming a
+/// passkey. See the
+/// [BlueZ documentation](https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/agent-api.txt)
+/// for when each method is called.
+///
+/// Methods are called from a thread of the agent, one request at a time, and BlueZ waits for them
+/// to return. They can wait for the user, such as to confirm a passkey, without holding up the
+/// events and operations of any adapter. Returning `Error::AuthenticationRejected` or `Error::AuthenticationCanceled` rejects or
+/// cancels the request, any other error rejects it. Requests an agent doesn't implement are
+/// rejected.
+pub trait Agent: Send + Sync {
+    /// Returns the PIN code to pair with a legacy device.
+    fn request_pin_code(&self, device: BDAddr) -> Result<String> {
+        let _ = device;
+        Err(Error::AuthenticationRejected(
+            "No PIN code available".to_string(),
+        ))
+    }
+
+    /// Displays the PIN code the user must enter on a legacy device.
+    fn display_pin_code(&self, device: BDAddr, pin_code: &str) -> Result<()> {
+        let _ = (device, pin_code);
+        Ok(())
+    }
+
+    /// Returns the passkey, between 0 and 999999, displayed by the device.
+    fn request_passkey(&self, device: BDAddr) -> Result<u32> {
+        let _ = device;
+        Err(Error::AuthenticationRejected(
+            "No passkey available".to_string(),
+        ))
+    }
+
+    /// Displays the passkey the user must type on the device. `entered` is the number of digits
+    /// typed so far, this is called again as it changes.
+    fn display_passkey(&self, device: BDAddr, passkey: u32, entered: u16) {
+        let _ = (device, passkey, entered);
+    }
+
+    /// Asks the user to confirm that the device displays `passkey` too (numeric comparison).
+    fn request_confirmation(&self, device: BDAddr, passkey: u32) -> Result<()> {
+        let _ = (device, passkey);
+        Err(Error::AuthenticationRejected(
+            "Confirmation not supported".to_string(),
+        ))
+    }
+
+    /// Asks the user to accept a pairing request coming from the device, when no passkey is
+    /// involved.
+    fn request_authorization(&self, device: BDAddr) -> Result<()> {
+        let _ = device;
+        Err(Error::AuthenticationRejected(
+            "Authorization not supported".to_string(),
+        ))
+    }
+
+    /// Asks whether the device may connect to the local service `uuid`.
+    fn authorize_service(&self, device: BDAddr, uuid: Uuid) -> Result<()> {
+        let _ = (device, uuid);
+        Err(Error::AuthenticationRejected(
+            "Service authorization not supported".to_string(),
+        ))
+    }
+
+    /// The pending request was canceled, for instance because the device went away.
+    fn cancel(&self) {}
+
+    /// BlueZ no longer uses the agent, for instance because it is shutting down.
+    fn release(&self) {}
+}
+
+/// An [Agent] registered with BlueZ. It stays registered until it is unregistered or dropped.
+pub struct AgentRegistration {
+    path: String,
+    calls: AsyncConnection,
+    released: Arc<AtomicBool>,
+    export: Option<Export>,
+}
+
+impl AgentRegistration {
+    pub(crate) fn register<A: Agent + 'static>(
+        listener: Arc<ReentrantMutex<SyncConnection>>,
+        calls: AsyncConnection,
+        agent: A,
+        capability: AgentCapability,
+    ) -> Result<AgentRegistration> {
+        if calls.is_dispatcher_thread() {
+            return Err(Error::NotSupported(
+                "Agents can't be registered from the thread dispatching BlueZ events".to_string(),
+            ));
+        }
+        let path = format!(
+            "/org/btleplug/agent{}",
+            AGENT_COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let released = Arc::new(AtomicBool::new(false));
+        let export = {
+            let released = released.clone();
+            Export::new(listener, calls.clone(), &path, move |msg| {
+                handle(msg, &agent, &released)
+            })
+        };
+        calls.call_blocking::<_, ()>(
+            "/org/bluez",
+            ORG_BLUEZ_AGENT_MANAGER1_NAME,
+            "RegisterAgent",
+            (Path::from(path.clone()), capability.as_str()),
+            DEFAULT_TIMEOUT,
+        )?;
+        debug!("Registered agent {} as {:?}", path, capability);
+        Ok(AgentRegistration {
+            path,
+            calls,
+            released,
+            export: Some(export),
+        })
+    }
+
+    /// The D-Bus object path the agent is exported at.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Makes this the agent handling pairing requests that don't come from this application, such
+    /// as devices initiating pairing.
+    pub fn request_default(&self) -> Result<()> {
+        Ok(self.calls.call_blocking::<_, ()>(
+            "/org/bluez",
+            ORG_BLUEZ_AGENT_MANAGER1_NAME,
+            "RequestDefaultAgent",
+            (Path::from(self.path.clone()),),
+            DEFAULT_TIMEOUT,
+        )?)
+    }
+
+    /// Unregisters the agent from BlueZ.
+    pub fn unregister(mut self) -> Result<()> {
+        self.unregister_agent()
+    }
+
+    fn unregister_agent(&mut self) -> Result<()> {
+        let export = match self.export.take() {
+            Some(export) => export,
+            None => return Ok(()),
+        };
+        if self.released.load(Ordering::SeqCst) {
+            // BlueZ already forgot about it.
+            return Ok(());
+        }
+        let result = self.calls.call_blocking::<_, ()>(
+            "/org/bluez",
+            ORG_BLUEZ_AGENT_MANAGER1_NAME,
+            "UnregisterAgent",
+            (Path::from(self.path.clone()),),
+            DEFAULT_TIMEOUT,
+        );
+        drop(export);
+        debug!("Unregistered agent {}", self.path);
+        Ok(result?)
+    }
+}
+
+impl Drop for AgentRegistration {
+    fn drop(&mut self) {
+        if let Err(e) = self.unregister_agent() {
+            warn!("Could not unregister agent {}: {}", self.path, e);
+        }
+    }
+}
+
+impl fmt::Debug for AgentRegistration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgentRegistration")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// Builds the reply to a method call made by BlueZ on an agent.
+fn handle<A: Agent>(msg: &Message, agent: &A, released: &AtomicBool) -> Message {
+    let interface = msg.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+    if interface != ORG_BLUEZ_AGENT1_NAME {
+        return unknown_method(msg);
+    }
+    debug!("Agent request {}", member);
+    match member.as_str() {
+        "Release" => {
+            released.store(true, Ordering::SeqCst);
+            agent.release();
+            return msg.method_return();
+        }
+        "Cancel" => {
+            agent.cancel();
+            return msg.method_return();
+        }
+        _ => {}
+    }
+
+    // Every other request is about a device.
+    let device = match msg.get1::<Path>().as_deref().and_then(device_address) {
+        Some(device) => device,
+        None => {
+            return error_reply(
+                msg,
+                &Error::AuthenticationRejected("Unknown device".to_string()),
+            )
+        }
+    };
+    let reply = match member.as_str() {
+        "RequestPinCode" => agent
+            .request_pin_code(device)
+            .map(|pin_code| msg.method_return().append1(pin_code)),
+        "DisplayPinCode" => match msg.read2::<Path, &str>() {
+            Ok((_, pin_code)) => agent.display_pin_code(device, pin_code),
+            Err(_) => Err(invalid_request()),
+        }
+        .map(|_| msg.method_return()),
+        "RequestPasskey" => agent
+            .request_passkey(device)
+            .map(|passkey| msg.method_return().append1(passkey)),
+        "DisplayPasskey" => match msg.read3::<Path, u32, u16>() {
+            Ok((_, passkey, entered)) => {
+                agent.display_passkey(device, passkey, entered);
+                Ok(msg.method_return())
+            }
+            Err(_) => Err(invalid_request()),
+        },
+        "RequestConfirmation" => match msg.read2::<Path, u32>() {
+            Ok((_, passkey)) => agent.request_confirmation(device, passkey),
+            Err(_) => Err(invalid_request()),
+        }
+        .map(|_| msg.method_return()),
+        "RequestAuthorization" => agent
+            .request_authorization(device)
+            .map(|_| msg.method_return()),
+        "AuthorizeService" => match msg.read2::<Path, &str>() {
+            Ok((_, uuid)) => uuid
+                .parse()
+                .map_err(Error::from)
+                .and_then(|uuid| agent.authorize_service(device, uuid)),
+            Err(_) => Err(invalid_request()),
+        }
+        .map(|_| msg.method_return()),
+        _ => return unknown_method(msg),
+    };
+    reply.unwrap_or_else(|error| {
+        debug!("Agent rejected {}: {}", member, error);
+        // Agents can only reject or cancel requests.
+        let error = match error {
+            Error::AuthenticationCanceled(_) | Error::AuthenticationRejected(_) => error,
+            error => Error::AuthenticationRejected(error.to_string()),
+        };
+        error_reply(msg, &error)
+    })
+}
+
+fn invalid_request() -> Error {
+    Error::AuthenticationRejected("Invalid request".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{AsyncCentral, Peripheral as _},
+        bluez::{
+            adapter::Adapter,
+            fake_bluez::{FakeBluez, PrivateBus},
+            manager::Manager,
+        },
+    };
+    use futures::executor::block_on;
+    use std::{
+        sync::{
+            mpsc::{channel, Receiver, Sender},
+            Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    const DEVICE: &str = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F";
+
+    /// Accepts the passkey 123456 only, and records what it's asked.
+    #[derive(Clone, Default)]
+    struct TestAgent {
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Agent for TestAgent {
+        fn request_passkey(&self, device: BDAddr) -> Result<u32> {
+            self.log(format!("passkey for {}", device));
+            Ok(123456)
+        }
+
+        fn display_passkey(&self, _device: BDAddr, passkey: u32, entered: u16) {
+            self.log(format!("display {} ({})", passkey, entered));
+        }
+
+        fn request_confirmation(&self, _device: BDAddr, passkey: u32) -> Result<()> {
+            self.log(format!("confirm {}", passkey));
+            match passkey {
+                123456 => Ok(()),
+                _ => Err(Error::AuthenticationRejected("Wrong passkey".to_string())),
+            }
+        }
+
+        fn cancel(&self) {
+            self.log("cancel".to_string());
+        }
+    }
+
+    impl TestAgent {
+        fn log(&self, request: String) {
+            self.requests.lock().unwrap().push(request);
+        }
+    }
+
+    /// Sets up an adapter with a device to pair with, and returns the connection name and path
+    /// of the agents as they get registered.
+    fn setup() -> (
+        PrivateBus,
+        FakeBluez,
+        Adapter,
+        Arc<Mutex<Option<(String, String)>>>,
+    ) {
+        let bus = PrivateBus::start();
+        let bluez = FakeBluez::new(&bus);
+        bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        bluez.add_object(
+            DEVICE,
+            "org.bluez.Device1",
+            vec![("Address", Box::new("0A:0B:0C:0D:0E:0F".to_string()))],
+        );
+        let registered = Arc::new(Mutex::new(None));
+        let agent = registered.clone();
+        bluez.on_method(
+            ORG_BLUEZ_AGENT_MANAGER1_NAME,
+            "RegisterAgent",
+            move |msg, _| {
+                let (path, capability): (Path, &str) = msg.read2().unwrap();
+                assert_eq!(capability, "KeyboardDisplay");
+                *agent.lock().unwrap() =
+                    Some((msg.sender().unwrap().to_string(), path.to_string()));
+                msg.method_return()
+            },
+        );
+        let manager = Manager::with_bus_address(bus.address()).unwrap();
+        let adapter = manager.adapters().unwrap().remove(0);
+        (bus, bluez, adapter, registered)
+    }
+
+    fn agent_call(agent: &(String, String), member: &str) -> Message {
+        Message::new_method_call(&agent.0, &agent.1, ORG_BLUEZ_AGENT1_NAME, member).unwrap()
+    }
+
+    #[test]
+    fn agent_requests_are_answered() {
+        let (_bus, bluez, adapter, registered) = setup();
+        let agent = TestAgent::default();
+        let registration = adapter
+            .register_agent(agent.clone(), AgentCapability::KeyboardDisplay)
+            .unwrap();
+        let exported = registered.lock().unwrap().clone().unwrap();
+        assert_eq!(exported.1, registration.path());
+        registration.request_default().unwrap();
+        assert_eq!(
+            bluez.calls("RequestDefaultAgent")[0]
+                .read1::<Path>()
+                .unwrap()
+                .to_string(),
+            registration.path()
+        );
+
+        let device = Path::from(DEVICE);
+        let passkey: u32 = bluez
+            .call(agent_call(&exported, "RequestPasskey").append1(&device))
+            .unwrap()
+            .read1()
+            .unwrap();
+        assert_eq!(passkey, 123456);
+        bluez
+            .call(agent_call(&exported, "DisplayPasskey").append3(&device, 654321u32, 2u16))
+            .unwrap();
+        let rejected = bluez
+            .call(agent_call(&exported, "RequestConfirmation").append2(&device, 111111u32))
+            .unwrap_err();
+        assert_eq!(rejected.name(), Some("org.bluez.Error.Rejected"));
+        // Requests the agent doesn't implement are rejected.
+        let rejected = bluez
+            .call(agent_call(&exported, "RequestAuthorization").append1(&device))
+            .unwrap_err();
+        assert_eq!(rejected.name(), Some("org.bluez.Error.Rejected"));
+        bluez.call(agent_call(&exported, "Cancel")).unwrap();
+        assert_eq!(
+            *agent.requests.lock().unwrap(),
+            vec![
+                "passkey for 0A:0B:0C:0D:0E:0F",
+                "display 654321 (2)",
+                "confirm 111111",
+                "cancel"
+            ]
+        );
+
+        // Unregistering doesn't need an executor, so agents can be dropped within one.
+        block_on(async move { drop(registration) });
+        assert_eq!(
+            bluez.calls("UnregisterAgent")[0]
+                .read1::<Path>()
+                .unwrap()
+                .to_string(),
+            exported.1
+        );
+    }
+
+    /// Confirms passkeys once told to, the way an agent asking the user would.
+    struct PromptingAgent {
+        prompted: Mutex<Sender<u32>>,
+        answers: Mutex<Receiver<bool>>,
+    }
+
+    impl Agent for PromptingAgent {
+        fn request_confirmation(&self, _device: BDAddr, passkey: u32) -> Result<()> {
+            self.prompted.lock().unwrap().send(passkey).unwrap();
+            match self.answers.lock().unwrap().recv() {
+                Ok(true) => Ok(()),
+                _ => Err(Error::AuthenticationRejected("Not confirmed".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn prompts_dont_hold_up_the_dispatcher() {
+        let (_bus, bluez, adapter, registered) = setup();
+        let (prompted, prompts) = channel();
+        let (answer, answers) = channel();
+        let _registration = adapter
+            .register_agent(
+                PromptingAgent {
+                    prompted: Mutex::new(prompted),
+                    answers: Mutex::new(answers),
+                },
+                AgentCapability::KeyboardDisplay,
+            )
+            .unwrap();
+        let exported = registered.lock().unwrap().clone().unwrap();
+
+        let confirmation =
+            agent_call(&exported, "RequestConfirmation").append2(Path::from(DEVICE), 123456u32);
+        let caller = bluez.clone();
+        let reply = thread::spawn(move || caller.call(confirmation).map(|_reply| ()));
+        assert_eq!(
+            prompts.recv_timeout(Duration::from_secs(5)).unwrap(),
+            123456
+        );
+        // While the user makes up their mind, the adapter keeps working.
+        assert!(adapter.is_powered().unwrap());
+        answer.send(true).unwrap();
+        assert!(reply.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn released_agents_are_not_unregistered() {
+        let (_bus, bluez, adapter, registered) = setup();
+        let registration = adapter
+            .register_agent(TestAgent::default(), AgentCapability::KeyboardDisplay)
+            .unwrap();
+        let exported = registered.lock().unwrap().clone().unwrap();
+        bluez.call(agent_call(&exported, "Release")).unwrap();
+        registration.unregister().unwrap();
+        assert!(bluez.calls("UnregisterAgent").is_empty());
+    }
+
+    #[test]
+    fn pairing_outcome_is_reported() {
+        let (_bus, bluez, adapter, registered) = setup();
+        let _registration = adapter
+            .register_agent(TestAgent::default(), AgentCapability::KeyboardDisplay)
+            .unwrap();
+        // Pairing asks the agent to confirm the passkey displayed by the device.
+        let displayed = Arc::new(Mutex::new(123456u32));
+        let passkey = displayed.clone();
+        bluez.on_method("org.bluez.Device1", "Pair", move |msg, bluez| {
+            let agent = registered.lock().unwrap().clone().unwrap();
+            let confirm = agent_call(&agent, "RequestConfirmation")
+                .append2(Path::from(DEVICE), *passkey.lock().unwrap());
+            match bluez.call(confirm) {
+                Ok(_) => msg.method_return(),
+                Err(_) => msg.error(
+                    &"org.bluez.Error.AuthenticationRejected".into(),
+                    &std::ffi::CString::new("Authentication Rejected").unwrap(),
+                ),
+            }
+        });
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter
+            .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+            .unwrap();
+
+        peripheral.pair().unwrap();
+        *displayed.lock().unwrap() = 111111;
+        assert!(matches!(
+            peripheral.pair(),
+            Err(Error::AuthenticationRejected(_))
+        ));
+
+        // Pairing an already paired device is fine.
+        bluez.on_method("org.bluez.Device1", "Pair", |msg, _| {
+            msg.error(
+                &"org.bluez.Error.AlreadyExists".into(),
+                &std::ffi::CString::new("Already Exists").unwrap(),
+            )
+        });
+        peripheral.pair().unwrap();
+        assert_eq!(bluez.calls("Pair").len(), 3);
+    }
+}
@@ -0,0 +1,467 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The GATT database of a remote device, built from the objects BlueZ exports for it.
+//!
+//! BlueZ names the objects of a device after their handle, such as `service0010/char0011`, but
+//! reports them in no particular order, so attributes are kept sorted by handle. The handle range
+//! of a characteristic runs up to the attribute before the next characteristic or service.
+
+use crate::{
+    api::{CharPropFlags, Characteristic, Descriptor, IncludedService, Service},
+    bluez::{AttributeType, Handle},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Bound,
+};
+use uuid::Uuid;
+
+/// A service, characteristic or descriptor of a remote device.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Attribute {
+    /// The D-Bus object path of the attribute
+    pub path: String,
+    pub handle: Handle,
+    pub uuid: Uuid,
+    /// The properties of a characteristic, empty for services and descriptors
+    pub properties: CharPropFlags,
+    /// Whether a service is primary, true for other attributes
+    pub primary: bool,
+    /// The handles of the services included by a service
+    pub includes: Vec<u16>,
+}
+
+impl Attribute {
+    fn is(&self, typ: AttributeType) -> bool {
+        self.handle.typ == typ
+    }
+}
+
+/// Every attribute of a device, sorted by handle. This is what services, characteristics and their
+/// descriptors are derived from.
+#[derive(Debug, Default)]
+pub(crate) struct AttributeDb {
+    attributes: BTreeMap<u16, Attribute>,
+    /// The characteristics BlueZ reports as notifying or indicating
+    notifying: BTreeSet<u16>,
+    resolved: bool,
+    /// Set once the database was first resolved, from when on changes to it are reported.
+    reported: bool,
+    /// Whether attributes changed while BlueZ was resolving the services again.
+    changed: bool,
+}
+
+impl AttributeDb {
+    /// Adds an attribute, replacing any previous one with the same handle. Returns whether this
+    /// changed the database.
+    pub fn insert(&mut self, attribute: Attribute) -> bool {
+        let handle = attribute.handle.handle;
+        let changed = self.attributes.insert(handle, attribute.clone()) != Some(attribute);
+        self.changed |= changed;
+        changed
+    }
+
+    /// Removes an attribute along with the attributes it contains, and returns the
+    /// characteristics that were removed, as they were reported until then. Returns `None` if
+    /// there is no attribute at `handle`.
+    pub fn remove(&mut self, handle: u16) -> Option<Vec<Characteristic>> {
+        self.attributes.get(&handle)?;
+        let mut removed = BTreeSet::new();
+        removed.insert(handle);
+        // The characteristics of a service, then the descriptors of those characteristics.
+        for _ in 0..2 {
+            let contained: Vec<u16> = self
+                .attributes
+                .values()
+                .filter(|a| !a.is(AttributeType::Service) && removed.contains(&a.handle.parent))
+                .map(|a| a.handle.handle)
+                .collect();
+            removed.extend(contained);
+        }
+        let characteristics = removed
+            .iter()
+            .filter_map(|handle| self.attributes.get(handle))
+            .filter(|a| a.is(AttributeType::Characteristic))
+            .map(|c| self.characteristic(c))
+            .collect();
+        for handle in removed {
+            self.attributes.remove(&handle);
+            self.notifying.remove(&handle);
+        }
+        self.changed = true;
+        Some(characteristics)
+    }
+
+    pub fn get(&self, handle: u16) -> Option<&Attribute> {
+        self.attributes.get(&handle)
+    }
+
+    /// Returns the attribute at `handle`, provided it still has the expected type and UUID. The
+    /// same handle can be used by another attribute once the services of the device change.
+    pub fn find(&self, handle: u16, typ: AttributeType, uuid: Uuid) -> Option<&Attribute> {
+        self.attributes
+            .get(&handle)
+            .filter(|a| a.is(typ) && a.uuid == uuid)
+    }
+
+    /// Records whether the characteristic at `handle` is notifying, and returns it if this changed
+    /// its state. Returns `None` if there is no characteristic at `handle`.
+    pub fn set_notifying(&mut self, handle: u16, notifying: bool) -> Option<Characteristic> {
+        let attribute = self
+            .attributes
+            .get(&handle)
+            .filter(|a| a.is(AttributeType::Characteristic))?;
+        let changed = if notifying {
+            self.notifying.insert(handle)
+        } else {
+            self.notifying.remove(&handle)
+        };
+        if changed {
+            Some(self.characteristic(attribute))
+        } else {
+            None
+        }
+    }
+
+    /// Marks every characteristic as no longer notifying, as happens on disconnection, and
+    /// returns those that were.
+    pub fn clear_notifying(&mut self) -> Vec<Characteristic> {
+        let handles = std::mem::take(&mut self.notifying);
+        handles
+            .iter()
+            .filter_map(|handle| self.attributes.get(handle))
+            .map(|c| self.characteristic(c))
+            .collect()
+    }
+
+    pub fn is_notifying(&self, handle: u16) -> bool {
+        self.notifying.contains(&handle)
+    }
+
+    /// The characteristics that are notifying, once the database is resolved.
+    pub fn notifying(&self) -> BTreeSet<Characteristic> {
+        self.of_type(AttributeType::Characteristic)
+            .filter(|c| self.notifying.contains(&c.handle.handle))
+            .map(|c| self.characteristic(c))
+            .collect()
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.resolved
+    }
+
+    /// Whether the database was resolved before, so the attributes removed from it had been
+    /// reported, even if BlueZ is now resolving the services again.
+    pub fn is_reported(&self) -> bool {
+        self.reported
+    }
+
+    /// Marks the database as complete, once BlueZ has resolved every service, or as incomplete
+    /// while BlueZ resolves them again, such as after a Service Changed indication. Services and
+    /// characteristics are only reported while resolved, so a partial database is never seen.
+    /// Returns true when the database is resolved again after it changed meanwhile.
+    pub fn set_resolved(&mut self, resolved: bool) -> bool {
+        let changed = resolved && !self.resolved && self.reported && self.changed;
+        if resolved != self.resolved {
+            self.changed = false;
+        }
+        self.resolved = resolved;
+        self.reported |= resolved;
+        changed
+    }
+
+    pub fn services(&self) -> BTreeSet<Service> {
+        self.of_type(AttributeType::Service)
+            .map(|service| Service {
+                handle: service.handle.handle,
+                uuid: service.uuid,
+                primary: service.primary,
+                included_services: service
+                    .includes
+                    .iter()
+                    .filter_map(|handle| self.attributes.get(handle))
+                    .map(|included| IncludedService {
+                        handle: included.handle.handle,
+                        uuid: included.uuid,
+                    })
+                    .collect(),
+                characteristics: self
+                    .of_type(AttributeType::Characteristic)
+                    .filter(|c| c.handle.parent == service.handle.handle)
+                    .map(|c| self.characteristic(c))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    pub fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.of_type(AttributeType::Characteristic)
+            .map(|c| self.characteristic(c))
+            .collect()
+    }
+
+    pub fn descriptors(&self, characteristic: &Characteristic) -> BTreeSet<Descriptor> {
+        self.attributes
+            .values()
+            .filter(|d| {
+                d.is(AttributeType::Descriptor) && d.handle.parent == characteristic.value_handle
+            })
+            .map(|d| Descriptor {
+                handle: d.handle.handle,
+                uuid: d.uuid,
+                characteristic_uuid: characteristic.uuid,
+            })
+            .collect()
+    }
+
+    /// Returns the first characteristic or descriptor of type `uuid` within the handle range of
+    /// `characteristic`, which is what an ATT Read By Type request would read.
+    pub fn find_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Option<&Attribute> {
+        if characteristic.start_handle > characteristic.end_handle {
+            return None;
+        }
+        self.attributes
+            .range(characteristic.start_handle..=characteristic.end_handle)
+            .map(|(_h, attribute)| attribute)
+            .find(|a| a.uuid == uuid && !a.is(AttributeType::Service))
+    }
+
+    /// The attributes of one type, in handle order, once the database is resolved.
+    fn of_type(&self, typ: AttributeType) -> impl Iterator<Item = &Attribute> {
+        let resolved = self.resolved;
+        self.attributes
+            .values()
+            .filter(move |a| resolved && a.is(typ))
+    }
+
+    fn characteristic(&self, attribute: &Attribute) -> Characteristic {
+        let handle = attribute.handle.handle;
+        // Descriptors follow their characteristic, up to the next characteristic or service.
+        let end_handle = self
+            .attributes
+            .range((Bound::Excluded(handle), Bound::Unbounded))
+            .find(|(_h, a)| !a.is(AttributeType::Descriptor))
+            .map_or(u16::MAX, |(next, _a)| next - 1);
+        Characteristic {
+            start_handle: handle,
+            end_handle,
+            value_handle: handle,
+            uuid: attribute.uuid,
+            properties: attribute.properties,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::bleuuid::uuid_from_u16;
+
+    fn attribute(path: &str, uuid: u16) -> Attribute {
+        Attribute {
+            path: path.to_string(),
+            handle: path.parse().unwrap(),
+            uuid: uuid_from_u16(uuid),
+            properties: CharPropFlags::empty(),
+            primary: true,
+            includes: Vec::new(),
+        }
+    }
+
+    /// Two services, whose attributes are inserted in no particular order, as BlueZ reports them.
+    fn database() -> AttributeDb {
+        let mut db = AttributeDb::default();
+        for (path, uuid) in &[
+            ("service0020/char0023/desc0025", 0x2902),
+            ("service0010/char0014", 0x2A01),
+            ("service0020", 0x180D),
+            ("service0010/char0011/desc0013", 0x2901),
+            ("service0020/char0021", 0x2A37),
+            ("service0010", 0x1800),
+            ("service0010/char0011", 0x2A00),
+            ("service0020/char0023", 0x2A38),
+        ] {
+            db.insert(attribute(path, *uuid));
+        }
+        db.set_resolved(true);
+        db
+    }
+
+    fn ranges(db: &AttributeDb) -> Vec<(u16, u16)> {
+        db.characteristics()
+            .iter()
+            .map(|c| (c.start_handle, c.end_handle))
+            .collect()
+    }
+
+    #[test]
+    fn characteristic_ranges_follow_handles() {
+        assert_eq!(
+            ranges(&database()),
+            vec![(0x11, 0x13), (0x14, 0x1F), (0x21, 0x22), (0x23, u16::MAX)]
+        );
+    }
+
+    #[test]
+    fn services_own_their_characteristics() {
+        let services = database().services().into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            services
+                .iter()
+                .map(|s| (s.handle, s.uuid))
+                .collect::<Vec<_>>(),
+            vec![(0x10, uuid_from_u16(0x1800)), (0x20, uuid_from_u16(0x180D))]
+        );
+        let heart_rate = services[1]
+            .characteristics
+            .iter()
+            .map(|c| (c.value_handle, c.end_handle))
+            .collect::<Vec<_>>();
+        assert_eq!(heart_rate, vec![(0x21, 0x22), (0x23, u16::MAX)]);
+    }
+
+    #[test]
+    fn descriptors_belong_to_their_characteristic() {
+        let db = database();
+        let characteristic = db.characteristics().into_iter().next().unwrap();
+        let descriptors = db.descriptors(&characteristic);
+        assert_eq!(
+            descriptors.iter().map(|d| d.handle).collect::<Vec<_>>(),
+            vec![0x13]
+        );
+    }
+
+    #[test]
+    fn find_by_type_searches_the_characteristic_range() {
+        let db = database();
+        let characteristics = db.characteristics().into_iter().collect::<Vec<_>>();
+        let found = |c: &Characteristic, uuid| db.find_by_type(c, uuid_from_u16(uuid));
+
+        assert_eq!(
+            found(&characteristics[0], 0x2901).unwrap().handle.handle,
+            0x13
+        );
+        assert_eq!(
+            found(&characteristics[0], 0x2A00).unwrap().handle.handle,
+            0x11
+        );
+        // Attributes of other characteristics are out of range.
+        assert!(found(&characteristics[1], 0x2901).is_none());
+        assert!(found(&characteristics[2], 0x2902).is_none());
+        assert_eq!(
+            found(&characteristics[3], 0x2902).unwrap().handle.handle,
+            0x25
+        );
+        // Services are never read by type.
+        let everything = Characteristic {
+            start_handle: 0,
+            end_handle: u16::MAX,
+            ..characteristics[0].clone()
+        };
+        assert!(found(&everything, 0x180D).is_none());
+    }
+
+    #[test]
+    fn removal_takes_contained_attributes() {
+        let mut db = database();
+        let removed = db.remove(0x10).unwrap();
+        assert_eq!(
+            removed.iter().map(|c| c.end_handle).collect::<Vec<_>>(),
+            vec![0x13, 0x1F]
+        );
+        assert!(db.get(0x13).is_none());
+        assert_eq!(
+            db.services().iter().map(|s| s.handle).collect::<Vec<_>>(),
+            vec![0x20]
+        );
+
+        let removed = db.remove(0x21).unwrap();
+        assert_eq!(removed.len(), 1);
+        // The next characteristic's range is unaffected, and removing it again does nothing.
+        assert_eq!(ranges(&db), vec![(0x23, u16::MAX)]);
+        assert!(db.remove(0x21).is_none());
+        assert!(db.remove(0x25).unwrap().is_empty());
+    }
+
+    #[test]
+    fn attributes_are_found_by_type_and_uuid() {
+        let mut db = database();
+        let heart_rate = uuid_from_u16(0x2A37);
+        assert!(db
+            .find(0x21, AttributeType::Characteristic, heart_rate)
+            .is_some());
+        assert!(db
+            .find(0x21, AttributeType::Descriptor, heart_rate)
+            .is_none());
+
+        // Another characteristic took over the handle.
+        assert!(db.insert(attribute("service0020/char0021", 0x2A39)));
+        assert!(!db.insert(attribute("service0020/char0021", 0x2A39)));
+        assert!(db
+            .find(0x21, AttributeType::Characteristic, heart_rate)
+            .is_none());
+    }
+
+    #[test]
+    fn notifying_state_follows_characteristics() {
+        let mut db = database();
+        assert_eq!(db.set_notifying(0x21, true).unwrap().value_handle, 0x21);
+        assert!(db.set_notifying(0x21, true).is_none());
+        assert!(db.set_notifying(0x10, true).is_none());
+        assert!(db.set_notifying(0x25, true).is_none());
+        db.set_notifying(0x23, true);
+        assert_eq!(
+            db.notifying()
+                .iter()
+                .map(|c| c.value_handle)
+                .collect::<Vec<_>>(),
+            vec![0x21, 0x23]
+        );
+
+        // Removed characteristics stop notifying, even if their handle is reused.
+        db.remove(0x21);
+        db.insert(attribute("service0020/char0021", 0x2A39));
+        assert!(!db.is_notifying(0x21));
+        assert_eq!(db.clear_notifying().len(), 1);
+        assert!(db.notifying().is_empty());
+        assert!(db.set_notifying(0x23, false).is_none());
+    }
+
+    #[test]
+    fn nothing_is_reported_until_resolved() {
+        let mut db = AttributeDb::default();
+        db.insert(attribute("service0010", 0x1800));
+        db.insert(attribute("service0010/char0011", 0x2A00));
+        assert!(db.services().is_empty());
+        assert!(db.characteristics().is_empty());
+        assert_eq!(db.get(0x11).unwrap().uuid, uuid_from_u16(0x2A00));
+        db.set_resolved(true);
+        assert_eq!(ranges(&db), vec![(0x11, u16::MAX)]);
+    }
+
+    #[test]
+    fn changes_are_reported_once_resolved_again() {
+        let mut db = database();
+        // Nothing changed while BlueZ resolved the services again.
+        assert!(!db.set_resolved(false));
+        assert!(db.characteristics().is_empty());
+        assert!(db.is_reported());
+        assert!(!db.set_resolved(true));
+        assert_eq!(db.characteristics().len(), 4);
+
+        db.set_resolved(false);
+        assert_eq!(db.remove(0x20).unwrap().len(), 2);
+        db.insert(attribute("service0020", 0x180F));
+        db.insert(attribute("service0020/char0021", 0x2A19));
+        assert!(db.set_resolved(true));
+        assert_eq!(
+            ranges(&db),
+            vec![(0x11, 0x13), (0x14, 0x1F), (0x21, u16::MAX)]
+        );
+    }
+}
@@ -0,0 +1,1165 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! A local GATT server, exported to BlueZ as an application through
+//! `org.bluez.GattManager1.RegisterApplication`.
+//!
+//! The services are exported as the object tree described in the
+//! [BlueZ documentation](https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/gatt-api.txt):
+//! an `ObjectManager` at the root of the application, with one object per service,
+//! characteristic and descriptor below it.
+
+use crate::{
+    api::{AttErrorCode, BDAddr, CharPropFlags, WriteType},
+    bluez::{
+        async_connection::AsyncConnection,
+        bluez_dbus::adapter::ORG_BLUEZ_GATT_MANAGER1_NAME,
+        bluez_dbus::gatt_characteristic::ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+        bluez_dbus::gatt_descriptor::ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+        bluez_dbus::gatt_service::ORG_BLUEZ_GATT_SERVICE1_NAME,
+        export::{
+            device_address, error_reply, properties_reply, unknown_method, variant, Export,
+            Interfaces,
+        },
+        DEFAULT_TIMEOUT,
+    },
+    Error, Result,
+};
+use dbus::{
+    arg::PropMap,
+    blocking::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, SyncConnection},
+    message::SignalArgs,
+    Message, Path,
+};
+use log::{debug, warn};
+use parking_lot::ReentrantMutex;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use uuid::Uuid;
+
+static APPLICATION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+type ReadHandler = Box<dyn Fn(&ReadRequest) -> Result<Vec<u8>> + Send + Sync>;
+type WriteHandler = Box<dyn Fn(&WriteRequest) -> Result<()> + Send + Sync>;
+
+/// A read of a local characteristic or descriptor by a remote central.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadRequest {
+    /// The offset into the value at which the central is reading, for reads of long values.
+    pub offset: usize,
+    /// The central reading the value, when BlueZ reports it.
+    pub device: Option<BDAddr>,
+    /// The ATT MTU negotiated with the central, when BlueZ reports it.
+    pub mtu: Option<u16>,
+}
+
+/// A write to a local characteristic or descriptor by a remote central.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteRequest {
+    /// The bytes written.
+    pub value: Vec<u8>,
+    /// The offset into the value at which the bytes are written.
+    pub offset: usize,
+    /// The central writing the value, when BlueZ reports it.
+    pub device: Option<BDAddr>,
+    /// Whether the central expects a response to the write.
+    pub write_type: WriteType,
+}
+
+/// A service to expose from a local GATT server.
+#[derive(Debug)]
+pub struct LocalService {
+    uuid: Uuid,
+    primary: bool,
+    characteristics: Vec<LocalCharacteristic>,
+}
+
+impl LocalService {
+    /// Creates a primary service, without any characteristic.
+    pub fn new(uuid: Uuid) -> Self {
+        LocalService {
+            uuid,
+            primary: true,
+            characteristics: Vec::new(),
+        }
+    }
+
+    /// Makes this a secondary service.
+    pub fn secondary(mut self) -> Self {
+        self.primary = false;
+        self
+    }
+
+    /// Adds a characteristic to the service.
+    pub fn characteristic(mut self, characteristic: LocalCharacteristic) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+}
+
+/// The value of a local characteristic or descriptor, and how it is accessed.
+struct LocalValue {
+    value: Mutex<Vec<u8>>,
+    on_read: Option<ReadHandler>,
+    on_write: Option<WriteHandler>,
+}
+
+impl LocalValue {
+    fn new() -> Self {
+        LocalValue {
+            value: Mutex::new(Vec::new()),
+            on_read: None,
+            on_write: None,
+        }
+    }
+
+    fn get(&self) -> Vec<u8> {
+        self.value.lock().unwrap().clone()
+    }
+
+    fn set(&self, value: Vec<u8>) {
+        *self.value.lock().unwrap() = value;
+    }
+
+    fn read(&self, request: &ReadRequest) -> Result<Vec<u8>> {
+        let value = match &self.on_read {
+            Some(on_read) => {
+                let value = on_read(request)?;
+                self.set(value.clone());
+                value
+            }
+            None => self.get(),
+        };
+        value
+            .get(request.offset..)
+            .map(|v| v.to_vec())
+            .ok_or(Error::Att(AttErrorCode::InvalidOffset))
+    }
+
+    fn write(&self, request: &WriteRequest) -> Result<()> {
+        let invalid_offset = |value: &[u8]| request.offset > value.len();
+        if invalid_offset(&self.get()) {
+            return Err(Error::Att(AttErrorCode::InvalidOffset));
+        }
+        if let Some(on_write) = &self.on_write {
+            on_write(request)?;
+        }
+        // The value may have been changed meanwhile, such as by a notification.
+        let mut value = self.value.lock().unwrap();
+        if invalid_offset(&value) {
+            return Err(Error::Att(AttErrorCode::InvalidOffset));
+        }
+        value.truncate(request.offset);
+        value.extend_from_slice(&request.value);
+        Ok(())
+    }
+}
+
+/// A characteristic of a [LocalService].
+///
+/// Without handlers, reads return the last value written or notified, and writes replace it.
+pub struct LocalCharacteristic {
+    uuid: Uuid,
+    properties: CharPropFlags,
+    value: LocalValue,
+    descriptors: Vec<LocalDescriptor>,
+}
+
+impl LocalCharacteristic {
+    /// Creates a characteristic. `properties` decide which operations centrals can perform on it.
+    pub fn new(uuid: Uuid, properties: CharPropFlags) -> Self {
+        LocalCharacteristic {
+            uuid,
+            properties,
+            value: LocalValue::new(),
+            descriptors: Vec::new(),
+        }
+    }
+
+    /// Sets the initial value of the characteristic.
+    pub fn value(self, value: Vec<u8>) -> Self {
+        self.value.set(value);
+        self
+    }
+
+    /// Produces the value when a central reads the characteristic. The offset of the request is
+    /// applied to the returned value, which also becomes the stored value. Returning an error
+    /// fails the read.
+    pub fn on_read<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&ReadRequest) -> Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.value.on_read = Some(Box::new(handler));
+        self
+    }
+
+    /// Called when a central writes the characteristic, before the stored value is updated.
+    /// Returning an error rejects the write and leaves the value untouched.
+    pub fn on_write<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&WriteRequest) -> Result<()> + Send + Sync + 'static,
+    {
+        self.value.on_write = Some(Box::new(handler));
+        self
+    }
+
+    /// Adds a descriptor to the characteristic. BlueZ provides the Client Characteristic
+    /// Configuration descriptor of notifying characteristics by itself.
+    pub fn descriptor(mut self, descriptor: LocalDescriptor) -> Self {
+        self.descriptors.push(descriptor);
+        self
+    }
+
+    fn can_notify(&self) -> bool {
+        self.properties
+            .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+    }
+}
+
+impl fmt::Debug for LocalCharacteristic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalCharacteristic")
+            .field("uuid", &self.uuid)
+            .field("properties", &self.properties)
+            .field("descriptors", &self.descriptors)
+            .finish()
+    }
+}
+
+/// A descriptor of a [LocalCharacteristic]. Descriptors are readable, and only writable when
+/// asked for.
+pub struct LocalDescriptor {
+    uuid: Uuid,
+    writable: bool,
+    value: LocalValue,
+}
+
+impl LocalDescriptor {
+    /// Creates a read-only descriptor.
+    pub fn new(uuid: Uuid) -> Self {
+        LocalDescriptor {
+            uuid,
+            writable: false,
+            value: LocalValue::new(),
+        }
+    }
+
+    /// Sets the initial value of the descriptor.
+    pub fn value(self, value: Vec<u8>) -> Self {
+        self.value.set(value);
+        self
+    }
+
+    /// Lets centrals write the descriptor.
+    pub fn writable(mut self) -> Self {
+        self.writable = true;
+        self
+    }
+
+    /// Produces the value when a central reads the descriptor, see
+    /// [LocalCharacteristic::on_read].
+    pub fn on_read<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&ReadRequest) -> Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.value.on_read = Some(Box::new(handler));
+        self
+    }
+
+    /// Called when a central writes the descriptor, see [LocalCharacteristic::on_write].
+    pub fn on_write<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&WriteRequest) -> Result<()> + Send + Sync + 'static,
+    {
+        self.value.on_write = Some(Box::new(handler));
+        self
+    }
+}
+
+impl fmt::Debug for LocalDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalDescriptor")
+            .field("uuid", &self.uuid)
+            .field("writable", &self.writable)
+            .finish()
+    }
+}
+
+/// Converts characteristic properties into the flags understood by BlueZ.
+fn characteristic_flags(properties: CharPropFlags) -> Vec<String> {
+    [
+        (CharPropFlags::BROADCAST, "broadcast"),
+        (CharPropFlags::READ, "read"),
+        (
+            CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            "write-without-response",
+        ),
+        (CharPropFlags::WRITE, "write"),
+        (CharPropFlags::NOTIFY, "notify"),
+        (CharPropFlags::INDICATE, "indicate"),
+        (
+            CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+            "authenticated-signed-writes",
+        ),
+        (CharPropFlags::EXTENDED_PROPERTIES, "extended-properties"),
+    ]
+    .iter()
+    .filter(|(flag, _)| properties.contains(*flag))
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+/// The object a method call is addressed to.
+enum Object<'a> {
+    Root,
+    Service(&'a ServiceObject),
+    Characteristic(&'a CharacteristicObject),
+    Descriptor(&'a DescriptorObject),
+}
+
+struct ServiceObject {
+    path: String,
+    service: LocalService,
+    characteristics: Vec<CharacteristicObject>,
+}
+
+struct CharacteristicObject {
+    path: String,
+    service_path: String,
+    characteristic: LocalCharacteristic,
+    notifying: AtomicBool,
+    descriptors: Vec<DescriptorObject>,
+}
+
+struct DescriptorObject {
+    path: String,
+    characteristic_path: String,
+    descriptor: LocalDescriptor,
+}
+
+impl ServiceObject {
+    fn interfaces(&self) -> Interfaces {
+        let mut props = PropMap::new();
+        props.insert("UUID".to_string(), variant(self.service.uuid.to_string()));
+        props.insert("Primary".to_string(), variant(self.service.primary));
+        props.insert("Includes".to_string(), variant(Vec::<Path>::new()));
+        vec![(ORG_BLUEZ_GATT_SERVICE1_NAME.to_string(), props)]
+            .into_iter()
+            .collect()
+    }
+}
+
+impl CharacteristicObject {
+    fn interfaces(&self) -> Interfaces {
+        let characteristic = &self.characteristic;
+        let mut props = PropMap::new();
+        props.insert("UUID".to_string(), variant(characteristic.uuid.to_string()));
+        props.insert(
+            "Service".to_string(),
+            variant(Path::from(self.service_path.clone())),
+        );
+        props.insert(
+            "Flags".to_string(),
+            variant(characteristic_flags(characteristic.properties)),
+        );
+        props.insert("Value".to_string(), variant(characteristic.value.get()));
+        if characteristic.can_notify() {
+            props.insert(
+                "Notifying".to_string(),
+                variant(self.notifying.load(Ordering::SeqCst)),
+            );
+        }
+        vec![(ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME.to_string(), props)]
+            .into_iter()
+            .collect()
+    }
+}
+
+impl DescriptorObject {
+    fn interfaces(&self) -> Interfaces {
+        let descriptor = &self.descriptor;
+        let mut flags = vec!["read".to_string()];
+        if descriptor.writable {
+            flags.push("write".to_string());
+        }
+        let mut props = PropMap::new();
+        props.insert("UUID".to_string(), variant(descriptor.uuid.to_string()));
+        props.insert(
+            "Characteristic".to_string(),
+            variant(Path::from(self.characteristic_path.clone())),
+        );
+        props.insert("Flags".to_string(), variant(flags));
+        props.insert("Value".to_string(), variant(descriptor.value.get()));
+        vec![(ORG_BLUEZ_GATT_DESCRIPTOR1_NAME.to_string(), props)]
+            .into_iter()
+            .collect()
+    }
+}
+
+/// The exported object tree of an application, shared with the thread serving it.
+struct Application {
+    path: String,
+    services: Vec<ServiceObject>,
+}
+
+impl Application {
+    fn new(services: Vec<LocalService>) -> Self {
+        let path = format!(
+            "/org/btleplug/gatt_app{}",
+            APPLICATION_COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let services = services
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut service)| {
+                let service_path = format!("{}/service{}", path, i);
+                let characteristics = service
+                    .characteristics
+                    .drain(..)
+                    .enumerate()
+                    .map(|(j, mut characteristic)| {
+                        let characteristic_path = format!("{}/char{}", service_path, j);
+                        let descriptors = characteristic
+                            .descriptors
+                            .drain(..)
+                            .enumerate()
+                            .map(|(k, descriptor)| DescriptorObject {
+                                path: format!("{}/desc{}", characteristic_path, k),
+                                characteristic_path: characteristic_path.clone(),
+                                descriptor,
+                            })
+                            .collect();
+                        CharacteristicObject {
+                            path: characteristic_path,
+                            service_path: service_path.clone(),
+                            characteristic,
+                            notifying: AtomicBool::new(false),
+                            descriptors,
+                        }
+                    })
+                    .collect();
+                ServiceObject {
+                    path: service_path,
+                    service,
+                    characteristics,
+                }
+            })
+            .collect();
+        Application { path, services }
+    }
+
+    fn object(&self, path: &str) -> Option<Object<'_>> {
+        if path == self.path {
+            return Some(Object::Root);
+        }
+        for service in &self.services {
+            if service.path == path {
+                return Some(Object::Service(service));
+            }
+            for characteristic in &service.characteristics {
+                if characteristic.path == path {
+                    return Some(Object::Characteristic(characteristic));
+                }
+                if let Some(descriptor) = characteristic.descriptors.iter().find(|d| d.path == path)
+                {
+                    return Some(Object::Descriptor(descriptor));
+                }
+            }
+        }
+        None
+    }
+
+    fn characteristic(&self, service: Uuid, characteristic: Uuid) -> Result<&CharacteristicObject> {
+        self.services
+            .iter()
+            .filter(|s| s.service.uuid == service)
+            .flat_map(|s| s.characteristics.iter())
+            .find(|c| c.characteristic.uuid == characteristic)
+            .ok_or_else(|| {
+                Error::Other(format!(
+                    "No local characteristic {} in service {}",
+                    characteristic, service
+                ))
+            })
+    }
+
+    fn managed_objects(&self) -> HashMap<Path<'static>, Interfaces> {
+        let mut objects = HashMap::new();
+        for service in &self.services {
+            objects.insert(Path::from(service.path.clone()), service.interfaces());
+            for characteristic in &service.characteristics {
+                objects.insert(
+                    Path::from(characteristic.path.clone()),
+                    characteristic.interfaces(),
+                );
+                for descriptor in &characteristic.descriptors {
+                    objects.insert(Path::from(descriptor.path.clone()), descriptor.interfaces());
+                }
+            }
+        }
+        objects
+    }
+
+    /// Builds the reply to a method call made by BlueZ.
+    fn handle(&self, msg: &Message) -> Message {
+        let path = msg.path().map(|p| p.to_string()).unwrap_or_default();
+        let interface = msg.interface().map(|i| i.to_string()).unwrap_or_default();
+        let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+        debug!("GATT application call {}.{} on {}", interface, member, path);
+
+        let object = match self.object(&path) {
+            Some(object) => object,
+            None => return unknown_method(msg),
+        };
+        let result = match (object, interface.as_str(), member.as_str()) {
+            (Object::Root, "org.freedesktop.DBus.ObjectManager", "GetManagedObjects") => {
+                return msg.method_return().append1(self.managed_objects())
+            }
+            (Object::Service(service), "org.freedesktop.DBus.Properties", _) => {
+                return properties_reply(msg, service.interfaces())
+            }
+            (Object::Characteristic(characteristic), "org.freedesktop.DBus.Properties", _) => {
+                return properties_reply(msg, characteristic.interfaces())
+            }
+            (Object::Descriptor(descriptor), "org.freedesktop.DBus.Properties", _) => {
+                return properties_reply(msg, descriptor.interfaces())
+            }
+            (Object::Characteristic(characteristic), _, "ReadValue") => {
+                read_request(msg).and_then(|request| {
+                    if !characteristic
+                        .characteristic
+                        .properties
+                        .contains(CharPropFlags::READ)
+                    {
+                        return Err(Error::Att(AttErrorCode::ReadNotPermitted));
+                    }
+                    characteristic.characteristic.value.read(&request)
+                })
+            }
+            (Object::Characteristic(characteristic), _, "WriteValue") => write_request(msg)
+                .and_then(|request| {
+                    let properties = characteristic.characteristic.properties;
+                    let allowed = match request.write_type {
+                        WriteType::WithResponse => properties.contains(CharPropFlags::WRITE),
+                        WriteType::WithoutResponse => {
+                            properties.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+                        }
+                    };
+                    if !allowed {
+                        return Err(Error::Att(AttErrorCode::WriteNotPermitted));
+                    }
+                    characteristic.characteristic.value.write(&request)?;
+                    Ok(Vec::new())
+                }),
+            (Object::Characteristic(characteristic), _, "StartNotify")
+            | (Object::Characteristic(characteristic), _, "StopNotify") => {
+                if characteristic.characteristic.can_notify() {
+                    characteristic
+                        .notifying
+                        .store(member == "StartNotify", Ordering::SeqCst);
+                    return msg.method_return();
+                }
+                Err(Error::NotSupported(
+                    "The characteristic doesn't notify nor indicate".to_string(),
+                ))
+            }
+            (Object::Descriptor(descriptor), _, "ReadValue") => {
+                read_request(msg).and_then(|request| descriptor.descriptor.value.read(&request))
+            }
+            (Object::Descriptor(descriptor), _, "WriteValue") => {
+                write_request(msg).and_then(|request| {
+                    if !descriptor.descriptor.writable {
+                        return Err(Error::Att(AttErrorCode::WriteNotPermitted));
+                    }
+                    descriptor.descriptor.value.write(&request)?;
+                    Ok(Vec::new())
+                })
+            }
+            _ => return unknown_method(msg),
+        };
+        match result {
+            Ok(value) if member == "ReadValue" => msg.method_return().append1(value),
+            Ok(_) => msg.method_return(),
+            Err(error) => {
+                debug!("Rejecting {} on {}: {}", member, path, error);
+                error_reply(msg, &error)
+            }
+        }
+    }
+}
+
+/// Reads the address of the device whose object path is given in an options dictionary.
+fn device_option(options: &PropMap) -> Option<BDAddr> {
+    options
+        .get("device")
+        .and_then(|device| device.0.as_str())
+        .and_then(device_address)
+}
+
+fn offset_option(options: &PropMap) -> usize {
+    options
+        .get("offset")
+        .and_then(|offset| offset.0.as_u64())
+        .unwrap_or(0) as usize
+}
+
+fn read_request(msg: &Message) -> Result<ReadRequest> {
+    let options: PropMap = msg.read1().map_err(dbus::Error::from)?;
+    Ok(ReadRequest {
+        offset: offset_option(&options),
+        device: device_option(&options),
+        mtu: options
+            .get("mtu")
+            .and_then(|mtu| mtu.0.as_u64())
+            .map(|mtu| mtu as u16),
+    })
+}
+
+fn write_request(msg: &Message) -> Result<WriteRequest> {
+    let (value, options): (Vec<u8>, PropMap) = msg.read2().map_err(dbus::Error::from)?;
+    Ok(WriteRequest {
+        value,
+        offset: offset_option(&options),
+        device: device_option(&options),
+        write_type: match options.get("type").and_then(|t| t.0.as_str()) {
+            Some("command") => WriteType::WithoutResponse,
+            _ => WriteType::WithResponse,
+        },
+    })
+}
+
+/// A GATT application registered with BlueZ. Its services stay exposed to remote centrals until
+/// it is unregistered or dropped.
+pub struct GattApplication {
+    application: Arc<Application>,
+    calls: AsyncConnection,
+    adapter_path: String,
+    export: Option<Export>,
+}
+
+impl GattApplication {
+    pub(crate) fn register(
+        listener: Arc<ReentrantMutex<SyncConnection>>,
+        calls: AsyncConnection,
+        adapter_path: &str,
+        services: Vec<LocalService>,
+    ) -> Result<GattApplication> {
+        if calls.is_dispatcher_thread() {
+            return Err(Error::NotSupported(
+                "GATT applications can't be registered from the thread dispatching BlueZ events"
+                    .to_string(),
+            ));
+        }
+        let application = Arc::new(Application::new(services));
+        let served = application.clone();
+        let export = Export::new(listener, calls.clone(), &application.path, move |msg| {
+            served.handle(msg)
+        });
+        // BlueZ fetches the object tree before replying, which the thread of the export answers.
+        calls.call_blocking::<_, ()>(
+            adapter_path,
+            ORG_BLUEZ_GATT_MANAGER1_NAME,
+            "RegisterApplication",
+            (Path::from(application.path.clone()), PropMap::new()),
+            DEFAULT_TIMEOUT,
+        )?;
+        debug!("Registered GATT application {}", application.path);
+        Ok(GattApplication {
+            application,
+            calls,
+            adapter_path: adapter_path.to_string(),
+            export: Some(export),
+        })
+    }
+
+    /// The D-Bus object path the application is exported at.
+    pub fn path(&self) -> &str {
+        &self.application.path
+    }
+
+    /// Returns the current value of a characteristic.
+    pub fn value(&self, service: Uuid, characteristic: Uuid) -> Result<Vec<u8>> {
+        Ok(self
+            .application
+            .characteristic(service, characteristic)?
+            .characteristic
+            .value
+            .get())
+    }
+
+    /// Sets the value of a characteristic and notifies (or indicates) it to the centrals that
+    /// subscribed to it. Returns whether any central is subscribed.
+    pub fn notify(&self, service: Uuid, characteristic: Uuid, value: Vec<u8>) -> Result<bool> {
+        let characteristic = self.application.characteristic(service, characteristic)?;
+        if !characteristic.characteristic.can_notify() {
+            return Err(Error::NotSupported(format!(
+                "Characteristic {} doesn't notify nor indicate",
+                characteristic.characteristic.uuid
+            )));
+        }
+        characteristic.characteristic.value.set(value.clone());
+        if !characteristic.notifying.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        let mut changed = PropMap::new();
+        changed.insert("Value".to_string(), variant(value));
+        let signal = PropertiesPropertiesChanged {
+            interface_name: ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME.to_string(),
+            changed_properties: changed,
+            invalidated_properties: vec![],
+        };
+        let msg = signal.to_emit_message(&Path::from(characteristic.path.clone()));
+        self.calls
+            .send(msg)
+            .map_err(|_| Error::Other("Could not send notification to BlueZ".to_string()))?;
+        Ok(true)
+    }
+
+    /// Unregisters the application from BlueZ, and stops serving its objects.
+    pub fn unregister(mut self) -> Result<()> {
+        self.unregister_application()
+    }
+
+    fn unregister_application(&mut self) -> Result<()> {
+        let export = match self.export.take() {
+            Some(export) => export,
+            None => return Ok(()),
+        };
+        let result = self.calls.call_blocking::<_, ()>(
+            &self.adapter_path,
+            ORG_BLUEZ_GATT_MANAGER1_NAME,
+            "UnregisterApplication",
+            (Path::from(self.application.path.clone()),),
+            DEFAULT_TIMEOUT,
+        );
+        drop(export);
+        debug!("Unregistered GATT application {}", self.application.path);
+        Ok(result?)
+    }
+}
+
+impl Drop for GattApplication {
+    fn drop(&mut self) {
+        if let Err(e) = self.unregister_application() {
+            warn!(
+                "Could not unregister GATT application {}: {}",
+                self.application.path, e
+            );
+        }
+    }
+}
+
+impl fmt::Debug for GattApplication {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GattApplication")
+            .field("path", &self.application.path)
+            .field("adapter", &self.adapter_path)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::bleuuid::uuid_from_u16,
+        bluez::{
+            adapter::Adapter,
+            fake_bluez::{FakeBluez, PrivateBus},
+            manager::Manager,
+        },
+    };
+    use dbus::arg::{prop_cast, RefArg};
+    use futures::executor::block_on;
+    use std::{sync::mpsc::channel, thread, time::Duration};
+
+    type ManagedObjects = HashMap<Path<'static>, Interfaces>;
+
+    /// Sets up an adapter whose `RegisterApplication` fetches the objects of the application,
+    /// like BlueZ does, and returns them with the name of the connection that registered them.
+    fn setup() -> (
+        PrivateBus,
+        FakeBluez,
+        Adapter,
+        Arc<Mutex<Option<(String, ManagedObjects)>>>,
+    ) {
+        let bus = PrivateBus::start();
+        let bluez = FakeBluez::new(&bus);
+        bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        let registered = Arc::new(Mutex::new(None));
+        let objects = registered.clone();
+        bluez.on_method(
+            ORG_BLUEZ_GATT_MANAGER1_NAME,
+            "RegisterApplication",
+            move |msg, bluez| {
+                let sender = msg.sender().unwrap().to_string();
+                let path: Path = msg.read1().unwrap();
+                let call = Message::new_method_call(
+                    &sender,
+                    path,
+                    "org.freedesktop.DBus.ObjectManager",
+                    "GetManagedObjects",
+                )
+                .unwrap();
+                let managed_objects = bluez.call(call).unwrap().read1().unwrap();
+                *objects.lock().unwrap() = Some((sender, managed_objects));
+                msg.method_return()
+            },
+        );
+        let manager = Manager::with_bus_address(bus.address()).unwrap();
+        let adapter = manager.adapters().unwrap().remove(0);
+        (bus, bluez, adapter, registered)
+    }
+
+    fn heart_rate_service() -> LocalService {
+        LocalService::new(uuid_from_u16(0x180D))
+            .characteristic(
+                LocalCharacteristic::new(
+                    uuid_from_u16(0x2A37),
+                    CharPropFlags::READ | CharPropFlags::NOTIFY,
+                )
+                .value(vec![1, 2, 3])
+                .descriptor(LocalDescriptor::new(uuid_from_u16(0x2901)).value(b"Rate".to_vec())),
+            )
+            .characteristic(
+                LocalCharacteristic::new(uuid_from_u16(0x2A39), CharPropFlags::WRITE).on_write(
+                    |request| match request.value.as_slice() {
+                        [0] => Err(Error::NotPermitted("Reserved value".to_string())),
+                        _ => Ok(()),
+                    },
+                ),
+            )
+    }
+
+    fn method_call(destination: &str, path: &str, interface: &str, member: &str) -> Message {
+        Message::new_method_call(destination, path, interface, member).unwrap()
+    }
+
+    fn options(entries: Vec<(&str, Box<dyn RefArg>)>) -> PropMap {
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), dbus::arg::Variant(v)))
+            .collect()
+    }
+
+    #[test]
+    fn application_is_exported_to_bluez() {
+        let (_bus, bluez, adapter, registered) = setup();
+        let application = adapter
+            .register_application(vec![heart_rate_service()])
+            .unwrap();
+        let (_sender, objects) = registered.lock().unwrap().take().unwrap();
+        let registration = bluez.calls("RegisterApplication").remove(0);
+        assert_eq!(registration.path().unwrap().to_string(), "/org/bluez/hci0");
+        assert_eq!(
+            registration.read1::<Path>().unwrap().to_string(),
+            application.path()
+        );
+
+        let service = format!("{}/service0", application.path());
+        let measurement = format!("{}/char0", service);
+        assert_eq!(
+            {
+                let mut paths: Vec<_> = objects.keys().map(|p| p.to_string()).collect();
+                paths.sort();
+                paths
+            },
+            vec![
+                service.clone(),
+                measurement.clone(),
+                format!("{}/desc0", measurement),
+                format!("{}/char1", service),
+            ]
+        );
+        let service_props = &objects[&Path::from(service.clone())][ORG_BLUEZ_GATT_SERVICE1_NAME];
+        assert_eq!(
+            prop_cast::<String>(service_props, "UUID").unwrap(),
+            &uuid_from_u16(0x180D).to_string()
+        );
+        assert_eq!(prop_cast::<bool>(service_props, "Primary"), Some(&true));
+        let measurement_props =
+            &objects[&Path::from(measurement.clone())][ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME];
+        assert_eq!(
+            prop_cast::<Vec<String>>(measurement_props, "Flags").unwrap(),
+            &vec!["read".to_string(), "notify".to_string()]
+        );
+        assert_eq!(
+            measurement_props["Service"].0.as_str(),
+            Some(service.as_str())
+        );
+    }
+
+    #[test]
+    fn reads_and_writes_are_served() {
+        let (_bus, bluez, adapter, registered) = setup();
+        let application = adapter
+            .register_application(vec![heart_rate_service()])
+            .unwrap();
+        let (sender, _objects) = registered.lock().unwrap().take().unwrap();
+        let measurement = format!("{}/service0/char0", application.path());
+        let control_point = format!("{}/service0/char1", application.path());
+
+        let read = method_call(
+            &sender,
+            &measurement,
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            "ReadValue",
+        )
+        .append1(options(vec![
+            ("offset", Box::new(1u16)),
+            (
+                "device",
+                Box::new(Path::from("/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F")),
+            ),
+        ]));
+        let value: Vec<u8> = bluez.call(read).unwrap().read1().unwrap();
+        assert_eq!(value, vec![2, 3]);
+
+        let read = method_call(
+            &sender,
+            &format!("{}/desc0", measurement),
+            ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+            "ReadValue",
+        )
+        .append1(PropMap::new());
+        let value: Vec<u8> = bluez.call(read).unwrap().read1().unwrap();
+        assert_eq!(value, b"Rate".to_vec());
+
+        let write = method_call(
+            &sender,
+            &control_point,
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            "WriteValue",
+        )
+        .append2(
+            vec![5u8],
+            options(vec![("type", Box::new("request".to_string()))]),
+        );
+        bluez.call(write).unwrap();
+        assert_eq!(
+            application
+                .value(uuid_from_u16(0x180D), uuid_from_u16(0x2A39))
+                .unwrap(),
+            vec![5]
+        );
+
+        // Errors from handlers are reported to BlueZ, and the value is left untouched.
+        let write = method_call(
+            &sender,
+            &control_point,
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            "WriteValue",
+        )
+        .append2(vec![0u8], PropMap::new());
+        let error = bluez.call(write).unwrap_err();
+        assert_eq!(error.name(), Some("org.bluez.Error.NotPermitted"));
+        // Operations the characteristic doesn't support are refused.
+        let read = method_call(
+            &sender,
+            &control_point,
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            "ReadValue",
+        )
+        .append1(PropMap::new());
+        assert_eq!(
+            bluez.call(read).unwrap_err().name(),
+            Some("org.bluez.Error.NotPermitted")
+        );
+        assert_eq!(
+            application
+                .value(uuid_from_u16(0x180D), uuid_from_u16(0x2A39))
+                .unwrap(),
+            vec![5]
+        );
+    }
+
+    #[test]
+    fn notifications_are_sent_once_subscribed() {
+        let (_bus, bluez, adapter, registered) = setup();
+        let application = adapter
+            .register_application(vec![heart_rate_service()])
+            .unwrap();
+        let (sender, _objects) = registered.lock().unwrap().take().unwrap();
+        let measurement = format!("{}/service0/char0", application.path());
+        let (service_uuid, measurement_uuid) = (uuid_from_u16(0x180D), uuid_from_u16(0x2A37));
+
+        // Nobody is subscribed yet.
+        assert!(!application
+            .notify(service_uuid, measurement_uuid, vec![60])
+            .unwrap());
+        bluez
+            .call(method_call(
+                &sender,
+                &measurement,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "StartNotify",
+            ))
+            .unwrap();
+        assert!(application
+            .notify(service_uuid, measurement_uuid, vec![61])
+            .unwrap());
+        FakeBluez::wait_for(|| !bluez.signals().is_empty());
+        let signal = bluez.signals().remove(0);
+        assert_eq!(signal.path().unwrap().to_string(), measurement);
+        let changed = PropertiesPropertiesChanged::from_message(&signal).unwrap();
+        assert_eq!(changed.interface_name, ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME);
+        assert_eq!(
+            prop_cast::<Vec<u8>>(&changed.changed_properties, "Value"),
+            Some(&vec![61])
+        );
+        assert_eq!(
+            application.value(service_uuid, measurement_uuid).unwrap(),
+            vec![61]
+        );
+
+        // Characteristics that can't notify, or don't exist, are reported.
+        assert!(application
+            .notify(service_uuid, uuid_from_u16(0x2A39), vec![1])
+            .is_err());
+        assert!(application
+            .notify(uuid_from_u16(0x180F), measurement_uuid, vec![1])
+            .is_err());
+    }
+
+    #[test]
+    fn applications_are_unregistered() {
+        let (_bus, bluez, adapter, _registered) = setup();
+        let first = adapter
+            .register_application(vec![heart_rate_service()])
+            .unwrap();
+        let second = adapter
+            .register_application(vec![heart_rate_service()])
+            .unwrap();
+        assert_ne!(first.path(), second.path());
+        let paths = vec![first.path().to_string(), second.path().to_string()];
+
+        first.unregister().unwrap();
+        // Unregistering doesn't need an executor, so applications can be dropped within one.
+        block_on(async move { drop(second) });
+        assert_eq!(
+            bluez
+                .calls("UnregisterApplication")
+                .iter()
+                .map(|m| m.read1::<Path>().unwrap().to_string())
+                .collect::<Vec<_>>(),
+            paths
+        );
+    }
+
+    #[test]
+    fn writes_apply_to_the_current_value() {
+        let (started, writing) = channel();
+        let (release, released) = channel::<()>();
+        let (started, released) = (Mutex::new(started), Mutex::new(released));
+        let mut value = LocalValue::new();
+        value.set(vec![1, 2, 3]);
+        value.on_write = Some(Box::new(move |_request| {
+            started.lock().unwrap().send(()).unwrap();
+            released
+                .lock()
+                .unwrap()
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap();
+            Ok(())
+        }));
+        let value = Arc::new(value);
+        let written = value.clone();
+        let writer = thread::spawn(move || {
+            written.write(&WriteRequest {
+                value: vec![4],
+                offset: 3,
+                device: None,
+                write_type: WriteType::WithResponse,
+            })
+        });
+        // The value shrinks while the handler runs, so the offset is now past its end.
+        writing.recv_timeout(Duration::from_secs(5)).unwrap();
+        value.set(vec![1]);
+        release.send(()).unwrap();
+        assert!(matches!(
+            writer.join().unwrap(),
+            Err(Error::Att(AttErrorCode::InvalidOffset))
+        ));
+        assert_eq!(value.get(), vec![1]);
+    }
+
+    #[test]
+    fn handlers_dont_hold_up_the_dispatcher() {
+        let (_bus, bluez, adapter, registered) = setup();
+        let (started, reading) = channel();
+        let (release, released) = channel::<()>();
+        let (started, released) = (Mutex::new(started), Mutex::new(released));
+        let (sender, registrations) = channel();
+        let sender = Mutex::new(sender);
+        let handler_adapter = adapter.clone();
+        let service = LocalService::new(uuid_from_u16(0x180D))
+            .characteristic(
+                LocalCharacteristic::new(uuid_from_u16(0x2A37), CharPropFlags::READ).on_read(
+                    move |_request| {
+                        started.lock().unwrap().send(()).unwrap();
+                        released
+                            .lock()
+                            .unwrap()
+                            .recv_timeout(Duration::from_secs(5))
+                            .unwrap();
+                        Ok(vec![72])
+                    },
+                ),
+            )
+            .characteristic(
+                LocalCharacteristic::new(uuid_from_u16(0x2A39), CharPropFlags::WRITE).on_write(
+                    move |_request| {
+                        let result =
+                            handler_adapter.register_application(vec![heart_rate_service()]);
+                        let _ = sender.lock().unwrap().send(result.map(|_application| ()));
+                        Ok(())
+                    },
+                ),
+            );
+        let application = adapter.register_application(vec![service]).unwrap();
+        let (bus_name, _objects) = registered.lock().unwrap().take().unwrap();
+        let path = |characteristic: usize| {
+            format!("{}/service0/char{}", application.path(), characteristic)
+        };
+
+        // BlueZ waits for the read, but other calls are still answered meanwhile.
+        let read = method_call(
+            &bus_name,
+            &path(0),
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            "ReadValue",
+        )
+        .append1(PropMap::new());
+        let reader = bluez.clone();
+        let reply = thread::spawn(move || reader.call(read).unwrap().read1::<Vec<u8>>().unwrap());
+        reading.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(adapter.is_powered().unwrap());
+        release.send(()).unwrap();
+        assert_eq!(reply.join().unwrap(), vec![72]);
+
+        // Handlers can also register applications, as BlueZ's calls into them are answered too.
+        let write = method_call(
+            &bus_name,
+            &path(1),
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            "WriteValue",
+        )
+        .append2(vec![1u8], PropMap::new());
+        bluez.call(write).unwrap();
+        assert!(registrations
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .is_ok());
+        assert_eq!(bluez.calls("RegisterApplication").len(), 2);
+    }
+}
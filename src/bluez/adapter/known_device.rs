@@ -0,0 +1,38 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+    api::{BDAddr, BondState},
+    bluez::bluez_dbus::device::OrgBluezDevice1Properties,
+};
+
+/// A device BlueZ remembers on an adapter, whether or not it has been discovered by a scan. These
+/// are the devices listed by `bluetoothctl devices`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownDevice {
+    /// The address of the device
+    pub address: BDAddr,
+    /// The name of the device, as set by the user or else as advertised by the device
+    pub name: Option<String>,
+    /// Whether the device is paired, trusted or blocked
+    pub bond: BondState,
+}
+
+impl KnownDevice {
+    pub(crate) fn from_properties(device: OrgBluezDevice1Properties) -> Option<KnownDevice> {
+        Some(KnownDevice {
+            address: device.address()?.parse().ok()?,
+            name: device.alias().or_else(|| device.name()).cloned(),
+            bond: BondState {
+                paired: device.paired().unwrap_or(false),
+                trusted: device.trusted().unwrap_or(false),
+                blocked: device.blocked().unwrap_or(false),
+                legacy_pairing: device.legacy_pairing().unwrap_or(false),
+            },
+        })
+    }
+}
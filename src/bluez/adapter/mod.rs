@@ -13,26 +13,60 @@
 
 // mod acl_stream;
 // mod dbus;
+mod adapter_properties;
+mod advertisement;
+mod agent;
+mod attribute_db;
+mod gatt_server;
+mod known_device;
 mod peripheral;
-
+mod reconnect;
+mod scan_filter;
+mod timeouts;
+
+pub use self::advertisement::{Advertisement, RegisteredAdvertisement};
+pub use self::agent::{Agent, AgentCapability, AgentRegistration};
+pub use self::gatt_server::{
+    GattApplication, LocalCharacteristic, LocalDescriptor, LocalService, ReadRequest, WriteRequest,
+};
+pub use self::known_device::KnownDevice;
+pub use self::peripheral::CancelHandle;
+pub use self::reconnect::ReconnectPolicy;
+pub use self::scan_filter::{ScanFilter, Transport};
+pub use self::timeouts::Timeouts;
+
+use self::adapter_properties::{
+    adapter_info, adapter_properties, from_timeout, to_timeout, to_uuids,
+};
 use super::{
-    bluez_dbus::adapter::OrgBluezAdapter1, bluez_dbus::device::OrgBluezDevice1Properties,
+    async_connection::AsyncConnection, bluez_dbus::adapter::OrgBluezAdapter1,
+    bluez_dbus::adapter::OrgBluezAdapter1Properties,
+    bluez_dbus::adapter::OrgBluezLEAdvertisingManager1,
+    bluez_dbus::adapter::ORG_BLUEZ_ADAPTER1_NAME, bluez_dbus::device::OrgBluezDevice1Properties,
     bluez_dbus::device::ORG_BLUEZ_DEVICE1_NAME,
     bluez_dbus::gatt_characteristic::OrgBluezGattCharacteristic1Properties,
     bluez_dbus::gatt_characteristic::ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
-    bluez_dbus::gatt_service::ORG_BLUEZ_GATT_SERVICE1_NAME, BLUEZ_DEST, DEFAULT_TIMEOUT,
+    bluez_dbus::gatt_descriptor::OrgBluezGattDescriptor1Properties,
+    bluez_dbus::gatt_descriptor::ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+    bluez_dbus::gatt_service::OrgBluezGattService1Properties,
+    bluez_dbus::gatt_service::ORG_BLUEZ_GATT_SERVICE1_NAME, dispatcher::Dispatcher, Connection,
+    BLUEZ_DEST, DEFAULT_TIMEOUT,
 };
 use crate::{
-    api::{AdapterManager, BDAddr, Central, CentralEvent, CharPropFlags},
+    api::{
+        AdapterInfo, AdapterManager, AddressType, AsyncCentral, BDAddr, CentralEvent,
+        CharPropFlags, EventReceiver, EventSubscription,
+    },
     bluez::adapter::peripheral::Peripheral,
     Error, Result,
 };
+use async_trait::async_trait;
 use dashmap::DashMap;
 use dbus::{
-    arg::RefArg,
-    blocking::{Proxy, SyncConnection},
+    arg::PropMap,
+    blocking::{stdintf::org_freedesktop_dbus::ObjectManager, Proxy, SyncConnection},
     channel::Token,
-    message::SignalArgs,
+    message::{MatchRule, SignalArgs},
     Path,
 };
 use displaydoc::Display;
@@ -41,10 +75,13 @@ use parking_lot::ReentrantMutex;
 use static_assertions::assert_impl_all;
 use std::{
     self,
+    collections::HashMap,
     iter::Iterator,
     str::FromStr,
-    sync::{mpsc::Receiver, Arc, Condvar, Mutex},
-    thread::{self, JoinHandle},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 use thiserror::Error;
@@ -54,8 +91,13 @@ use uuid::Uuid;
 enum TokenType {
     DeviceDiscovery,
     DeviceLost,
+    AdapterProperties,
+    InterfacesAdded,
+    ServiceOwner,
 }
 
+type ManagedObjects = HashMap<Path<'static>, HashMap<String, PropMap>>;
+
 type ParseCharPropFlagsResult<T> = std::result::Result<T, ParseCharPropFlagsError>;
 
 #[derive(Debug, Error, Display, Clone, PartialEq)]
@@ -102,217 +144,672 @@ impl FromStr for CharPropFlags {
 /// dongle.
 #[derive(Clone)]
 pub struct Adapter {
-    connection: Arc<SyncConnection>,
     listener: Arc<ReentrantMutex<SyncConnection>>,
+    calls: AsyncConnection,
     path: String,
     manager: AdapterManager<Peripheral>,
     match_tokens: Arc<DashMap<TokenType, Token>>,
-
-    should_stop: Arc<(Condvar, Mutex<bool>)>,
-    thread_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    scan_filter: Arc<Mutex<ScanFilter>>,
+    /// Shared with the peripherals, so changes apply to them as well.
+    timeouts: Arc<Mutex<Timeouts>>,
+    /// Set once BlueZ reports the adapter as removed, such as when a dongle is unplugged.
+    removed: Arc<AtomicBool>,
+    /// Set once the adapter has been shut down, which is final.
+    shut_down: Arc<AtomicBool>,
+    /// Keeps the connection shared with the manager processed for as long as the adapter is used.
+    /// The clones held by the adapter's own signal handlers leave it out, as the handlers belong
+    /// to the connection and would keep it running forever.
+    _dispatcher: Option<Arc<Dispatcher>>,
 }
 
 assert_impl_all!(SyncConnection: Sync, Send);
 assert_impl_all!(Adapter: Sync, Send);
 
 impl Adapter {
-    pub(crate) fn from_dbus_path(path: &Path) -> Result<Adapter> {
-        let conn = Arc::new(SyncConnection::new_system()?);
-        let proxy = conn.with_proxy(BLUEZ_DEST, path, DEFAULT_TIMEOUT);
+    pub(crate) fn from_dbus_path(connection: &Connection, path: &Path) -> Result<Adapter> {
+        let proxy = connection.calls.proxy(path, DEFAULT_TIMEOUT);
         info!("DevInfo: {:?}", proxy.address()?);
 
         let adapter = Adapter {
-            connection: conn,
-            listener: Arc::new(ReentrantMutex::new(SyncConnection::new_system()?)),
+            listener: connection.listener.clone(),
+            calls: connection.calls.clone(),
+            _dispatcher: Some(connection.dispatcher.clone()),
             path: path.to_string(),
             manager: AdapterManager::new(),
             match_tokens: Arc::new(DashMap::new()),
-            should_stop: Arc::new((Condvar::new(), Mutex::new(false))),
-            thread_handle: Arc::new(Mutex::new(None)),
+            scan_filter: Arc::new(Mutex::new(ScanFilter::default())),
+            timeouts: Arc::new(Mutex::new(Timeouts::default())),
+            removed: Arc::new(AtomicBool::new(false)),
+            shut_down: Arc::new(AtomicBool::new(false)),
         };
 
-        adapter.setup();
+        adapter.setup()?;
 
         Ok(adapter)
     }
 
-    fn setup(&self) {
+    fn setup(&self) -> Result<()> {
+        use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesAdded as InterfacesAdded;
         use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesRemoved as InterfacesRemoved;
+        use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
         let mut lost_rule = InterfacesRemoved::match_rule(None, None);
         lost_rule.path = Some(Path::from("/"));
 
-        // // Enable watching the DBus channel for new messages
-        // conn.channel().set_watch_enabled(true);
-        // let watcher = conn.channel().watch();
-        // // watcher.
-        let listener = self.listener.clone();
-        let should_stop = self.should_stop.clone();
-
-        // Spawn a new thread to process incoming DBus messages.
-        // This thread gets cleaned up in the 'impl Drop for Adapter'
-        *self.thread_handle.lock().unwrap() = Some(thread::spawn(move || {
-            let (cvar, should_stop) = &*should_stop;
-            loop {
-                let (should_stop, _timeout_result) = cvar
-                    .wait_timeout(should_stop.lock().unwrap(), Duration::from_millis(100))
-                    .unwrap();
-                if *should_stop {
-                    break;
-                }
-
-                // listener is protected by a mutex. as calling `process()` when any proxied request is awaiting a reply, will cause the reply to be dropped...
-                let lock = listener.lock();
-                // Important! The [parking_log::ReentrantMutex] promotes fairness, that is, eventually giving
-                //  other threads waiting on a lock to get the lock. Where as, Rust's std::sync::Mutex does not.
-                //  See: https://docs.rs/parking_lot/0.11.1/parking_lot/type.Mutex.html#fairness
-                while lock.process(Duration::from_secs(0)).unwrap() {}
-            }
-        }));
+        let mut adapter_rule = PropertiesPropertiesChanged::match_rule(None, None);
+        adapter_rule.path = Some(Path::from(self.path.clone()));
+        let manager = self.manager.clone();
+        self.match_tokens.insert(
+            TokenType::AdapterProperties,
+            self.calls.add_match(
+                adapter_rule,
+                move |args: PropertiesPropertiesChanged, _c, _msg| {
+                    if args.interface_name == ORG_BLUEZ_ADAPTER1_NAME {
+                        let changed = OrgBluezAdapter1Properties(&args.changed_properties);
+                        for property in adapter_properties(changed) {
+                            debug!("Adapter property changed: {:?}", property);
+                            manager.emit(CentralEvent::AdapterUpdated(property));
+                        }
+                    }
+                    true
+                },
+            )?,
+        );
 
-        let adapter = self.clone();
+        let adapter = self.handler_clone();
         self.match_tokens.insert(
             TokenType::DeviceLost,
-            self.listener
-                .lock()
+            self.calls
                 .add_match(lost_rule, move |args: InterfacesRemoved, _c, _msg| {
                     trace!("Received 'InterfacesRemoved' signal");
-                    let path = args.object;
-
-                    if args.interfaces.iter().any(|s| s == ORG_BLUEZ_DEVICE1_NAME) {
-                        adapter.remove_device(&path).unwrap();
-                    } else if args
-                        .interfaces
-                        .iter()
-                        .any(|s| s == ORG_BLUEZ_GATT_SERVICE1_NAME)
-                    {
-                        // Ignore Services that get removed, the BTLEPlug API doesn't support that
-                    } else if args
-                        .interfaces
-                        .iter()
-                        .any(|s| s == ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME)
+                    let path = &args.object;
+                    let removed = |name| args.interfaces.iter().any(|s| s == name);
+
+                    if **path == *adapter.path && removed(ORG_BLUEZ_ADAPTER1_NAME) {
+                        info!("Adapter \"{}\" was removed", adapter.path);
+                        adapter.removed.store(true, Ordering::SeqCst);
+                    } else if !adapter.owns(path) {
+                        // An object of another adapter.
+                    } else if removed(ORG_BLUEZ_DEVICE1_NAME) {
+                        if let Err(e) = adapter.remove_device(path) {
+                            adapter
+                                .manager
+                                .emit_error(adapter.device_address(path).ok(), e);
+                        }
+                    } else if removed(ORG_BLUEZ_GATT_SERVICE1_NAME)
+                        || removed(ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME)
+                        || removed(ORG_BLUEZ_GATT_DESCRIPTOR1_NAME)
                     {
-                        // Ignore Characteristics that get removed, the BTLEPlug API doesn't support that
+                        adapter.skip_invalid(path, adapter.remove_attribute(path));
                     }
 
                     return true;
-                })
-                .unwrap(),
+                })?,
         );
+
+        let mut added_rule = InterfacesAdded::match_rule(None, None);
+        added_rule.path = Some(Path::from("/"));
+        let adapter = self.handler_clone();
+        self.match_tokens.insert(
+            TokenType::InterfacesAdded,
+            self.calls
+                .add_match(added_rule, move |args: InterfacesAdded, _c, _msg| {
+                    if *args.object == *adapter.path
+                        && args.interfaces.contains_key(ORG_BLUEZ_ADAPTER1_NAME)
+                    {
+                        if let Err(e) = adapter.restore() {
+                            adapter.manager.emit_error(None, e);
+                        }
+                    } else if adapter.owns(&args.object) {
+                        // Services can change on connected devices whether or not we're scanning.
+                        let added = adapter.add_gatt_object(&args.object, &args.interfaces);
+                        adapter.skip_invalid(&args.object, added);
+                    }
+                    true
+                })?,
+        );
+
+        // BlueZ forgets about us when it restarts, so watch it going away and coming back.
+        let mut service_rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+        service_rule.sender = Some("org.freedesktop.DBus".into());
+        let adapter = self.handler_clone();
+        self.match_tokens.insert(
+            TokenType::ServiceOwner,
+            self.calls.add_match(
+                service_rule,
+                move |(name, old_owner, new_owner): (String, String, String), _c, _msg| {
+                    if name == BLUEZ_DEST {
+                        if !old_owner.is_empty() {
+                            adapter.service_lost();
+                        }
+                        if !new_owner.is_empty() {
+                            adapter.service_restored();
+                        }
+                    }
+                    true
+                },
+            )?,
+        );
+        Ok(())
+    }
+
+    /// A clone of the adapter for its signal handlers, which doesn't keep the dispatcher running.
+    fn handler_clone(&self) -> Adapter {
+        Adapter {
+            _dispatcher: None,
+            ..self.clone()
+        }
     }
 
-    pub fn proxy(&self) -> Proxy<&SyncConnection> {
-        self.connection
-            .with_proxy(BLUEZ_DEST, &self.path, DEFAULT_TIMEOUT)
+    /// Stops scanning, and removes every signal match the adapter and its peripherals added, so it
+    /// no longer handles any event. This is final, and applies to every clone of the adapter:
+    /// operations fail with `AdapterNotFound` from then on.
+    ///
+    /// Everything is cleaned up even if some of it fails, in which case the first error is
+    /// returned. The connection, which is shared with the manager and the other adapters, keeps
+    /// being processed until the last of them is dropped, or the manager is shut down.
+    pub fn shutdown(&self) -> Result<()> {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        debug!("Shutting down adapter \"{}\"", self.path);
+        let mut result = Ok(());
+        if let Some((_t, token)) = self.match_tokens.remove(&TokenType::DeviceDiscovery) {
+            result = result.and(self.calls.remove_match(token).map_err(Error::from));
+            if !self.removed.load(Ordering::SeqCst) {
+                result = result.and(match self.proxy().stop_discovery() {
+                    Err(e) if e.name() == Some("org.bluez.Error.InProgress") => Ok(()),
+                    stopped => stopped.map_err(Error::from),
+                });
+            }
+        }
+        let token_types: Vec<TokenType> =
+            self.match_tokens.iter().map(|t| t.key().clone()).collect();
+        for token_type in token_types {
+            if let Some((_t, token)) = self.match_tokens.remove(&token_type) {
+                result = result.and(self.calls.remove_match(token).map_err(Error::from));
+            }
+        }
+        for peripheral in self.manager.peripherals() {
+            result = result.and(peripheral.stop_listening());
+        }
+        result
+    }
+
+    /// Drops every peripheral once BlueZ has stopped, as their objects are gone with it.
+    fn service_lost(&self) {
+        warn!(
+            "BlueZ has stopped, adapter \"{}\" is unavailable until it is back",
+            self.path
+        );
+        self.removed.store(true, Ordering::SeqCst);
+        self.manager.emit(CentralEvent::BluetoothServiceLost);
+        for peripheral in self.manager.peripherals() {
+            let address = crate::api::AsyncPeripheral::address(&peripheral);
+            if let Err(e) = peripheral.stop_listening() {
+                self.manager.emit_error(Some(address), e);
+            }
+            self.manager.emit(CentralEvent::DeviceLost(address));
+        }
+    }
+
+    fn service_restored(&self) {
+        info!("BlueZ has started");
+        self.manager.emit(CentralEvent::BluetoothServiceRestored);
+        // BlueZ registers the adapters after taking its name, so the adapter is generally restored
+        // once it is announced with `InterfacesAdded`, rather than here.
+        if let Err(e) = self.restore() {
+            self.manager.emit_error(None, e);
+        }
+    }
+
+    /// Reloads the devices of an adapter that is available again after being removed, and resumes
+    /// scanning if a scan was running. Does nothing if the adapter is still missing, or was never
+    /// removed.
+    ///
+    /// This is called from the dispatcher thread, whose blocking calls read their own reply.
+    fn restore(&self) -> Result<()> {
+        let objects = self
+            .calls
+            .proxy("/", self.timeouts().calls)
+            .get_managed_objects()?;
+        let available = matches!(
+            objects.get(&Path::from(self.path.clone())),
+            Some(interfaces) if interfaces.contains_key(ORG_BLUEZ_ADAPTER1_NAME)
+        );
+        if !available || !self.removed.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        info!("Adapter \"{}\" is available again", self.path);
+        self.add_existing_objects(objects)?;
+        if self.match_tokens.contains_key(&TokenType::DeviceDiscovery) {
+            debug!("Resuming discovery");
+            self.apply_scan_filter()?;
+            self.checked(self.proxy().start_discovery())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn proxy(&self) -> Proxy<'_, &AsyncConnection> {
+        self.calls.proxy(self.path.as_str(), self.timeouts().calls)
+    }
+
+    /// Fails with `AdapterNotFound` once the adapter has been removed from the system, or shut
+    /// down.
+    fn check_available(&self) -> Result<()> {
+        if self.removed.load(Ordering::SeqCst) || self.shut_down.load(Ordering::SeqCst) {
+            Err(Error::AdapterNotFound)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Converts the result of a call to the adapter object, reporting the calls that failed
+    /// because the adapter is gone, and every call made once it is shut down, as
+    /// `AdapterNotFound`, and calls that got no reply in time as `TimedOut`.
+    fn checked<T>(&self, result: std::result::Result<T, dbus::Error>) -> Result<T> {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return Err(Error::AdapterNotFound);
+        }
+        result.map_err(|error| {
+            if self.removed.load(Ordering::SeqCst)
+                || error.name() == Some("org.freedesktop.DBus.Error.UnknownObject")
+            {
+                Error::AdapterNotFound
+            } else if error.name() == Some("org.freedesktop.DBus.Error.NoReply") {
+                Error::TimedOut(self.timeouts().calls)
+            } else {
+                error.into()
+            }
+        })
     }
 
     /// Get the adapter's powered state. This also indicates the appropriate connectable state of the adapter.
     pub fn is_powered(&self) -> Result<bool> {
-        Ok(self.proxy().powered()?)
+        self.checked(self.proxy().powered())
     }
 
     /// Switch an adapter on or off. This will also set the appropriate connectable state of the adapter.
     pub fn set_powered(&self, powered: bool) -> Result<()> {
-        Ok(self.proxy().set_powered(powered)?)
+        self.checked(self.proxy().set_powered(powered))
     }
 
     pub fn name(&self) -> Result<String> {
-        Ok(self.proxy().name()?)
+        self.checked(self.proxy().name())
     }
 
     pub fn address(&self) -> Result<BDAddr> {
-        Ok(self.proxy().address()?.parse()?)
+        Ok(self.checked(self.proxy().address())?.parse()?)
     }
 
     pub fn discoverable(&self) -> Result<bool> {
-        Ok(self.proxy().discoverable()?)
+        self.checked(self.proxy().discoverable())
     }
 
     pub fn set_discoverable(&self, enabled: bool) -> Result<()> {
-        Ok(self.proxy().set_discoverable(enabled)?)
+        self.checked(self.proxy().set_discoverable(enabled))
+    }
+
+    /// How long the adapter stays discoverable once made so, or `None` if it stays discoverable
+    /// until told otherwise.
+    pub fn discoverable_timeout(&self) -> Result<Option<Duration>> {
+        Ok(from_timeout(
+            self.checked(self.proxy().discoverable_timeout())?,
+        ))
+    }
+
+    /// Sets how long the adapter stays discoverable, in whole seconds. `None` keeps it
+    /// discoverable until told otherwise.
+    pub fn set_discoverable_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.checked(self.proxy().set_discoverable_timeout(to_timeout(timeout)))
+    }
+
+    /// The name remote devices see for the adapter. Defaults to the system name.
+    pub fn alias(&self) -> Result<String> {
+        self.checked(self.proxy().alias())
+    }
+
+    /// Changes the name remote devices see for the adapter. An empty alias restores the system
+    /// name.
+    pub fn set_alias(&self, alias: &str) -> Result<()> {
+        self.checked(self.proxy().set_alias(alias.to_string()))
+    }
+
+    /// The Bluetooth class of device of the adapter.
+    pub fn class(&self) -> Result<u32> {
+        self.checked(self.proxy().class())
+    }
+
+    pub fn address_type(&self) -> Result<AddressType> {
+        let address_type = self.checked(self.proxy().address_type())?;
+        AddressType::from_str(&address_type)
+            .ok_or_else(|| Error::Other(format!("Unknown address type \"{}\"", address_type)))
+    }
+
+    /// Whether remote devices can pair with the adapter.
+    pub fn pairable(&self) -> Result<bool> {
+        self.checked(self.proxy().pairable())
+    }
+
+    pub fn set_pairable(&self, enabled: bool) -> Result<()> {
+        self.checked(self.proxy().set_pairable(enabled))
+    }
+
+    /// How long the adapter stays pairable once made so, or `None` if it stays pairable until
+    /// told otherwise.
+    pub fn pairable_timeout(&self) -> Result<Option<Duration>> {
+        Ok(from_timeout(self.checked(self.proxy().pairable_timeout())?))
+    }
+
+    /// Sets how long the adapter stays pairable, in whole seconds. `None` keeps it pairable until
+    /// told otherwise.
+    pub fn set_pairable_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.checked(self.proxy().set_pairable_timeout(to_timeout(timeout)))
+    }
+
+    /// Whether the adapter is scanning, on behalf of this or any other application.
+    pub fn discovering(&self) -> Result<bool> {
+        self.checked(self.proxy().discovering())
+    }
+
+    /// The UUIDs of the services the adapter offers.
+    pub fn uuids(&self) -> Result<Vec<Uuid>> {
+        Ok(to_uuids(&self.checked(self.proxy().uuids())?))
+    }
+
+    /// The vendor, product and version of the adapter, in modalias format, such as
+    /// `usb:v1D6Bp0246d0537`.
+    pub fn modalias(&self) -> Result<String> {
+        self.checked(self.proxy().modalias())
+    }
+
+    /// Returns the discovery filter used when scanning.
+    pub fn scan_filter(&self) -> ScanFilter {
+        self.scan_filter.lock().unwrap().clone()
     }
 
-    fn get_existing_peripherals(&self) -> Result<()> {
-        use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManager;
-        let proxy = self.connection.with_proxy(BLUEZ_DEST, "/", DEFAULT_TIMEOUT);
-        let objects = proxy.get_managed_objects()?;
+    /// Sets the discovery filter used when scanning. If a scan is already running, the filter is
+    /// applied to it straight away, otherwise it will be applied by the next `start_scan()`.
+    pub fn set_scan_filter(&self, filter: ScanFilter) -> Result<()> {
+        // Validate the filter before storing it, so a bad filter can't break the next scan.
+        filter.to_prop_map()?;
+        *self.scan_filter.lock().unwrap() = filter;
+        if self.match_tokens.contains_key(&TokenType::DeviceDiscovery) {
+            self.apply_scan_filter()?;
+        }
+        Ok(())
+    }
 
+    /// Returns how long the adapter and its peripherals wait for BlueZ or a device.
+    pub fn timeouts(&self) -> Timeouts {
+        *self.timeouts.lock().unwrap()
+    }
+
+    /// Sets how long the adapter and its peripherals wait for BlueZ or a device. This applies to
+    /// the operations started from then on, on every peripheral of the adapter.
+    pub fn set_timeouts(&self, timeouts: Timeouts) {
+        *self.timeouts.lock().unwrap() = timeouts;
+    }
+
+    /// Changes the current discovery filter with `f`.
+    fn update_scan_filter<F: FnOnce(&mut ScanFilter)>(&self, f: F) -> Result<()> {
+        let mut filter = self.scan_filter();
+        f(&mut filter);
+        self.set_scan_filter(filter)
+    }
+
+    /// Exposes `services` to remote centrals, by registering them with BlueZ as a GATT
+    /// application. The services stay registered until the returned [GattApplication] is
+    /// unregistered or dropped.
+    ///
+    /// The read and write handlers of the services are called from a thread of the application,
+    /// one request at a time, so a slow handler only holds up the other requests to the
+    /// application. Registering from the thread processing BlueZ events fails with
+    /// `Error::NotSupported`, as BlueZ calls back into the application before replying.
+    pub fn register_application(&self, services: Vec<LocalService>) -> Result<GattApplication> {
+        self.check_available()?;
+        GattApplication::register(
+            self.listener.clone(),
+            self.calls.clone(),
+            &self.path,
+            services,
+        )
+    }
+
+    /// Starts advertising `advertisement`. Several advertisements can be active at once, up to
+    /// [Adapter::supported_advertisement_instances]. Each one is advertised until the returned
+    /// [RegisteredAdvertisement] is unregistered or dropped. As with
+    /// [Adapter::register_application], this fails when called from the thread processing BlueZ
+    /// events.
+    pub fn advertise(&self, advertisement: &Advertisement) -> Result<RegisteredAdvertisement> {
+        self.check_available()?;
+        RegisteredAdvertisement::register(
+            self.listener.clone(),
+            self.calls.clone(),
+            &self.path,
+            advertisement,
+        )
+    }
+
+    /// Registers `agent` to handle the interactions needed to pair with devices, such as entering a
+    /// passkey. Agents are shared by all adapters. It stays registered until the returned
+    /// [AgentRegistration] is unregistered or dropped. As with [Adapter::register_application],
+    /// this fails when called from the thread processing BlueZ events.
+    pub fn register_agent<A: Agent + 'static>(
+        &self,
+        agent: A,
+        capability: AgentCapability,
+    ) -> Result<AgentRegistration> {
+        AgentRegistration::register(self.listener.clone(), self.calls.clone(), agent, capability)
+    }
+
+    /// The number of advertisements currently registered on the adapter, by any application.
+    pub fn active_advertisement_instances(&self) -> Result<u8> {
+        self.checked(self.proxy().active_instances())
+    }
+
+    /// The number of advertisements the adapter can still accept.
+    pub fn supported_advertisement_instances(&self) -> Result<u8> {
+        self.checked(self.proxy().supported_instances())
+    }
+
+    /// Returns every device BlueZ remembers on this adapter, including the ones that haven't been
+    /// discovered since starting. Unlike `peripherals()`, this includes blocked devices.
+    pub fn known_devices(&self) -> Result<Vec<KnownDevice>> {
+        self.check_available()?;
+        let objects = self
+            .calls
+            .proxy("/", self.timeouts().calls)
+            .get_managed_objects()?;
+        let prefix = format!("{}/", self.path);
+        Ok(objects
+            .iter()
+            .filter(|(path, _i)| path.starts_with(&prefix))
+            .filter_map(|(_p, interfaces)| OrgBluezDevice1Properties::from_interfaces(interfaces))
+            .filter_map(KnownDevice::from_properties)
+            .collect())
+    }
+
+    /// Returns the devices that are paired with this adapter.
+    pub fn bonded_devices(&self) -> Result<Vec<KnownDevice>> {
+        let mut devices = self.known_devices()?;
+        devices.retain(|d| d.bond.paired);
+        Ok(devices)
+    }
+
+    /// Returns the devices that are trusted to connect to this adapter without authorization.
+    pub fn trusted_devices(&self) -> Result<Vec<KnownDevice>> {
+        let mut devices = self.known_devices()?;
+        devices.retain(|d| d.bond.trusted);
+        Ok(devices)
+    }
+
+    /// Marks a known device as trusted or untrusted.
+    pub fn set_trusted(&self, address: BDAddr, trusted: bool) -> Result<()> {
+        self.check_available()?;
+        // Not imported, as it has methods with the same names as `OrgBluezAdapter1`.
+        super::bluez_dbus::device::OrgBluezDevice1::set_trusted(
+            &self.device_proxy(address),
+            trusted,
+        )
+        .map_err(device_error)
+    }
+
+    /// Blocks or unblocks a known device. Connections from blocked devices are refused.
+    pub fn set_blocked(&self, address: BDAddr, blocked: bool) -> Result<()> {
+        self.check_available()?;
+        super::bluez_dbus::device::OrgBluezDevice1::set_blocked(
+            &self.device_proxy(address),
+            blocked,
+        )
+        .map_err(device_error)
+    }
+
+    /// Makes BlueZ forget a device, removing its bond and cached GATT database. A
+    /// `DeviceLost` event is emitted once the device is gone.
+    pub fn forget_device(&self, address: BDAddr) -> Result<()> {
+        let path = Path::from(self.device_path(address));
+        self.checked(self.proxy().remove_device(path))
+    }
+
+    fn device_path(&self, address: BDAddr) -> String {
+        format!(
+            "{}/dev_{}",
+            self.path,
+            address.to_string().replace(":", "_")
+        )
+    }
+
+    fn device_proxy(&self, address: BDAddr) -> Proxy<'_, &AsyncConnection> {
+        self.calls
+            .proxy(self.device_path(address), self.timeouts().calls)
+    }
+
+    fn apply_scan_filter(&self) -> Result<()> {
+        let filter = self.scan_filter.lock().unwrap().to_prop_map()?;
+        debug!("Setting discovery filter {:?}", filter);
+        self.checked(self.proxy().set_discovery_filter(filter))
+    }
+
+    fn add_existing_objects(&self, objects: ManagedObjects) -> Result<()> {
         trace!("Fetching already known peripherals from \"{}\"", self.path);
 
         // A lot of out of order objects get returned, and we need to add them in order
         // Let's start off by filtering out objects that belong to this adapter
-        let adapter_objects = objects
-            .iter()
-            .filter(|(p, _i)| p.as_str().unwrap().starts_with(self.path.as_str()));
+        let adapter_objects = objects.iter().filter(|(p, _i)| self.owns(p));
 
         // first, objects that implement org.bluez.Device1,
         adapter_objects
             .clone()
             .filter_map(|(p, i)| i.get(ORG_BLUEZ_DEVICE1_NAME).map(|d| (p, d)))
-            .map(|(path, device)| {
-                Ok(self.add_device(path.as_str().unwrap(), OrgBluezDevice1Properties(device))?)
-            })
-            .collect::<Result<()>>()?;
+            .for_each(|(path, device)| {
+                self.skip_invalid(
+                    path,
+                    self.add_device(path, OrgBluezDevice1Properties(device)),
+                )
+            });
 
         trace!("Fetching known peripheral services");
         // then, objects that implement org.bluez.GattService1 as they depend on devices being known first
         adapter_objects
             .clone()
-            .filter_map(|(p, i)| i.get(ORG_BLUEZ_GATT_SERVICE1_NAME).map(|a| (p, a)))
-            .map(|(path, attribute)| {
-                Ok(self.add_attribute(
-                    path.as_str().unwrap(),
-                    OrgBluezGattCharacteristic1Properties(attribute),
-                )?)
-            })
-            .collect::<Result<()>>()?;
+            .filter_map(|(p, i)| i.get(ORG_BLUEZ_GATT_SERVICE1_NAME).map(|s| (p, s)))
+            .for_each(|(path, service)| {
+                self.skip_invalid(
+                    path,
+                    self.add_service(path, OrgBluezGattService1Properties(service)),
+                )
+            });
 
         trace!("Fetching known peripheral characteristics");
         // then, objects that implement org.bluez.GattCharacteristic1 as they depend on devices being known first
         adapter_objects
             .clone()
             .filter_map(|(p, i)| i.get(ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME).map(|a| (p, a)))
-            .map(|(path, attribute)| {
-                Ok(self.add_attribute(
-                    path.as_str().unwrap(),
-                    OrgBluezGattCharacteristic1Properties(attribute),
-                )?)
-            })
-            .collect::<Result<()>>()?;
-
-        // TODO: Descriptors are nested behind characteristics, and their UUID may be used more than once.
-        //       btleplug will need to refactor it's API before descriptors may be supported.
-        // trace!("Fetching known peripheral descriptors");
-        // adapter_objects
-        //     .clone()
-        //     .filter_map(|(p, i)| i.get(BLUEZ_INTERFACE_DESCRIPTOR).map(|a| (p, a)))
-        //     .map(|(path, descriptor)| {
-        //         Ok(self.add_attribute(path.as_str().unwrap(), descriptor)?)
-        //     })
-        //     .collect::<Result<()>>()?;
+            .for_each(|(path, attribute)| {
+                self.skip_invalid(
+                    path,
+                    self.add_attribute(path, OrgBluezGattCharacteristic1Properties(attribute)),
+                )
+            });
+
+        trace!("Fetching known peripheral descriptors");
+        // and finally, descriptors which are nested behind characteristics.
+        adapter_objects
+            .clone()
+            .filter_map(|(p, i)| i.get(ORG_BLUEZ_GATT_DESCRIPTOR1_NAME).map(|d| (p, d)))
+            .for_each(|(path, descriptor)| {
+                self.skip_invalid(
+                    path,
+                    self.add_descriptor(path, OrgBluezGattDescriptor1Properties(descriptor)),
+                )
+            });
 
         Ok(())
     }
 
-    fn remove_device(&self, path: &str) -> Result<()> {
-        if let Some(address) = path
-            .strip_prefix(format!("{}/dev_", self.path).as_str())
-            .and_then(|p| p[..17].replace("_", ":").parse::<BDAddr>().ok())
-        {
-            debug!("Removing device \"{:?}\"", address);
-            let listener = self.listener.lock();
-            debug!("Got listener lock");
-            if let Some(peripheral) = self.manager.peripheral(address) {
-                peripheral.stop_listening(&*listener).unwrap()
-            } else {
-                error!("Device \"{:?}\" not found!", address);
-            }
+    /// Whether an object belongs to this adapter, rather than to another one.
+    fn owns(&self, path: &str) -> bool {
+        path.starts_with(&format!("{}/", self.path))
+    }
 
-            self.manager.emit(CentralEvent::DeviceLost(address));
+    /// Adds a service, characteristic or descriptor BlueZ announced on one of our devices.
+    fn add_gatt_object(&self, path: &str, interfaces: &HashMap<String, PropMap>) -> Result<()> {
+        if let Some(service) = OrgBluezGattService1Properties::from_interfaces(interfaces) {
+            self.add_service(path, service)
+        } else if let Some(characteristic) =
+            OrgBluezGattCharacteristic1Properties::from_interfaces(interfaces)
+        {
+            self.add_attribute(path, characteristic)
+        } else if let Some(descriptor) =
+            OrgBluezGattDescriptor1Properties::from_interfaces(interfaces)
+        {
+            self.add_descriptor(path, descriptor)
         } else {
-            error!("Could not parse path {:?}", path)
+            Ok(())
         }
+    }
 
-        Ok(())
+    /// Removes a service, characteristic or descriptor BlueZ no longer exports, such as after the
+    /// device indicated that its services changed.
+    fn remove_attribute(&self, path: &str) -> Result<()> {
+        let address = self.device_address(path)?;
+        match self.manager.peripheral(address) {
+            Some(peripheral) => peripheral.remove_attribute(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Reports an object BlueZ announced that couldn't be added, which is skipped so it doesn't
+    /// keep the others from being added.
+    fn skip_invalid(&self, path: &str, added: Result<()>) {
+        if let Err(e) = added {
+            self.manager.emit_error(self.device_address(path).ok(), e);
+        }
+    }
+
+    /// Returns the address of the device an object belongs to, such as `0A:0B:0C:0D:0E:0F` for
+    /// "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F/service0025".
+    fn device_address(&self, path: &str) -> Result<BDAddr> {
+        let device_id = path
+            .strip_prefix(format!("{}/dev_", self.path).as_str())
+            .and_then(|p| p.get(..17))
+            .ok_or_else(|| Error::Other(format!("Invalid DBus path for device \"{}\"", path)))?;
+        Ok(device_id.replace("_", ":").parse()?)
+    }
+
+    fn remove_device(&self, path: &str) -> Result<()> {
+        let address = self.device_address(path)?;
+        debug!("Removing device \"{:?}\"", address);
+        let stopped = match self.manager.peripheral(address) {
+            Some(peripheral) => peripheral.stop_listening(),
+            None => {
+                debug!("Device \"{:?}\" was never discovered", address);
+                Ok(())
+            }
+        };
+        self.manager.emit(CentralEvent::DeviceLost(address));
+        stopped
     }
 
     /// Helper function to add a org.bluez.Device1 object to the adapter manager
@@ -326,7 +823,13 @@ impl Adapter {
                 return Ok(());
             }
             let peripheral = self.manager.peripheral(address).unwrap_or_else(|| {
-                Peripheral::new(self.manager.clone(), self.connection.clone(), path, address)
+                Peripheral::new(
+                    self.manager.clone(),
+                    self.calls.clone(),
+                    path,
+                    address,
+                    self.timeouts.clone(),
+                )
             });
             peripheral.update_properties(device);
             if !self.manager.has_peripheral(&address) {
@@ -334,16 +837,11 @@ impl Adapter {
                     "Adding discovered peripheral \"{}\" on \"{}\"",
                     address, self.path
                 );
-                {
-                    let listener = self.listener.lock();
-                    peripheral.listen(&listener)?;
-                    // TODO: cal peripheral.stop_listening(...) when the peripheral is removed.
-                }
+                peripheral.listen()?;
                 self.manager.add_peripheral(address, peripheral);
                 self.manager.emit(CentralEvent::DeviceDiscovered(address));
             } else {
                 info!("Updating peripheral \"{}\"", address);
-                self.manager.update_peripheral(address, peripheral);
                 self.manager.emit(CentralEvent::DeviceUpdated(address));
             }
         } else {
@@ -353,129 +851,166 @@ impl Adapter {
         Ok(())
     }
 
+    fn add_service(&self, path: &str, service: OrgBluezGattService1Properties) -> Result<()> {
+        let device_id = self.device_address(path)?;
+        if let Some(device) = self.manager.peripheral(device_id) {
+            trace!("Adding service \"{}\" on \"{:?}\"", path, device_id);
+            let uuid: Uuid = service
+                .uuid()
+                .ok_or_else(|| Error::Other("Service has no UUID".to_string()))?
+                .parse()?;
+            device.add_service(
+                path,
+                uuid,
+                service.primary().unwrap_or(true),
+                service.includes().map_or(&[], |i| i.as_slice()),
+            )?;
+        }
+
+        Ok(())
+    }
+
     fn add_attribute(
         &self,
         path: &str,
         characteristic: OrgBluezGattCharacteristic1Properties,
     ) -> Result<()> {
-        // Convert "/org/bluez/hciXX/dev_XX_XX_XX_XX_XX_XX/serviceXX" into "XX:XX:XX:XX:XX:XX"
-        if let Some(device_id) = path.strip_prefix(format!("{}/dev_", self.path).as_str()) {
-            let device_id: BDAddr = device_id[..17].replace("_", ":").parse()?;
-
-            if let Some(device) = self.manager.peripheral(device_id) {
-                trace!("Adding characteristic \"{}\" on \"{:?}\"", path, device_id);
-                let uuid: Uuid = characteristic.uuid().unwrap().parse()?;
-                let flags = if let Some(flags) = characteristic.flags() {
-                    flags.iter().map(|s| s.parse::<CharPropFlags>()).fold(
-                        Ok(CharPropFlags::new()),
-                        |a, f| {
-                            if f.is_ok() {
-                                Ok(f.unwrap() | a.unwrap())
-                            } else {
-                                f
-                            }
-                        },
-                    )?
-                } else {
-                    CharPropFlags::new()
-                };
+        let device_id = self.device_address(path)?;
+        if let Some(device) = self.manager.peripheral(device_id) {
+            trace!("Adding characteristic \"{}\" on \"{:?}\"", path, device_id);
+            let uuid: Uuid = characteristic
+                .uuid()
+                .ok_or_else(|| Error::Other("Characteristic has no UUID".to_string()))?
+                .parse()?;
+            let flags = if let Some(flags) = characteristic.flags() {
+                flags.iter().try_fold(CharPropFlags::new(), |flags, flag| {
+                    flag.parse::<CharPropFlags>().map(|flag| flags | flag)
+                })?
+            } else {
+                CharPropFlags::new()
+            };
 
-                device.add_attribute(path, uuid, flags)?;
+            device.add_attribute(path, uuid, flags)?;
+            if let Some(notifying) = characteristic.notifying() {
+                device.set_notifying(path, notifying)?;
             }
-        } else {
-            return Err(Error::Other(
-                "Invalid DBus path for characteristic".to_string(),
-            ));
         }
 
         Ok(())
     }
-}
 
-impl Drop for Adapter {
-    /// Cleans up the thread started in Adapter::new()
-    fn drop(&mut self) {
-        let mut thread_handle = self.thread_handle.lock().unwrap();
-        let handle = std::mem::replace(&mut *thread_handle, None);
-        if let Some(handle) = handle {
-            let (cvar, should_stop) = &*self.should_stop;
-            // mimicing a cancelation token though a ConVar<bool>
-            *should_stop.lock().unwrap() = true;
-            cvar.notify_all();
-
-            handle.join().unwrap();
+    fn add_descriptor(
+        &self,
+        path: &str,
+        descriptor: OrgBluezGattDescriptor1Properties,
+    ) -> Result<()> {
+        let device_id = self.device_address(path)?;
+        if let Some(device) = self.manager.peripheral(device_id) {
+            trace!("Adding descriptor \"{}\" on \"{:?}\"", path, device_id);
+            let uuid: Uuid = descriptor
+                .uuid()
+                .ok_or_else(|| Error::Other("Descriptor has no UUID".to_string()))?
+                .parse()?;
+            device.add_attribute(path, uuid, CharPropFlags::empty())?;
         }
+
+        Ok(())
     }
 }
 
-impl Central<Peripheral> for Adapter {
-    fn event_receiver(&self) -> Option<Receiver<CentralEvent>> {
-        self.manager.event_receiver()
+/// Reports calls made to the object of a device BlueZ doesn't know as `DeviceNotFound`.
+fn device_error(error: dbus::Error) -> Error {
+    match error.name() {
+        Some("org.freedesktop.DBus.Error.UnknownObject") => Error::DeviceNotFound,
+        _ => error.into(),
+    }
+}
+
+#[async_trait]
+impl AsyncCentral<Peripheral> for Adapter {
+    fn subscribe_events(&self, subscription: EventSubscription) -> EventReceiver {
+        self.manager.subscribe_events(subscription)
     }
 
-    fn filter_duplicates(&self, _enabled: bool) {
-        todo!()
-        // self.filter_duplicates
-        // .clone()
-        // .store(enabled, Ordering::Relaxed);
+    fn filter_duplicates(&self, enabled: bool) -> Result<()> {
+        self.update_scan_filter(|filter| filter.duplicate_data = Some(!enabled))
     }
 
-    fn start_scan<'a>(&'a self) -> Result<()> {
+    async fn start_scan(&self) -> Result<()> {
         use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesAdded as InterfacesAdded;
 
+        self.check_available()?;
         // remove the previous token if it's still awkwardly overstaying their welcome...
         if let Some((_t, token)) = self.match_tokens.remove(&TokenType::DeviceDiscovery) {
             warn!("Removing previous match token");
-            self.listener.lock().remove_match(token)?;
+            self.calls.remove_match(token)?;
         }
 
         // TODO: Should this be invoked earlier? Do we need to rely on the application to called 'start_scan()' before fetching peripherals that may already be known to bluez?
-        self.get_existing_peripherals()?;
+        let (objects,): (ManagedObjects,) = self
+            .calls
+            .call(
+                "/",
+                "org.freedesktop.DBus.ObjectManager",
+                "GetManagedObjects",
+                (),
+                self.timeouts().calls,
+            )
+            .await?;
+        self.add_existing_objects(objects)?;
 
         trace!("Starting discovery listener");
         {
             let mut discovered_rule = InterfacesAdded::match_rule(None, None);
             discovered_rule.path = Some(Path::from("/"));
 
-            let adapter = self.clone();
+            let adapter = self.handler_clone();
             self.match_tokens.insert(
                 TokenType::DeviceDiscovery,
-                self.listener.lock().add_match(
-                    discovered_rule,
-                    move |args: InterfacesAdded, _c, _msg| {
+                self.calls
+                    .add_match(discovered_rule, move |args: InterfacesAdded, _c, _msg| {
                         trace!("Received 'InterfacesAdded' signal");
                         let path = args.object;
 
+                        // GATT objects are added whether or not we're scanning, see `setup`.
                         if let Some(device) =
                             OrgBluezDevice1Properties::from_interfaces(&args.interfaces)
                         {
-                            adapter.add_device(&path, device).unwrap();
-                        } else if let Some(service) =
-                            args.interfaces.get(ORG_BLUEZ_GATT_SERVICE1_NAME)
-                        {
-                            adapter
-                                .add_attribute(
-                                    &path,
-                                    OrgBluezGattCharacteristic1Properties(service),
-                                )
-                                .unwrap();
-                        } else if let Some(characteristic) =
-                            OrgBluezGattCharacteristic1Properties::from_interfaces(&args.interfaces)
-                        {
-                            adapter.add_attribute(&path, characteristic).unwrap();
+                            if adapter.owns(&path) {
+                                adapter.skip_invalid(&path, adapter.add_device(&path, device));
+                            }
                         }
 
                         return true;
-                    },
-                )?,
+                    })?,
             );
         }
 
-        if let Err(error) = self.proxy().start_discovery() {
+        let set_filter = {
+            let filter = self.scan_filter.lock().unwrap().to_prop_map()?;
+            debug!("Setting discovery filter {:?}", filter);
+            self.calls.call::<_, ()>(
+                &self.path,
+                ORG_BLUEZ_ADAPTER1_NAME,
+                "SetDiscoveryFilter",
+                (filter,),
+                self.timeouts().calls,
+            )
+        };
+        self.checked(set_filter.await)?;
+
+        let start_discovery = self.calls.call::<_, ()>(
+            &self.path,
+            ORG_BLUEZ_ADAPTER1_NAME,
+            "StartDiscovery",
+            (),
+            self.timeouts().calls,
+        );
+        if let Err(error) = start_discovery.await {
             match error.name() {
                 // Don't error if BlueZ has already started scanning.
                 Some("org.bluez.Error.InProgress") => Ok(()),
-                _ => Err(error)?,
+                _ => self.checked(Err(error)),
             }
         } else {
             debug!("Starting discovery");
@@ -483,17 +1018,25 @@ impl Central<Peripheral> for Adapter {
         }
     }
 
-    fn stop_scan(&self) -> Result<()> {
+    async fn stop_scan(&self) -> Result<()> {
+        self.check_available()?;
         if let Some((_t, token)) = self.match_tokens.remove(&TokenType::DeviceDiscovery) {
             trace!("Stopping discovery listener");
-            self.listener.lock().remove_match(token)?;
+            self.calls.remove_match(token)?;
         }
 
-        if let Err(error) = self.proxy().stop_discovery() {
+        let stop_discovery = self.calls.call::<_, ()>(
+            &self.path,
+            ORG_BLUEZ_ADAPTER1_NAME,
+            "StopDiscovery",
+            (),
+            self.timeouts().calls,
+        );
+        if let Err(error) = stop_discovery.await {
             match error.name() {
                 // Don't error if BlueZ has already stopped scanning.
                 Some("org.bluez.Error.InProgress") => Ok(()),
-                _ => Err(error)?,
+                _ => self.checked(Err(error)),
             }
         } else {
             debug!("Stopping discovery");
@@ -509,7 +1052,1166 @@ impl Central<Peripheral> for Adapter {
         self.manager.peripheral(address)
     }
 
-    fn active(&self, _enabled: bool) {
-        todo!()
+    async fn adapter_info(&self) -> Result<AdapterInfo> {
+        let get_all = self.calls.call(
+            &self.path,
+            "org.freedesktop.DBus.Properties",
+            "GetAll",
+            (ORG_BLUEZ_ADAPTER1_NAME,),
+            self.timeouts().calls,
+        );
+        let (properties,): (PropMap,) = self.checked(get_all.await)?;
+        Ok(adapter_info(OrgBluezAdapter1Properties(&properties)))
+    }
+
+    fn active(&self, enabled: bool) -> Result<()> {
+        self.update_scan_filter(|filter| filter.passive = !enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{
+            bleuuid::uuid_from_u16, Characteristic, Descriptor, IncludedService, Peripheral as _,
+        },
+        bluez::{
+            fake_bluez::{FakeBluez, PrivateBus},
+            manager::Manager,
+        },
+    };
+    use dbus::arg::{prop_cast, PropMap};
+    use futures::executor::block_on;
+
+    fn setup() -> (PrivateBus, FakeBluez, Adapter) {
+        let bus = PrivateBus::start();
+        let bluez = FakeBluez::new(&bus);
+        bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        let manager = Manager::with_bus_address(bus.address()).unwrap();
+        let adapter = manager.adapters().unwrap().remove(0);
+        (bus, bluez, adapter)
+    }
+
+    fn last_filter(bluez: &FakeBluez) -> PropMap {
+        bluez
+            .calls("SetDiscoveryFilter")
+            .last()
+            .expect("SetDiscoveryFilter was never called")
+            .read1()
+            .unwrap()
+    }
+
+    #[test]
+    fn start_scan_sets_discovery_filter() {
+        let (_bus, bluez, adapter) = setup();
+        adapter
+            .set_scan_filter(ScanFilter {
+                services: vec![uuid_from_u16(0x180D)],
+                rssi: Some(-60),
+                transport: Some(Transport::Le),
+                ..Default::default()
+            })
+            .unwrap();
+        // Nothing is sent to BlueZ until a scan is started.
+        assert!(bluez.calls("SetDiscoveryFilter").is_empty());
+
+        block_on(adapter.start_scan()).unwrap();
+        let filter = last_filter(&bluez);
+        assert_eq!(prop_cast::<i16>(&filter, "RSSI"), Some(&-60));
+        assert_eq!(prop_cast::<String>(&filter, "Transport").unwrap(), "le");
+        assert_eq!(
+            bluez
+                .call_names()
+                .into_iter()
+                .filter(|c| c == "SetDiscoveryFilter" || c == "StartDiscovery")
+                .collect::<Vec<_>>(),
+            vec!["SetDiscoveryFilter", "StartDiscovery"]
+        );
+    }
+
+    #[test]
+    fn filter_duplicates_updates_running_scan() {
+        let (_bus, bluez, adapter) = setup();
+        block_on(adapter.start_scan()).unwrap();
+        assert!(last_filter(&bluez).is_empty());
+
+        adapter.filter_duplicates(false).unwrap();
+        assert_eq!(
+            prop_cast::<bool>(&last_filter(&bluez), "DuplicateData"),
+            Some(&true)
+        );
+        adapter.filter_duplicates(true).unwrap();
+        assert_eq!(
+            prop_cast::<bool>(&last_filter(&bluez), "DuplicateData"),
+            Some(&false)
+        );
+        assert_eq!(adapter.scan_filter().duplicate_data, Some(false));
+
+        // BlueZ can't scan passively, which leaves the filter as it was.
+        assert!(matches!(adapter.active(false), Err(Error::NotSupported(_))));
+        assert!(!adapter.scan_filter().passive);
+        adapter.active(true).unwrap();
+
+        // Filters BlueZ rejects are reported.
+        bluez.on_method("org.bluez.Adapter1", "SetDiscoveryFilter", |msg, _bluez| {
+            msg.error(
+                &"org.bluez.Error.NotReady".into(),
+                &std::ffi::CString::new("Resource Not Ready").unwrap(),
+            )
+        });
+        assert!(adapter.filter_duplicates(false).is_err());
+    }
+
+    #[test]
+    fn descriptors_are_loaded_and_accessed() {
+        let (_bus, bluez, adapter) = setup();
+        let device = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F";
+        let characteristic_path = format!("{}/service0025/char0026", device);
+        let descriptor = format!("{}/desc0028", characteristic_path);
+        bluez.add_object(
+            device,
+            "org.bluez.Device1",
+            vec![("Address", Box::new("0A:0B:0C:0D:0E:0F".to_string()))],
+        );
+        bluez.add_object(
+            &format!("{}/service0025", device),
+            "org.bluez.GattService1",
+            vec![("UUID", Box::new(uuid_from_u16(0x180F).to_string()))],
+        );
+        bluez.add_object(
+            &characteristic_path,
+            "org.bluez.GattCharacteristic1",
+            vec![
+                ("UUID", Box::new(uuid_from_u16(0x2A19).to_string())),
+                ("Flags", Box::new(vec!["read".to_string()])),
+            ],
+        );
+        bluez.add_object(
+            &descriptor,
+            "org.bluez.GattDescriptor1",
+            vec![("UUID", Box::new(uuid_from_u16(0x2901).to_string()))],
+        );
+        bluez.on_method("org.bluez.GattDescriptor1", "ReadValue", |msg, _| {
+            msg.method_return().append1(b"Battery".to_vec())
+        });
+        block_on(adapter.start_scan()).unwrap();
+
+        let peripheral = adapter
+            .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+            .unwrap();
+        let characteristic = Characteristic {
+            start_handle: 0x26,
+            end_handle: 0x28,
+            value_handle: 0x26,
+            uuid: uuid_from_u16(0x2A19),
+            properties: CharPropFlags::READ,
+        };
+        let descriptors = peripheral.descriptors(&characteristic);
+        assert_eq!(
+            descriptors.into_iter().collect::<Vec<_>>(),
+            vec![Descriptor {
+                handle: 0x28,
+                uuid: uuid_from_u16(0x2901),
+                characteristic_uuid: uuid_from_u16(0x2A19),
+            }]
+        );
+
+        let user_description = Descriptor {
+            handle: 0x28,
+            uuid: uuid_from_u16(0x2901),
+            characteristic_uuid: uuid_from_u16(0x2A19),
+        };
+        assert_eq!(
+            peripheral.read_descriptor(&user_description).unwrap(),
+            b"Battery".to_vec()
+        );
+        // Reading by type finds the descriptor within the range of the characteristic.
+        assert_eq!(
+            peripheral
+                .read_by_type(&characteristic, uuid_from_u16(0x2901))
+                .unwrap(),
+            b"Battery".to_vec()
+        );
+        peripheral
+            .write_descriptor(&user_description, &[0x01, 0x02])
+            .unwrap();
+        let write = bluez.calls("WriteValue").remove(0);
+        assert_eq!(write.path().unwrap().to_string(), descriptor);
+        assert_eq!(write.read1::<Vec<u8>>().unwrap(), vec![0x01, 0x02]);
+
+        // BlueZ updates the value of a descriptor when it is read, which isn't a notification.
+        let notifications = peripheral.notifications(&characteristic);
+        for (path, value) in &[
+            (descriptor.clone(), b"Battery".to_vec()),
+            (characteristic_path, vec![80]),
+        ] {
+            bluez.emit_properties_changed(
+                path,
+                "org.bluez.GattCharacteristic1",
+                vec![("Value", Box::new(value.clone()))],
+            );
+        }
+        let notification = notifications.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(notification.handle, Some(0x26));
+        assert_eq!(*notification.value, [80]);
+
+        // Descriptors that aren't known can't be accessed.
+        assert!(peripheral
+            .read_descriptor(&Descriptor {
+                handle: 0x30,
+                ..user_description
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn services_group_characteristics() {
+        let (_bus, bluez, adapter) = setup();
+        let device = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F";
+        bluez.add_object(
+            device,
+            "org.bluez.Device1",
+            vec![
+                ("Address", Box::new("0A:0B:0C:0D:0E:0F".to_string())),
+                ("Connected", Box::new(true)),
+                ("ServicesResolved", Box::new(true)),
+            ],
+        );
+        // Two services with the same characteristic, the second one including the second of two
+        // secondary services of the same type.
+        for (service, uuid, primary, includes) in vec![
+            ("service0010", 0x1801, true, vec![]),
+            (
+                "service0020",
+                0x1801,
+                true,
+                vec![Path::from(format!("{}/service0040", device))],
+            ),
+            ("service0030", 0x1800, false, vec![]),
+            ("service0040", 0x1800, false, vec![]),
+        ] {
+            bluez.add_object(
+                &format!("{}/{}", device, service),
+                "org.bluez.GattService1",
+                vec![
+                    ("UUID", Box::new(uuid_from_u16(uuid).to_string())),
+                    ("Primary", Box::new(primary)),
+                    ("Includes", Box::new(includes)),
+                ],
+            );
+        }
+        for characteristic in &["service0010/char0011", "service0020/char0021"] {
+            bluez.add_object(
+                &format!("{}/{}", device, characteristic),
+                "org.bluez.GattCharacteristic1",
+                vec![
+                    ("UUID", Box::new(uuid_from_u16(0x2A00).to_string())),
+                    ("Flags", Box::new(vec!["read".to_string()])),
+                ],
+            );
+        }
+        block_on(adapter.start_scan()).unwrap();
+
+        let peripheral = adapter
+            .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+            .unwrap();
+        let services = peripheral.services().into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            services
+                .iter()
+                .map(|s| (s.handle, s.primary, s.characteristics.len()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x10, true, 1),
+                (0x20, true, 1),
+                (0x30, false, 0),
+                (0x40, false, 0)
+            ]
+        );
+        assert_eq!(
+            services[1].included_services,
+            vec![IncludedService {
+                handle: 0x40,
+                uuid: uuid_from_u16(0x1800)
+            }]
+        );
+        // Service declarations are not reported as characteristics.
+        assert_eq!(peripheral.characteristics().len(), 2);
+
+        let characteristic = peripheral
+            .characteristic(uuid_from_u16(0x1801), uuid_from_u16(0x2A00))
+            .unwrap();
+        assert_eq!(characteristic.value_handle, 0x11);
+        assert!(peripheral
+            .characteristic(uuid_from_u16(0x1801), uuid_from_u16(0x2A01))
+            .is_none());
+        assert_eq!(
+            peripheral.service(uuid_from_u16(0x1800)).unwrap().handle,
+            0x30
+        );
+    }
+
+    #[test]
+    fn rssi_and_tx_power_are_kept_apart() {
+        let (_bus, bluez, adapter) = setup();
+        let device = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F";
+        bluez.add_object(
+            device,
+            "org.bluez.Device1",
+            vec![
+                ("Address", Box::new("0A:0B:0C:0D:0E:0F".to_string())),
+                ("RSSI", Box::new(-60i16)),
+                ("TxPower", Box::new(4i16)),
+            ],
+        );
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter
+            .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+            .unwrap();
+        assert_eq!(peripheral.properties().rssi, Some(-60));
+        assert_eq!(peripheral.properties().tx_power_level, Some(4));
+
+        bluez.emit_properties_changed(
+            device,
+            "org.bluez.Device1",
+            vec![("RSSI", Box::new(-70i16))],
+        );
+        FakeBluez::wait_for(|| peripheral.properties().rssi == Some(-70));
+
+        let properties = peripheral.properties();
+        assert_eq!(properties.tx_power_level, Some(4));
+        assert_eq!(properties.signal.last, Some(-70));
+        assert_eq!(properties.signal.min, Some(-70));
+        assert_eq!(properties.signal.max, Some(-60));
+        assert_eq!(properties.signal.count, 2);
+    }
+
+    #[test]
+    fn properties_are_readable_while_a_subscriber_is_full() {
+        use crate::api::{CentralEventKind, EventFilter, OverflowPolicy};
+
+        let (_bus, bluez, adapter) = setup();
+        let device = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F";
+        bluez.add_object(
+            device,
+            "org.bluez.Device1",
+            vec![("Address", Box::new("0A:0B:0C:0D:0E:0F".to_string()))],
+        );
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter
+            .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+            .unwrap();
+        let events = adapter.subscribe_events(
+            EventSubscription::default()
+                .filter(EventFilter::default().kind(CentralEventKind::DeviceUpdated))
+                .capacity(1)
+                .overflow(OverflowPolicy::Block),
+        );
+
+        // The second update waits for the subscriber, which doesn't keep the first one from
+        // being read, nor the state of the device.
+        for rssi in &[-60i16, -70] {
+            bluez.emit_properties_changed(
+                device,
+                "org.bluez.Device1",
+                vec![("RSSI", Box::new(*rssi))],
+            );
+        }
+        FakeBluez::wait_for(|| peripheral.properties().rssi == Some(-70));
+        assert!(!peripheral.is_connected());
+
+        for _ in 0..2 {
+            assert!(matches!(
+                events.recv_timeout(Duration::from_secs(5)).unwrap(),
+                CentralEvent::DeviceUpdated(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn adapter_properties_are_exposed() {
+        let (_bus, bluez, adapter) = setup();
+        bluez.add_object(
+            "/org/bluez/hci0",
+            "org.bluez.Adapter1",
+            vec![
+                ("AddressType", Box::new("public".to_string())),
+                ("Class", Box::new(0x6c010cu32)),
+                ("Pairable", Box::new(true)),
+                ("PairableTimeout", Box::new(0u32)),
+                ("DiscoverableTimeout", Box::new(180u32)),
+                ("UUIDs", Box::new(vec![uuid_from_u16(0x1800).to_string()])),
+                ("Modalias", Box::new("usb:v1D6Bp0246d0537".to_string())),
+            ],
+        );
+
+        let info = block_on(adapter.adapter_info()).unwrap();
+        assert_eq!(info.address, Some("00:11:22:33:44:55".parse().unwrap()));
+        assert_eq!(info.address_type, Some(AddressType::Public));
+        assert_eq!(info.name.as_deref(), Some("hci0"));
+        assert_eq!(info.class, Some(0x6c010c));
+        assert_eq!(info.powered, Some(true));
+        assert_eq!(info.pairable, Some(true));
+        assert_eq!(info.pairable_timeout, None);
+        assert_eq!(info.discoverable_timeout, Some(Duration::from_secs(180)));
+        assert_eq!(info.discovering, Some(false));
+        assert_eq!(info.services, vec![uuid_from_u16(0x1800)]);
+        assert_eq!(info.modalias.as_deref(), Some("usb:v1D6Bp0246d0537"));
+
+        adapter.set_alias("Kitchen").unwrap();
+        assert_eq!(adapter.alias().unwrap(), "Kitchen");
+        adapter
+            .set_pairable_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+        assert_eq!(
+            adapter.pairable_timeout().unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        adapter.set_discoverable_timeout(None).unwrap();
+        assert_eq!(adapter.discoverable_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn adapter_changes_are_emitted() {
+        use crate::api::{AdapterProperty, CentralEventKind, EventFilter};
+
+        let (_bus, bluez, adapter) = setup();
+        let events = adapter.subscribe_events(EventSubscription {
+            filter: EventFilter::default().kind(CentralEventKind::AdapterUpdated),
+            ..Default::default()
+        });
+
+        bluez.emit_properties_changed(
+            "/org/bluez/hci0",
+            "org.bluez.Adapter1",
+            vec![
+                ("Powered", Box::new(false)),
+                ("Discovering", Box::new(true)),
+            ],
+        );
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                CentralEvent::AdapterUpdated(property) => received.push(property),
+                event => panic!("Unexpected event {:?}", event),
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                AdapterProperty::Powered(false),
+                AdapterProperty::Discovering(true)
+            ]
+        );
+    }
+
+    #[test]
+    fn bonds_are_listed_and_managed() {
+        let (_bus, bluez, adapter) = setup();
+        bluez.add_object(
+            "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F",
+            "org.bluez.Device1",
+            vec![
+                ("Address", Box::new("0A:0B:0C:0D:0E:0F".to_string())),
+                ("Alias", Box::new("Sensor".to_string())),
+                ("Paired", Box::new(true)),
+                ("Trusted", Box::new(false)),
+            ],
+        );
+        bluez.add_object(
+            "/org/bluez/hci0/dev_01_02_03_04_05_06",
+            "org.bluez.Device1",
+            vec![
+                ("Address", Box::new("01:02:03:04:05:06".to_string())),
+                ("Blocked", Box::new(true)),
+            ],
+        );
+        let sensor: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+
+        // Known devices don't need to be discovered first.
+        assert_eq!(adapter.known_devices().unwrap().len(), 2);
+        let bonded = adapter.bonded_devices().unwrap();
+        assert_eq!(bonded.len(), 1);
+        assert_eq!(bonded[0].address, sensor);
+        assert_eq!(bonded[0].name.as_deref(), Some("Sensor"));
+        assert!(adapter.trusted_devices().unwrap().is_empty());
+
+        adapter.set_trusted(sensor, true).unwrap();
+        assert_eq!(adapter.trusted_devices().unwrap()[0].address, sensor);
+
+        adapter.forget_device(sensor).unwrap();
+        let removed: Path = bluez.calls("RemoveDevice")[0].read1().unwrap();
+        assert_eq!(&*removed, "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F");
+    }
+
+    #[test]
+    fn bond_changes_are_emitted() {
+        use crate::api::{BondState, CentralEventKind, EventFilter};
+
+        let (_bus, bluez, adapter) = setup();
+        let device = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F";
+        bluez.add_object(
+            device,
+            "org.bluez.Device1",
+            vec![
+                ("Address", Box::new("0A:0B:0C:0D:0E:0F".to_string())),
+                ("Paired", Box::new(false)),
+            ],
+        );
+        let events = adapter.subscribe_events(EventSubscription {
+            filter: EventFilter::default().kind(CentralEventKind::DeviceBondChanged),
+            ..Default::default()
+        });
+        block_on(adapter.start_scan()).unwrap();
+
+        bluez.emit_properties_changed(
+            device,
+            "org.bluez.Device1",
+            vec![("Paired", Box::new(true)), ("Trusted", Box::new(true))],
+        );
+
+        let bond = BondState {
+            paired: true,
+            trusted: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::DeviceBondChanged { bond: b, .. } if b == bond
+        ));
+        let peripheral = adapter
+            .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+            .unwrap();
+        assert_eq!(peripheral.bond_state(), bond);
+    }
+
+    /// Adds a connectable device with one readable and notifying characteristic, whose value is
+    /// also sent as a notification the first time it is read.
+    fn add_connectable_device(bluez: &FakeBluez) -> (String, Characteristic) {
+        let device = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F".to_string();
+        let characteristic = format!("{}/service0010/char0011", device);
+        bluez.add_object(
+            &device,
+            "org.bluez.Device1",
+            vec![("Address", Box::new("0A:0B:0C:0D:0E:0F".to_string()))],
+        );
+        bluez.add_object(
+            &characteristic,
+            "org.bluez.GattCharacteristic1",
+            vec![
+                ("UUID", Box::new(uuid_from_u16(0x2A37).to_string())),
+                (
+                    "Flags",
+                    Box::new(vec!["read".to_string(), "notify".to_string()]),
+                ),
+            ],
+        );
+        let connected = device.clone();
+        bluez.on_method("org.bluez.Device1", "Connect", move |msg, bluez| {
+            bluez.emit_properties_changed(
+                &connected,
+                "org.bluez.Device1",
+                vec![("Connected", Box::new(true))],
+            );
+            msg.method_return()
+        });
+        let notified = characteristic.clone();
+        let mut first_read = true;
+        bluez.on_method(
+            "org.bluez.GattCharacteristic1",
+            "ReadValue",
+            move |msg, bluez| {
+                if !std::mem::replace(&mut first_read, false) {
+                    return msg.method_return().append1(vec![42u8]);
+                }
+                bluez.emit_properties_changed(
+                    &notified,
+                    "org.bluez.GattCharacteristic1",
+                    vec![("Value", Box::new(vec![42u8]))],
+                );
+                msg.method_return().append1(vec![42u8])
+            },
+        );
+        (
+            device,
+            Characteristic {
+                start_handle: 0x11,
+                end_handle: 0x11,
+                value_handle: 0x11,
+                uuid: uuid_from_u16(0x2A37),
+                properties: CharPropFlags::READ | CharPropFlags::NOTIFY,
+            },
+        )
+    }
+
+    #[test]
+    fn async_operations_resolve_through_the_dispatcher() {
+        use crate::api::AsyncPeripheral;
+
+        let (_bus, bluez, adapter) = setup();
+        let (_device, characteristic) = add_connectable_device(&bluez);
+        block_on(async {
+            adapter.start_scan().await.unwrap();
+            let peripheral = adapter
+                .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+                .unwrap();
+            // The blocking `Peripheral` trait is also in scope here.
+            AsyncPeripheral::connect(&peripheral).await.unwrap();
+            assert!(AsyncPeripheral::is_connected(&peripheral));
+            assert_eq!(
+                AsyncPeripheral::read(&peripheral, &characteristic)
+                    .await
+                    .unwrap(),
+                vec![42]
+            );
+            // Blocking calls don't need an executor, so they work within one.
+            assert_eq!(
+                crate::api::Peripheral::read(&peripheral, &characteristic).unwrap(),
+                vec![42]
+            );
+            adapter.stop_scan().await.unwrap();
+        });
+        assert_eq!(bluez.calls("Connect").len(), 1);
+        assert_eq!(bluez.calls("StopDiscovery").len(), 1);
+    }
+
+    #[test]
+    fn blocking_calls_work_from_handlers() {
+        use std::sync::mpsc::channel;
+
+        let (_bus, bluez, adapter) = setup();
+        let (_device, characteristic) = add_connectable_device(&bluez);
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter
+            .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+            .unwrap();
+        peripheral.connect().unwrap();
+
+        // Notification handlers run on a thread of their own, so they can wait for replies from
+        // the thread that dispatches them.
+        let (sender, receiver) = channel();
+        let sender = Mutex::new(sender);
+        let handler_peripheral = peripheral.clone();
+        let handler_characteristic = characteristic.clone();
+        peripheral.on_notification(Box::new(move |_notification| {
+            let value = handler_peripheral.read(&handler_characteristic);
+            let _ = sender.lock().unwrap().send(value);
+        }));
+        assert_eq!(peripheral.read(&characteristic).unwrap(), vec![42]);
+        let from_handler = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(from_handler.unwrap(), vec![42]);
+    }
+
+    #[test]
+    fn bluez_restarts_are_survived() {
+        let (_bus, bluez, adapter) = setup();
+        let address: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+        bluez.add_object(
+            "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F",
+            "org.bluez.Device1",
+            vec![("Address", Box::new(address.to_string()))],
+        );
+        let events = adapter.event_receiver();
+        block_on(adapter.start_scan()).unwrap();
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::DeviceDiscovered(a) if a == address
+        ));
+
+        bluez.release_name();
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::BluetoothServiceLost
+        ));
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::DeviceLost(a) if a == address
+        ));
+        assert!(adapter.peripherals().is_empty());
+        assert!(matches!(adapter.is_powered(), Err(Error::AdapterNotFound)));
+
+        bluez.acquire_name();
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::BluetoothServiceRestored
+        ));
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::DeviceDiscovered(a) if a == address
+        ));
+        assert!(adapter.peripheral(address).is_some());
+        assert!(adapter.is_powered().unwrap());
+        // The scan that was running is started again.
+        FakeBluez::wait_for(|| bluez.calls("StartDiscovery").len() == 2);
+        assert_eq!(bluez.calls("SetDiscoveryFilter").len(), 2);
+    }
+
+    #[test]
+    fn unexpected_objects_are_reported() {
+        use crate::api::{CentralEventKind, EventFilter};
+
+        let (_bus, bluez, adapter) = setup();
+        let address: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+        let device = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F";
+        bluez.add_object(
+            device,
+            "org.bluez.Device1",
+            vec![("Address", Box::new(address.to_string()))],
+        );
+        // Loaded along with the device, but skipped.
+        bluez.add_object(
+            &format!("{}/service0010/charXYZW", device),
+            "org.bluez.GattCharacteristic1",
+            vec![("UUID", Box::new(uuid_from_u16(0x2A37).to_string()))],
+        );
+        let errors = adapter.subscribe_events(EventSubscription {
+            filter: EventFilter::default().kind(CentralEventKind::Error),
+            ..Default::default()
+        });
+        block_on(adapter.start_scan()).unwrap();
+        assert!(matches!(
+            errors.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::Error { address: Some(a), .. } if a == address
+        ));
+
+        let characteristic = format!("{}/service0010/char0011", device);
+        bluez.add_object(
+            &characteristic,
+            "org.bluez.GattCharacteristic1",
+            vec![("Flags", Box::new(vec!["read".to_string()]))],
+        );
+        bluez.announce(&characteristic);
+        assert!(matches!(
+            errors.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::Error { address: Some(a), .. } if a == address
+        ));
+
+        bluez.emit_properties_changed(
+            &format!("{}/service0010/char0020", device),
+            "org.bluez.GattCharacteristic1",
+            vec![("Value", Box::new(vec![1u8]))],
+        );
+        assert!(matches!(
+            errors.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::Error { address: Some(a), .. } if a == address
+        ));
+
+        bluez.add_object("/org/bluez/hci0/dev_0A", "org.bluez.Device1", vec![]);
+        bluez.remove_object("/org/bluez/hci0/dev_0A");
+        assert!(matches!(
+            errors.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::Error { address: None, .. }
+        ));
+
+        // Events keep being handled.
+        let other = "/org/bluez/hci0/dev_01_02_03_04_05_06";
+        bluez.add_object(
+            other,
+            "org.bluez.Device1",
+            vec![("Address", Box::new("01:02:03:04:05:06".to_string()))],
+        );
+        bluez.announce(other);
+        FakeBluez::wait_for(|| adapter.peripherals().len() == 2);
+    }
+
+    #[test]
+    fn service_changes_are_tracked() {
+        let (_bus, bluez, adapter) = setup();
+        let address: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+        let (device, characteristic) = add_connectable_device(&bluez);
+        let events = adapter.event_receiver();
+        // GATT objects come and go whether or not we're scanning.
+        block_on(async {
+            adapter.start_scan().await.unwrap();
+            adapter.stop_scan().await.unwrap();
+        });
+        let peripheral = adapter.peripheral(address).unwrap();
+        peripheral.connect().unwrap();
+        bluez.emit_properties_changed(
+            &device,
+            "org.bluez.Device1",
+            vec![("ServicesResolved", Box::new(true))],
+        );
+        assert_eq!(peripheral.discover_characteristics().unwrap().len(), 1);
+
+        let added = format!("{}/service0010/char0014", device);
+        bluez.add_object(
+            &added,
+            "org.bluez.GattCharacteristic1",
+            vec![("UUID", Box::new(uuid_from_u16(0x2A38).to_string()))],
+        );
+        bluez.announce(&added);
+        let next_change = || {
+            events
+                .iter()
+                .find(|e| {
+                    matches!(
+                        e,
+                        CentralEvent::ServicesChanged(_)
+                            | CentralEvent::CharacteristicsInvalidated { .. }
+                    )
+                })
+                .unwrap()
+        };
+        assert!(matches!(next_change(), CentralEvent::ServicesChanged(a) if a == address));
+        assert_eq!(peripheral.characteristics().len(), 2);
+
+        bluez.remove_object(&format!("{}/service0010/char0011", device));
+        match next_change() {
+            CentralEvent::CharacteristicsInvalidated {
+                address: a,
+                characteristics,
+            } => {
+                assert_eq!(a, address);
+                // As it was reported, up to the characteristic that followed it.
+                let invalidated = Characteristic {
+                    end_handle: 0x13,
+                    ..characteristic.clone()
+                };
+                assert_eq!(characteristics, vec![invalidated]);
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        assert!(matches!(next_change(), CentralEvent::ServicesChanged(a) if a == address));
+        assert!(matches!(
+            peripheral.read(&characteristic),
+            Err(Error::CharacteristicNotFound(uuid)) if uuid == characteristic.uuid
+        ));
+        assert_eq!(
+            peripheral
+                .characteristics()
+                .iter()
+                .map(|c| c.value_handle)
+                .collect::<Vec<_>>(),
+            vec![0x14]
+        );
+    }
+
+    #[test]
+    fn services_are_resolved_again_after_a_change() {
+        let (_bus, bluez, adapter) = setup();
+        let address: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+        let (device, characteristic) = add_connectable_device(&bluez);
+        let services_resolved = |resolved: bool| {
+            bluez.emit_properties_changed(
+                &device,
+                "org.bluez.Device1",
+                vec![("ServicesResolved", Box::new(resolved))],
+            );
+        };
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter.peripheral(address).unwrap();
+        peripheral.connect().unwrap();
+        services_resolved(true);
+        assert_eq!(peripheral.discover_characteristics().unwrap().len(), 1);
+        let events = adapter.event_receiver();
+
+        // After a Service Changed indication, BlueZ discovers the services again, and the stale
+        // ones aren't returned meanwhile.
+        services_resolved(false);
+        FakeBluez::wait_for(|| peripheral.characteristics().is_empty());
+        let timeout = Duration::from_millis(100);
+        assert!(matches!(
+            block_on(peripheral.discover_characteristics_with_timeout(timeout)),
+            Err(Error::TimedOut(t)) if t == timeout
+        ));
+        assert!(peripheral.is_connected());
+
+        bluez.remove_object(&format!("{}/service0010/char0011", device));
+        let added = format!("{}/service0010/char0014", device);
+        bluez.add_object(
+            &added,
+            "org.bluez.GattCharacteristic1",
+            vec![("UUID", Box::new(uuid_from_u16(0x2A38).to_string()))],
+        );
+        bluez.announce(&added);
+        services_resolved(true);
+        assert_eq!(
+            peripheral
+                .discover_characteristics()
+                .unwrap()
+                .iter()
+                .map(|c| c.value_handle)
+                .collect::<Vec<_>>(),
+            vec![0x14]
+        );
+        // The removed characteristic is reported right away, and the change once resolved.
+        let changes: Vec<_> = events
+            .iter()
+            .filter(|e| {
+                matches!(
+                    e,
+                    CentralEvent::ServicesChanged(_)
+                        | CentralEvent::CharacteristicsInvalidated { .. }
+                )
+            })
+            .take(2)
+            .collect();
+        assert!(matches!(
+            &changes[..],
+            [
+                CentralEvent::CharacteristicsInvalidated { characteristics, .. },
+                CentralEvent::ServicesChanged(a),
+            ] if characteristics[0].value_handle == characteristic.value_handle && *a == address
+        ));
+    }
+
+    #[test]
+    fn connecting_times_out_or_is_canceled() {
+        use std::time::Instant;
+
+        let (_bus, bluez, adapter) = setup();
+        let address: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+        bluez.add_object(
+            "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F",
+            "org.bluez.Device1",
+            vec![("Address", Box::new(address.to_string()))],
+        );
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter.peripheral(address).unwrap();
+
+        // BlueZ accepts to connect, but the device never shows up, so the attempt is aborted.
+        let timeout = Duration::from_millis(100);
+        assert!(matches!(
+            block_on(peripheral.connect_with_timeout(timeout)),
+            Err(Error::TimedOut(t)) if t == timeout
+        ));
+        assert_eq!(bluez.calls("Disconnect").len(), 1);
+
+        let cancel = peripheral.cancel_handle();
+        bluez.on_method("org.bluez.Device1", "Connect", move |msg, _bluez| {
+            cancel.cancel();
+            msg.method_return()
+        });
+        let started = Instant::now();
+        assert!(matches!(
+            block_on(peripheral.connect_with_timeout(Duration::from_secs(30))),
+            Err(Error::TimedOut(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(bluez.calls("Disconnect").len(), 2);
+    }
+
+    #[test]
+    fn service_discovery_uses_the_adapter_timeouts() {
+        let (_bus, bluez, adapter) = setup();
+        add_connectable_device(&bluez);
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter
+            .peripheral("0A:0B:0C:0D:0E:0F".parse().unwrap())
+            .unwrap();
+        peripheral.connect().unwrap();
+
+        // The services are never resolved.
+        let timeout = Duration::from_millis(100);
+        adapter.set_timeouts(Timeouts {
+            service_discovery: timeout,
+            ..adapter.timeouts()
+        });
+        assert!(matches!(
+            peripheral.discover_characteristics(),
+            Err(Error::TimedOut(t)) if t == timeout
+        ));
+        assert!(peripheral.is_connected());
+    }
+
+    #[test]
+    fn dropped_connections_are_restored() {
+        use crate::api::CentralEventKind;
+
+        let (_bus, bluez, adapter) = setup();
+        let address: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+        let (device, characteristic) = add_connectable_device(&bluez);
+        let connected = device.clone();
+        bluez.on_method("org.bluez.Device1", "Connect", move |msg, bluez| {
+            bluez.emit_properties_changed(
+                &connected,
+                "org.bluez.Device1",
+                vec![
+                    ("Connected", Box::new(true)),
+                    ("ServicesResolved", Box::new(true)),
+                ],
+            );
+            msg.method_return()
+        });
+        let events = adapter.event_receiver();
+        let next = |kind: CentralEventKind| loop {
+            let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+            if event.kind() == kind {
+                break event;
+            }
+        };
+
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter.peripheral(address).unwrap();
+        peripheral.set_reconnect_policy(Some(ReconnectPolicy {
+            max_attempts: Some(3),
+            initial_delay: Duration::from_millis(10),
+            jitter: 0.0,
+            ..Default::default()
+        }));
+        peripheral.connect().unwrap();
+        peripheral.discover_characteristics().unwrap();
+        peripheral.subscribe(&characteristic).unwrap();
+
+        // The device goes out of range, and comes back.
+        bluez.emit_properties_changed(
+            &device,
+            "org.bluez.Device1",
+            vec![
+                ("Connected", Box::new(false)),
+                ("ServicesResolved", Box::new(false)),
+            ],
+        );
+        next(CentralEventKind::DeviceDisconnected);
+        assert!(adapter.peripheral(address).is_some());
+        next(CentralEventKind::DeviceReconnected);
+        assert!(peripheral.is_connected());
+        assert_eq!(bluez.calls("Connect").len(), 2);
+        assert_eq!(bluez.calls("StartNotify").len(), 2);
+
+        // Then stays away.
+        bluez.on_method("org.bluez.Device1", "Connect", |msg, _bluez| {
+            msg.error(
+                &"org.bluez.Error.Failed".into(),
+                &std::ffi::CString::new("Page Timeout").unwrap(),
+            )
+        });
+        bluez.emit_properties_changed(
+            &device,
+            "org.bluez.Device1",
+            vec![("Connected", Box::new(false))],
+        );
+        assert!(matches!(
+            next(CentralEventKind::DeviceReconnectFailed),
+            CentralEvent::DeviceReconnectFailed { attempts: 3, .. }
+        ));
+        assert_eq!(bluez.calls("Connect").len(), 5);
+        assert!(adapter.peripheral(address).is_none());
+    }
+
+    #[test]
+    fn subscriptions_follow_bluez() {
+        use crate::api::CentralEventKind;
+
+        let (_bus, bluez, adapter) = setup();
+        let address: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+        let (device, characteristic) = add_connectable_device(&bluez);
+        let path = format!("{}/service0010/char0011", device);
+        let connected = device.clone();
+        bluez.on_method("org.bluez.Device1", "Connect", move |msg, bluez| {
+            bluez.emit_properties_changed(
+                &connected,
+                "org.bluez.Device1",
+                vec![
+                    ("Connected", Box::new(true)),
+                    ("ServicesResolved", Box::new(true)),
+                ],
+            );
+            msg.method_return()
+        });
+        let notifying = path.clone();
+        bluez.on_method(
+            "org.bluez.GattCharacteristic1",
+            "StartNotify",
+            move |msg, bluez| {
+                bluez.emit_properties_changed(
+                    &notifying,
+                    "org.bluez.GattCharacteristic1",
+                    vec![("Notifying", Box::new(true))],
+                );
+                msg.method_return()
+            },
+        );
+        let events = adapter.event_receiver();
+        let subscription_changed = || loop {
+            let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+            if let CentralEvent::SubscriptionChanged {
+                characteristic,
+                subscribed,
+                ..
+            } = event
+            {
+                break (characteristic.value_handle, subscribed);
+            }
+        };
+
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter.peripheral(address).unwrap();
+        peripheral.connect().unwrap();
+        peripheral.discover_characteristics().unwrap();
+        assert!(!peripheral.is_subscribed(&characteristic));
+
+        // BlueZ confirms the subscription.
+        peripheral.subscribe(&characteristic).unwrap();
+        assert_eq!(subscription_changed(), (0x11, true));
+        assert!(peripheral.is_subscribed(&characteristic));
+        assert_eq!(peripheral.subscribed_characteristics().len(), 1);
+
+        // Then notifications stop without us asking, and start again.
+        bluez.emit_properties_changed(
+            &path,
+            "org.bluez.GattCharacteristic1",
+            vec![("Notifying", Box::new(false))],
+        );
+        assert_eq!(subscription_changed(), (0x11, false));
+        assert!(!peripheral.is_subscribed(&characteristic));
+        peripheral.subscribe(&characteristic).unwrap();
+        assert_eq!(subscription_changed(), (0x11, true));
+
+        // A disconnection stops them too.
+        bluez.emit_properties_changed(
+            &device,
+            "org.bluez.Device1",
+            vec![("Connected", Box::new(false))],
+        );
+        assert_eq!(subscription_changed(), (0x11, false));
+        assert!(peripheral.subscribed_characteristics().is_empty());
+        // BlueZ reporting it as well changes nothing.
+        bluez.emit_properties_changed(
+            &path,
+            "org.bluez.GattCharacteristic1",
+            vec![("Notifying", Box::new(false))],
+        );
+        peripheral.connect().unwrap();
+        loop {
+            let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_ne!(event.kind(), CentralEventKind::SubscriptionChanged);
+            if event.kind() == CentralEventKind::DeviceConnected {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn requested_disconnections_are_not_undone() {
+        use crate::api::CentralEventKind;
+
+        let (_bus, bluez, adapter) = setup();
+        let address: BDAddr = "0A:0B:0C:0D:0E:0F".parse().unwrap();
+        let (device, _characteristic) = add_connectable_device(&bluez);
+        bluez.on_method("org.bluez.Device1", "Disconnect", move |msg, bluez| {
+            bluez.emit_properties_changed(
+                &device,
+                "org.bluez.Device1",
+                vec![("Connected", Box::new(false))],
+            );
+            msg.method_return()
+        });
+        let disconnected = adapter.subscribe_events(EventSubscription {
+            filter: crate::api::EventFilter::default().kind(CentralEventKind::DeviceDisconnected),
+            ..Default::default()
+        });
+
+        block_on(adapter.start_scan()).unwrap();
+        let peripheral = adapter.peripheral(address).unwrap();
+        peripheral.set_reconnect_policy(Some(ReconnectPolicy::default()));
+        peripheral.connect().unwrap();
+        peripheral.disconnect().unwrap();
+        disconnected.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(adapter.peripheral(address).is_none());
+        assert_eq!(bluez.calls("Connect").len(), 1);
     }
 }
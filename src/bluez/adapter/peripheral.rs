@@ -13,32 +13,47 @@
 
 use crate::{
     api::{
-        AdapterManager, AddressType, BDAddr, CentralEvent, CharPropFlags, Characteristic,
-        NotificationHandler, Peripheral as ApiPeripheral, PeripheralProperties, ValueNotification,
-        WriteType,
+        notifications::NotificationHandlers, AdapterManager, AddressType, AsyncPeripheral, BDAddr,
+        BondState, CentralEvent, CharPropFlags, Characteristic, Descriptor, NotificationHandler,
+        NotificationHandlerId, NotificationReceiver, OverflowPolicy, PeripheralProperties, Service,
+        ValueNotification, WriteType,
     },
     bluez::{
-        bluez_dbus::device::OrgBluezDevice1, bluez_dbus::device::OrgBluezDevice1Properties,
-        bluez_dbus::gatt_characteristic::OrgBluezGattCharacteristic1, AttributeType, Handle,
-        BLUEZ_DEST, DEFAULT_TIMEOUT,
+        adapter::{
+            attribute_db::{Attribute, AttributeDb},
+            ReconnectPolicy, Timeouts,
+        },
+        async_connection::{with_timeout, AsyncConnection},
+        bluez_dbus::device::OrgBluezDevice1Properties,
+        bluez_dbus::device::ORG_BLUEZ_DEVICE1_NAME,
+        bluez_dbus::gatt_characteristic::ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+        bluez_dbus::gatt_descriptor::ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+        AttributeType, Handle,
     },
-    common::util::invoke_handlers,
     Error, Result,
 };
+use async_trait::async_trait;
 use dbus::{
-    arg::{cast, PropMap, RefArg, Variant},
-    blocking::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, Proxy, SyncConnection},
+    arg::{cast, PropMap, Variant},
+    blocking::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, SyncConnection},
     channel::Token,
     message::{Message, SignalArgs},
     Path,
 };
-use log::{debug, error, trace, warn};
+use futures::{
+    executor::block_on,
+    future::{self, Either},
+};
+use log::{debug, error, info, trace, warn};
 use static_assertions::assert_impl_all;
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
-    sync::{Arc, Condvar, Mutex},
-    time::Instant,
+    future::Future,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+    thread,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
@@ -49,76 +64,280 @@ enum PeripheralState {
     ServicesResolved,
 }
 
+/// A value, such as the connection state of a peripheral, along with the tasks waiting for it to
+/// change.
+#[derive(Debug)]
+struct Watch<T> {
+    value: Mutex<T>,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl<T: Clone + Send> Watch<T> {
+    fn new(value: T) -> Self {
+        Watch {
+            value: Mutex::new(value),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn get(&self) -> T {
+        self.value.lock().unwrap().clone()
+    }
+
+    /// Changes the value with `f`, and wakes up everyone waiting for a change.
+    fn update<F: FnOnce(&mut T)>(&self, f: F) {
+        f(&mut self.value.lock().unwrap());
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Resolves to the first value for which `done` returns true.
+    fn wait_until<'a, F>(&'a self, done: F) -> impl Future<Output = T> + Send + 'a
+    where
+        F: Fn(&T) -> bool + Send + 'a,
+    {
+        futures::future::poll_fn(move |cx| {
+            let value = self.value.lock().unwrap();
+            if done(&value) {
+                Poll::Ready(value.clone())
+            } else {
+                // Registered while holding the value lock, so that no update can be missed.
+                self.wakers.lock().unwrap().push(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+}
+
+/// The reconnect policy of a peripheral, and the reconnection in progress.
+#[derive(Clone, Debug, Default)]
+struct Reconnect {
+    policy: Option<ReconnectPolicy>,
+    /// Whether a thread is reconnecting.
+    running: bool,
+    /// Bumped to stop the reconnection in progress.
+    generation: u64,
+    /// Set by `disconnect`, so a disconnection that was asked for isn't undone.
+    disconnecting: bool,
+}
+
+/// Aborts the connection attempts, pairings and service discoveries in progress on a peripheral,
+/// from any thread. They fail with `Error::TimedOut`, as if they had timed out right away, and
+/// BlueZ is told to stop connecting or pairing. Operations started afterwards aren't affected.
+#[derive(Clone, Debug)]
+pub struct CancelHandle {
+    canceled: Arc<Watch<u64>>,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.canceled.update(|canceled| *canceled += 1);
+    }
+}
+
 #[derive(Clone)]
 pub struct Peripheral {
     adapter: AdapterManager<Self>,
-    connection: Arc<SyncConnection>,
+    calls: AsyncConnection,
     path: String,
     address: BDAddr,
     properties: Arc<Mutex<PeripheralProperties>>,
-    characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
-    attributes_map: Arc<Mutex<HashMap<u16, (String, Handle, Characteristic)>>>,
-    state: Arc<(Mutex<PeripheralState>, Condvar)>,
-    notification_handlers: Arc<Mutex<Vec<NotificationHandler>>>,
+    attributes: Arc<Mutex<AttributeDb>>,
+    state: Arc<Watch<PeripheralState>>,
+    /// Counts the cancellations, so operations can tell whether they were canceled since they
+    /// started.
+    canceled: Arc<Watch<u64>>,
+    timeouts: Arc<Mutex<Timeouts>>,
+    reconnect: Arc<Watch<Reconnect>>,
+    /// The characteristics subscribed to, by value handle, to subscribe again after reconnecting.
+    /// Whether the device confirmed them is tracked by the attribute database instead.
+    subscriptions: Arc<Mutex<BTreeMap<u16, Characteristic>>>,
+    /// Unknown until the first properties of the device are loaded.
+    bond: Arc<Mutex<Option<BondState>>>,
+    notification_handlers: Arc<NotificationHandlers>,
     listen_token: Arc<Mutex<Option<Token>>>,
 }
 
 impl Peripheral {
-    pub fn new(
+    pub(crate) fn new(
         adapter: AdapterManager<Self>,
-        connection: Arc<SyncConnection>,
+        calls: AsyncConnection,
         path: &str,
         address: BDAddr,
+        timeouts: Arc<Mutex<Timeouts>>,
     ) -> Self {
         let mut properties = PeripheralProperties::default();
         properties.address = address;
         let properties = Arc::new(Mutex::new(properties));
-        let characteristics = Arc::new(Mutex::new(BTreeSet::new()));
-        let notification_handlers = Arc::new(Mutex::new(Vec::new()));
+        let notification_handlers = Arc::new(NotificationHandlers::default());
 
         Peripheral {
             adapter: adapter,
-            connection: connection,
+            calls,
             path: path.to_string(),
             address: address,
-            state: Arc::new((Mutex::new(PeripheralState::NotConnected), Condvar::new())),
+            state: Arc::new(Watch::new(PeripheralState::NotConnected)),
+            canceled: Arc::new(Watch::new(0)),
+            timeouts,
+            reconnect: Arc::new(Watch::new(Reconnect::default())),
+            subscriptions: Arc::new(Mutex::new(BTreeMap::new())),
+            bond: Arc::new(Mutex::new(None)),
             properties: properties,
-            attributes_map: Arc::new(Mutex::new(HashMap::new())),
-            characteristics: characteristics,
+            attributes: Arc::new(Mutex::new(AttributeDb::default())),
             notification_handlers: notification_handlers,
             listen_token: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Connects to the device, failing with `Error::TimedOut` if it isn't connected within
+    /// `timeout`, rather than the adapter's `Timeouts::connect`.
+    pub async fn connect_with_timeout(&self, timeout: Duration) -> Result<()> {
+        self.reconnect.update(|r| r.disconnecting = false);
+        let connect = async {
+            let connect = self.calls.call::<_, ()>(
+                &self.path,
+                ORG_BLUEZ_DEVICE1_NAME,
+                "Connect",
+                (),
+                timeout,
+            );
+            match connect.await {
+                Ok(()) => Ok(()),
+                Err(error) => match error.name() {
+                    Some("org.bluez.Error.AlreadyConnected") => Ok(()),
+                    Some("org.bluez.Error.Failed") => {
+                        error!(
+                            "BlueZ Failed to connect to \"{:?}\": {}",
+                            self.address,
+                            error.message().unwrap_or_default()
+                        );
+                        Err(Error::NotConnected)
+                    }
+                    _ => Err(call_error(timeout)(error)),
+                },
+            }?;
+            // BlueZ may reply before the device is actually connected, so wait for the
+            // "Connected" property to turn true.
+            self.state
+                .wait_until(|state| *state >= PeripheralState::Connected)
+                .await;
+            Ok(())
+        };
+        self.cancelable(timeout, Some("Disconnect"), connect).await
+    }
+
+    /// Pairs with the device, failing with `Error::TimedOut` if it takes longer than `timeout`,
+    /// rather than the adapter's `Timeouts::pairing`.
+    pub async fn pair_with_timeout(&self, timeout: Duration) -> Result<()> {
+        let pair = async {
+            let pair =
+                self.calls
+                    .call::<_, ()>(&self.path, ORG_BLUEZ_DEVICE1_NAME, "Pair", (), timeout);
+            match pair.await {
+                Ok(()) => Ok(()),
+                Err(error) => match error.name() {
+                    // Don't error if the device is already paired.
+                    Some("org.bluez.Error.AlreadyExists") => Ok(()),
+                    _ => Err(call_error(timeout)(error)),
+                },
+            }
+        };
+        self.cancelable(timeout, Some("CancelPairing"), pair).await
+    }
+
+    /// Waits for the services of the device to be resolved, failing with `Error::TimedOut` if
+    /// they aren't within `timeout`, rather than the adapter's `Timeouts::service_discovery`.
+    pub async fn discover_characteristics_with_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Vec<Characteristic>> {
+        let resolved = async {
+            trace!("Waiting for all services to be resolved");
+            let state = self
+                .state
+                .wait_until(|state| *state != PeripheralState::Connected)
+                .await;
+            if state == PeripheralState::NotConnected {
+                return Err(Error::NotConnected);
+            }
+            debug!("All services are now resolved!");
+            Ok(self.characteristics().into_iter().collect())
+        };
+        self.cancelable(timeout, None, resolved).await
+    }
+
+    /// Returns a handle that cancels the connection attempts, pairings and service discoveries
+    /// in progress on this peripheral.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle {
+            canceled: self.canceled.clone(),
+        }
+    }
+
+    /// Reconnects to the device on its own when it disconnects without being asked to, following
+    /// `policy`. Once connected, the services are resolved again and the characteristics that
+    /// were subscribed to are subscribed to again, then `CentralEvent::DeviceReconnected` is
+    /// emitted. The peripheral is kept while reconnecting, unless the policy gives up.
+    ///
+    /// `None` disables reconnection, and like any new policy, stops a reconnection in progress.
+    pub fn set_reconnect_policy(&self, policy: Option<ReconnectPolicy>) {
+        self.reconnect.update(|r| {
+            r.policy = policy;
+            r.generation += 1;
+        });
+    }
+
+    pub fn reconnect_policy(&self) -> Option<ReconnectPolicy> {
+        self.reconnect.get().policy
+    }
+
+    /// Whether the device is paired, trusted or blocked, as last reported by BlueZ.
+    pub fn bond_state(&self) -> BondState {
+        self.bond.lock().unwrap().unwrap_or_default()
+    }
+
     pub fn properties_changed(
         &self,
         args: PropertiesPropertiesChanged,
         _connection: &SyncConnection,
         message: &Message,
     ) -> bool {
-        let path = message.path().unwrap().into_static();
-        let path = path.as_str().unwrap();
+        let path = match message.path() {
+            Some(path) => path,
+            None => return true,
+        };
         if path.starts_with(self.path.as_str()) {
             if let Ok(handle) = path.parse::<Handle>() {
-                if args.changed_properties.contains_key("Value") {
-                    let notification = ValueNotification {
-                        handle: Some(handle.handle),
-                        uuid: self
-                            .attributes_map
-                            .lock()
-                            .unwrap()
-                            .get(&handle.handle)
-                            .unwrap()
-                            .2
-                            .uuid,
-                        value: dbus::arg::prop_cast::<Vec<u8>>(&args.changed_properties, "Value")
-                            .cloned()
-                            .unwrap_or_default(),
-                    };
-                    invoke_handlers(&self.notification_handlers, &notification);
-                } else if args.changed_properties.contains_key("Notifying") {
-                    // TODO: Keep track of subscribed and unsubscribed characteristics?
-                } else {
+                let value = dbus::arg::prop_cast::<Vec<u8>>(&args.changed_properties, "Value");
+                // Only characteristic values are notified, those of descriptors change when read.
+                let notified = value.filter(|_v| handle.typ == AttributeType::Characteristic);
+                let notifying = dbus::arg::prop_cast::<bool>(&args.changed_properties, "Notifying");
+                if let Some(value) = notified {
+                    let uuid = self
+                        .attributes
+                        .lock()
+                        .unwrap()
+                        .get(handle.handle)
+                        .map(|a| a.uuid);
+                    match uuid {
+                        Some(uuid) => {
+                            self.notification_handlers.notify(ValueNotification {
+                                handle: Some(handle.handle),
+                                uuid,
+                                value: value.as_slice().into(),
+                            });
+                        }
+                        None => self.adapter.emit_error(
+                            Some(self.address),
+                            Error::Other(format!("Value changed on unknown attribute {}", path)),
+                        ),
+                    }
+                }
+                if let Some(notifying) = notifying {
+                    self.notifying_changed(handle.handle, *notifying);
+                }
+                if value.is_none() && notifying.is_none() {
                     warn!(
                         "Unhandled properties changed on an attribute\n\t{:?}\n\t{:?}",
                         path, args.changed_properties
@@ -143,25 +362,28 @@ impl Peripheral {
         true
     }
 
-    pub fn listen(&self, listener: &SyncConnection) -> Result<()> {
+    pub fn listen(&self) -> Result<()> {
         let peripheral = self.clone();
         let mut rule = PropertiesPropertiesChanged::match_rule(None, None);
         // For some silly lifetime reasons, we need to assign path separately...
         rule.path = Some(Path::from(self.path.clone()));
         // And also, we're interested in properties changed on all sub elements
         rule.path_is_namespace = true;
-        let token =
-            listener.add_match(rule, move |a, s, m| peripheral.properties_changed(a, s, m))?;
+        let token = self
+            .calls
+            .add_match(rule, move |a, s, m| peripheral.properties_changed(a, s, m))?;
         *self.listen_token.lock().unwrap() = Some(token);
 
         Ok(())
     }
 
-    pub fn stop_listening(&self, listener: &SyncConnection) -> Result<()> {
+    pub fn stop_listening(&self) -> Result<()> {
         trace!("Stop listening for events");
+        // The device is gone, or the adapter shut down.
+        self.reconnect.update(|r| r.generation += 1);
         let mut token = self.listen_token.lock().unwrap();
         if token.is_some() {
-            listener.remove_match(token.unwrap())?;
+            self.calls.remove_match(token.unwrap())?;
             *token = None;
         }
 
@@ -175,210 +397,505 @@ impl Peripheral {
             properties,
             path
         );
-        let mut path_uuid_map = self.attributes_map.lock().unwrap();
-        let handle: Handle = path.parse()?;
-        // Create a placeholder attribute to store properties and uuid
-        let attribute = Characteristic {
+        self.insert_attribute(Attribute {
+            path: path.to_string(),
+            handle: path.parse()?,
             uuid,
-            value_handle: handle.handle,
             properties,
-            end_handle: 0,
-            start_handle: 0,
-        };
+            primary: true,
+            includes: Vec::new(),
+        });
+        Ok(())
+    }
 
-        path_uuid_map.insert(handle.handle, (path.to_string(), handle, attribute));
+    pub fn add_service(
+        &self,
+        path: &str,
+        uuid: Uuid,
+        primary: bool,
+        includes: &[Path<'static>],
+    ) -> Result<()> {
+        trace!("Adding service {} under {}", uuid, path);
+        self.insert_attribute(Attribute {
+            path: path.to_string(),
+            handle: path.parse()?,
+            uuid,
+            properties: CharPropFlags::empty(),
+            primary,
+            includes: includes
+                .iter()
+                .filter_map(|p| p.parse::<Handle>().ok())
+                .map(|h| h.handle)
+                .collect(),
+        });
         Ok(())
     }
 
-    fn build_characteristic_ranges(&self) -> Result<()> {
-        let handles = self.attributes_map.lock().unwrap();
+    /// Removes a service, characteristic or descriptor BlueZ no longer exports, along with the
+    /// attributes it contains.
+    pub fn remove_attribute(&self, path: &str) -> Result<()> {
+        let handle: Handle = path.parse()?;
+        let (removed, reported, resolved) = {
+            let mut attributes = self.attributes.lock().unwrap();
+            let removed = attributes.remove(handle.handle);
+            (removed, attributes.is_reported(), attributes.is_resolved())
+        };
+        let characteristics = match removed {
+            Some(characteristics) => characteristics,
+            None => {
+                debug!("Attribute {} was never added", path);
+                return Ok(());
+            }
+        };
+        trace!("Removed attribute {}", path);
+        // Attributes removed before the services are first resolved were never reported. While
+        // BlueZ resolves them again, the change is reported once it is done.
+        if reported && !characteristics.is_empty() {
+            self.adapter.emit(CentralEvent::CharacteristicsInvalidated {
+                address: self.address,
+                characteristics,
+            });
+        }
+        if resolved {
+            self.adapter
+                .emit(CentralEvent::ServicesChanged(self.address));
+        }
+        Ok(())
+    }
 
-        let mut services = handles
-            .iter()
-            .filter(|(_k, (_p, h, _v))| h.typ == AttributeType::Service)
-            .map(|(_h, (_p, k, v))| (k, v))
-            .peekable();
+    /// Records whether BlueZ reports the characteristic at `path` as notifying, when it is added.
+    pub(crate) fn set_notifying(&self, path: &str, notifying: bool) -> Result<()> {
+        let handle: Handle = path.parse()?;
+        self.notifying_changed(handle.handle, notifying);
+        Ok(())
+    }
 
-        let mut result = self.characteristics.lock().unwrap();
-        result.clear();
+    /// Whether BlueZ reports notifications or indications of `characteristic` as enabled. Unlike
+    /// a successful `subscribe`, this is only true once the device has confirmed it, and becomes
+    /// false again if the device, another client or a disconnection stops them.
+    pub fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        let attributes = self.attributes.lock().unwrap();
+        attributes
+            .find(
+                characteristic.value_handle,
+                AttributeType::Characteristic,
+                characteristic.uuid,
+            )
+            .is_some()
+            && attributes.is_notifying(characteristic.value_handle)
+    }
 
-        // TODO: Verify that service attributes are returned as characteristics
-        while let Some((handle, attribute)) = services.next() {
-            let next = services.peek();
-            result.insert(Characteristic {
-                start_handle: handle.handle,
-                end_handle: next.map_or(u16::MAX, |n| n.0.handle - 1),
-                value_handle: handle.handle,
-                properties: attribute.properties,
-                uuid: attribute.uuid.clone(),
-            });
-        }
+    /// The characteristics whose notifications or indications are enabled, as with
+    /// `is_subscribed`.
+    pub fn subscribed_characteristics(&self) -> BTreeSet<Characteristic> {
+        self.attributes.lock().unwrap().notifying()
+    }
 
-        let mut characteristics = handles
-            .iter()
-            .filter(|(_h, (_p, k, _v))| k.typ == AttributeType::Characteristic)
-            .map(|(_h, (_p, k, v))| (k, v))
-            .peekable();
-
-        while let Some((handle, attribute)) = characteristics.next() {
-            let next = characteristics.peek();
-            result.insert(Characteristic {
-                start_handle: handle.handle,
-                end_handle: next.map_or(u16::MAX, |n| n.0.handle - 1),
-                value_handle: handle.handle,
-                properties: attribute.properties,
-                uuid: attribute.uuid.clone(),
+    /// Records a change to the `Notifying` property of a characteristic, and reports it once the
+    /// services are resolved.
+    fn notifying_changed(&self, handle: u16, notifying: bool) {
+        let changed = {
+            let mut attributes = self.attributes.lock().unwrap();
+            attributes
+                .set_notifying(handle, notifying)
+                .filter(|_c| attributes.is_resolved())
+        };
+        if let Some(characteristic) = changed {
+            debug!(
+                "Characteristic {} of \"{}\" notifying: {}",
+                characteristic.uuid, self.address, notifying
+            );
+            self.adapter.emit(CentralEvent::SubscriptionChanged {
+                address: self.address,
+                characteristic,
+                subscribed: notifying,
             });
         }
+    }
 
-        Ok(())
+    /// Adds an attribute, reporting the change if the services were already resolved, such as
+    /// when the device announces new services with a Service Changed indication.
+    fn insert_attribute(&self, attribute: Attribute) {
+        let changed = {
+            let mut attributes = self.attributes.lock().unwrap();
+            attributes.insert(attribute) && attributes.is_resolved()
+        };
+        if changed {
+            self.adapter
+                .emit(CentralEvent::ServicesChanged(self.address));
+        }
     }
 
     pub fn update_properties(&self, args: OrgBluezDevice1Properties) {
         trace!("Updating peripheral properties");
-        let mut properties = self.properties.lock().unwrap();
-        let mut emit_updated = false;
-
-        properties.discovery_count += 1;
+        // Events are emitted once no lock is held, as emitting can block on a full subscriber.
+        let mut events = Vec::new();
 
         if let Some(connected) = args.connected() {
             debug!(
                 "Updating \"{}\" connected to \"{:?}\"",
                 self.address, connected
             );
-            let (ref lock, ref cvar) = *self.state;
-            let mut state = lock.lock().unwrap();
-            if connected {
-                if *state < PeripheralState::Connected {
-                    self.adapter
-                        .emit(CentralEvent::DeviceConnected(self.address));
-                    *state = PeripheralState::Connected;
-                }
-            } else {
-                if *state >= PeripheralState::Connected {
-                    self.adapter
-                        .emit(CentralEvent::DeviceDisconnected(self.address));
+            let mut disconnected = false;
+            self.state.update(|state| {
+                if connected {
+                    if *state < PeripheralState::Connected {
+                        events.push(CentralEvent::DeviceConnected(self.address));
+                        *state = PeripheralState::Connected;
+                    }
+                } else {
+                    disconnected = *state >= PeripheralState::Connected;
+                    *state = PeripheralState::NotConnected;
                 }
-                *state = PeripheralState::NotConnected;
+            });
+            if disconnected {
+                self.disconnected();
             }
-            cvar.notify_all();
-        }
-
-        if let Some(name) = args.name() {
-            debug!("Updating \"{}\" local name to \"{:?}\"", self.address, name);
-            properties.local_name = Some(name.to_owned());
-            emit_updated = true;
         }
 
         if let Some(services_resolved) = args.services_resolved() {
-            if services_resolved {
-                // Need to prase and figure out handle ranges for all discovered characteristics.
-                self.build_characteristic_ranges().unwrap();
-            }
-            // All services have been discovered, time to inform anyone waiting.
-            let (ref lock, ref cvar) = *self.state;
-            if services_resolved {
-                *lock.lock().unwrap() = PeripheralState::ServicesResolved;
+            debug!(
+                "Updating \"{}\" services resolved to \"{:?}\"",
+                self.address, services_resolved
+            );
+            if self
+                .attributes
+                .lock()
+                .unwrap()
+                .set_resolved(services_resolved)
+            {
+                events.push(CentralEvent::ServicesChanged(self.address));
             }
-            cvar.notify_all();
+            // All services have been discovered, time to inform anyone waiting. BlueZ discovers
+            // them again after a Service Changed indication, which is waited for as well.
+            self.state.update(|state| {
+                if services_resolved {
+                    *state = PeripheralState::ServicesResolved;
+                } else if *state == PeripheralState::ServicesResolved {
+                    *state = PeripheralState::Connected;
+                }
+            });
         }
 
-        if let Some(manufacturer_data) = args.manufacturer_data() {
-            debug!(
-                "Updating \"{}\" manufacturer data \"{:?}\"",
-                self.address, manufacturer_data
-            );
-            properties.manufacturer_data = manufacturer_data
-                .into_iter()
-                .filter_map(|(&k, v)| {
-                    if let Some(v) = cast::<Vec<u8>>(&v.0) {
-                        self.adapter
-                            .emit(CentralEvent::ManufacturerDataAdvertisement {
+        {
+            let mut properties = self.properties.lock().unwrap();
+            let mut emit_updated = false;
+
+            properties.discovery_count += 1;
+
+            if let Some(name) = args.name() {
+                debug!("Updating \"{}\" local name to \"{:?}\"", self.address, name);
+                properties.local_name = Some(name.to_owned());
+                emit_updated = true;
+            }
+
+            if let Some(manufacturer_data) = args.manufacturer_data() {
+                debug!(
+                    "Updating \"{}\" manufacturer data \"{:?}\"",
+                    self.address, manufacturer_data
+                );
+                properties.manufacturer_data = manufacturer_data
+                    .into_iter()
+                    .filter_map(|(&k, v)| {
+                        if let Some(v) = cast::<Vec<u8>>(&v.0) {
+                            events.push(CentralEvent::ManufacturerDataAdvertisement {
                                 address: self.address(),
                                 manufacturer_id: k,
                                 data: v.clone(),
                             });
-                        Some((k, v.to_owned()))
-                    } else {
-                        warn!("Manufacturer data had wrong type: {:?}", &v.0);
-                        None
-                    }
-                })
-                .collect();
-        }
+                            Some((k, v.to_owned()))
+                        } else {
+                            warn!("Manufacturer data had wrong type: {:?}", &v.0);
+                            None
+                        }
+                    })
+                    .collect();
+            }
 
-        if let Some(service_data) = args.service_data() {
-            properties.service_data = service_data
-                .into_iter()
-                .filter_map(|(service, data)| {
-                    let service: Uuid = service.parse().unwrap();
-                    if let Some(data) = cast::<Vec<u8>>(&data.0) {
-                        self.adapter.emit(CentralEvent::ServiceDataAdvertisement {
-                            address: self.address(),
-                            service,
-                            data: data.clone(),
-                        });
-                        Some((service, data.to_owned()))
-                    } else {
-                        warn!("Service data had wrong type: {:?}", &data.0);
-                        None
-                    }
-                })
-                .collect();
+            if let Some(service_data) = args.service_data() {
+                properties.service_data = service_data
+                    .into_iter()
+                    .filter_map(|(service, data)| {
+                        let service: Uuid = match service.parse() {
+                            Ok(service) => service,
+                            Err(_) => {
+                                warn!("Service data had an invalid UUID: {:?}", service);
+                                return None;
+                            }
+                        };
+                        if let Some(data) = cast::<Vec<u8>>(&data.0) {
+                            events.push(CentralEvent::ServiceDataAdvertisement {
+                                address: self.address(),
+                                service,
+                                data: data.clone(),
+                            });
+                            Some((service, data.to_owned()))
+                        } else {
+                            warn!("Service data had wrong type: {:?}", &data.0);
+                            None
+                        }
+                    })
+                    .collect();
+            }
+
+            if let Some(services) = args.uuids() {
+                properties.services = services
+                    .into_iter()
+                    .filter_map(|uuid| uuid.parse().ok())
+                    .collect();
+
+                events.push(CentralEvent::ServicesAdvertisement {
+                    address: self.address.clone(),
+                    services: properties.services.clone(),
+                });
+            }
+
+            if let Some(address_type) = args.address_type() {
+                let address_type = AddressType::from_str(address_type).unwrap_or_default();
+
+                debug!(
+                    "Updating \"{}\" address type \"{:?}\"",
+                    self.address, address_type
+                );
+
+                properties.address_type = address_type;
+                emit_updated = true;
+            }
+
+            if let Some(rssi) = args.rssi() {
+                debug!("Updating \"{}\" RSSI \"{:?}\"", self.address, rssi);
+                properties.rssi = Some(rssi);
+                properties.signal.record(rssi);
+                emit_updated = true;
+            }
+
+            if let Some(tx_power) = args.tx_power() {
+                let tx_power = tx_power as i8;
+                debug!("Updating \"{}\" TX power \"{:?}\"", self.address, tx_power);
+                properties.tx_power_level = Some(tx_power);
+                emit_updated = true;
+            }
+
+            if let Some(bond) = self.update_bond_state(&args) {
+                events.push(bond);
+            }
+
+            if emit_updated {
+                events.push(CentralEvent::DeviceUpdated(self.address));
+            }
         }
 
-        if let Some(services) = args.uuids() {
-            properties.services = services
-                .into_iter()
-                .filter_map(|uuid| uuid.parse().ok())
-                .collect();
+        for event in events {
+            self.adapter.emit(event);
+        }
+    }
 
-            self.adapter.emit(CentralEvent::ServicesAdvertisement {
-                address: self.address.clone(),
-                services: properties.services.clone(),
+    /// Reports a disconnection, and starts reconnecting if it wasn't asked for and there is a
+    /// reconnect policy.
+    fn disconnected(&self) {
+        // Notifications stop along with the connection, whether or not BlueZ reports it.
+        let unsubscribed = self.attributes.lock().unwrap().clear_notifying();
+        for characteristic in unsubscribed {
+            self.adapter.emit(CentralEvent::SubscriptionChanged {
+                address: self.address,
+                characteristic,
+                subscribed: false,
             });
         }
+        let mut reconnect = false;
+        let mut start = None;
+        self.reconnect.update(|r| {
+            reconnect = r.policy.is_some() && !r.disconnecting;
+            if reconnect && !r.running {
+                r.running = true;
+                start = Some(r.generation);
+            }
+        });
+        if !reconnect {
+            self.adapter
+                .emit(CentralEvent::DeviceDisconnected(self.address));
+            return;
+        }
+        self.adapter
+            .emit_keeping_peripheral(CentralEvent::DeviceDisconnected(self.address));
+        if let Some(generation) = start {
+            let peripheral = self.clone();
+            thread::spawn(move || peripheral.reconnect(generation));
+        }
+    }
 
-        if let Some(address_type) = args.address_type() {
-            let address_type = AddressType::from_str(address_type).unwrap_or_default();
-
+    /// Tries to reconnect until it works, the policy gives up, or the reconnection is stopped by
+    /// a change to the `generation` of the reconnect state.
+    fn reconnect(&self, generation: u64) {
+        let stopped = move |r: &Reconnect| r.generation != generation;
+        let mut attempts = 0;
+        let reconnected = loop {
+            let state = self.reconnect.get();
+            let policy = match state.policy {
+                Some(policy) if !stopped(&state) => policy,
+                _ => break None,
+            };
+            if !policy.allows(attempts) {
+                break Some(false);
+            }
+            attempts += 1;
+            let delay = policy.delay(attempts);
             debug!(
-                "Updating \"{}\" address type \"{:?}\"",
-                self.address, address_type
+                "Reconnecting to \"{}\" in {:?}, attempt {}",
+                self.address, delay, attempts
             );
+            if block_on(with_timeout(self.reconnect.wait_until(stopped), delay)).is_some() {
+                break None;
+            }
+            match block_on(self.reconnect_once()) {
+                Ok(()) => break Some(true),
+                Err(e) => debug!("Could not reconnect to \"{}\": {}", self.address, e),
+            }
+        };
+        self.reconnect.update(|r| r.running = false);
+        match reconnected {
+            Some(true) => {
+                info!("Reconnected to \"{}\"", self.address);
+                self.adapter
+                    .emit(CentralEvent::DeviceReconnected(self.address));
+            }
+            Some(false) => {
+                warn!(
+                    "Gave up reconnecting to \"{}\" after {} attempts",
+                    self.address, attempts
+                );
+                self.adapter.emit(CentralEvent::DeviceReconnectFailed {
+                    address: self.address,
+                    attempts,
+                });
+            }
+            None => debug!("Stopped reconnecting to \"{}\"", self.address),
+        }
+    }
 
-            properties.address_type = address_type;
-            emit_updated = true;
+    /// Connects, waits for the services to be resolved, and restores the subscriptions. A
+    /// subscription that can't be restored is reported, but doesn't fail the reconnection.
+    async fn reconnect_once(&self) -> Result<()> {
+        let timeouts = self.timeouts();
+        self.connect_with_timeout(timeouts.connect).await?;
+        self.discover_characteristics_with_timeout(timeouts.service_discovery)
+            .await?;
+        let subscriptions: Vec<Characteristic> = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        for characteristic in subscriptions {
+            if let Err(e) = AsyncPeripheral::subscribe(self, &characteristic).await {
+                self.adapter.emit_error(Some(self.address), e);
+            }
         }
+        Ok(())
+    }
+
+    /// Records the bond state, and returns the event reporting it if it changed.
+    fn update_bond_state(&self, args: &OrgBluezDevice1Properties) -> Option<CentralEvent> {
+        let mut bond = self.bond.lock().unwrap();
+        let previous = *bond;
+        let mut current = previous.unwrap_or_default();
+        current.paired = args.paired().unwrap_or(current.paired);
+        current.trusted = args.trusted().unwrap_or(current.trusted);
+        current.blocked = args.blocked().unwrap_or(current.blocked);
+        current.legacy_pairing = args.legacy_pairing().unwrap_or(current.legacy_pairing);
+        *bond = Some(current);
 
-        if let Some(rssi) = args.rssi() {
-            let rssi = rssi as i8;
-            debug!("Updating \"{}\" RSSI \"{:?}\"", self.address, rssi);
-            properties.tx_power_level = Some(rssi);
-            emit_updated = true;
+        // The state the device was first seen with isn't a change.
+        if matches!(previous, Some(previous) if previous != current) {
+            debug!("Updating \"{}\" bond state {:?}", self.address, current);
+            Some(CentralEvent::DeviceBondChanged {
+                address: self.address,
+                bond: current,
+            })
+        } else {
+            None
         }
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        *self.timeouts.lock().unwrap()
+    }
 
-        if emit_updated {
-            self.adapter.emit(CentralEvent::DeviceUpdated(self.address));
+    /// Runs `operation`, failing with `Error::TimedOut` if it takes longer than `timeout` or is
+    /// canceled through a [CancelHandle]. BlueZ would carry on with the operation regardless, so
+    /// the `abort` method of the device, such as `Disconnect`, is called then.
+    async fn cancelable<T, F>(
+        &self,
+        timeout: Duration,
+        abort: Option<&'static str>,
+        operation: F,
+    ) -> Result<T>
+    where
+        F: Future<Output = Result<T>> + Send,
+    {
+        let started = Instant::now();
+        let generation = self.canceled.get();
+        let canceled = self.canceled.wait_until(move |c| *c != generation);
+        let error = match with_timeout(future::select(Box::pin(operation), canceled), timeout).await
+        {
+            Some(Either::Left((result, _))) => return result,
+            Some(Either::Right(_)) => {
+                debug!("Canceled operation on \"{}\"", self.address);
+                Error::TimedOut(started.elapsed())
+            }
+            None => Error::TimedOut(timeout),
+        };
+        if let Some(abort) = abort {
+            let timeout = self.timeouts().calls;
+            let aborted =
+                self.calls
+                    .call::<_, ()>(&self.path, ORG_BLUEZ_DEVICE1_NAME, abort, (), timeout);
+            if let Err(e) = aborted.await {
+                debug!("{} failed on \"{}\": {}", abort, self.address, e);
+            }
         }
+        Err(error)
     }
 
-    pub fn proxy(&self) -> Proxy<&SyncConnection> {
-        self.connection
-            .with_proxy(BLUEZ_DEST, &self.path, DEFAULT_TIMEOUT)
+    /// The object path of a characteristic, which fails if the device no longer has it.
+    fn characteristic_path(&self, characteristic: &Characteristic) -> Result<String> {
+        let attributes = self.attributes.lock().unwrap();
+        attributes
+            .find(
+                characteristic.value_handle,
+                AttributeType::Characteristic,
+                characteristic.uuid,
+            )
+            .map(|a| a.path.clone())
+            .ok_or(Error::CharacteristicNotFound(characteristic.uuid))
     }
 
-    pub fn proxy_for(&self, characteristic: &Characteristic) -> Option<Proxy<&SyncConnection>> {
-        let map = self.attributes_map.lock().unwrap();
-        map.get(&characteristic.value_handle).map(|(path, _h, _c)| {
-            self.connection
-                .with_proxy(BLUEZ_DEST, path.clone(), DEFAULT_TIMEOUT)
-        })
+    /// The object path of a descriptor, which fails if the device no longer has it.
+    fn descriptor_path(&self, descriptor: &Descriptor) -> Result<String> {
+        let attributes = self.attributes.lock().unwrap();
+        attributes
+            .find(
+                descriptor.handle,
+                AttributeType::Descriptor,
+                descriptor.uuid,
+            )
+            .map(|a| a.path.clone())
+            .ok_or(Error::DescriptorNotFound(descriptor.uuid))
     }
 }
 
 assert_impl_all!(Peripheral: Sync, Send);
 
+/// Converts the error of a call that was given `timeout`, reporting a missing reply as
+/// `Error::TimedOut`.
+fn call_error(timeout: Duration) -> impl FnOnce(dbus::Error) -> Error {
+    move |error| match error.name() {
+        Some("org.freedesktop.DBus.Error.NoReply") => Error::TimedOut(timeout),
+        _ => error.into(),
+    }
+}
+
 impl Display for Peripheral {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         let connected = if self.is_connected() {
@@ -408,16 +925,17 @@ impl Debug for Peripheral {
             ""
         };
         let properties = self.properties.lock().unwrap();
-        let characteristics = self.characteristics.lock().unwrap();
+        let characteristics = self.attributes.lock().unwrap().characteristics();
         write!(
             f,
             "{} properties: {:?}, characteristics: {:?} {}",
-            self.address, *properties, *characteristics, connected
+            self.address, *properties, characteristics, connected
         )
     }
 }
 
-impl ApiPeripheral for Peripheral {
+#[async_trait]
+impl AsyncPeripheral for Peripheral {
     fn address(&self) -> BDAddr {
         self.address.clone()
     }
@@ -428,141 +946,207 @@ impl ApiPeripheral for Peripheral {
     }
 
     fn characteristics(&self) -> BTreeSet<Characteristic> {
-        let l = self.characteristics.lock().unwrap();
-        l.clone()
+        self.attributes.lock().unwrap().characteristics()
     }
 
-    fn is_connected(&self) -> bool {
-        *self.state.0.lock().unwrap() >= PeripheralState::Connected
+    fn services(&self) -> BTreeSet<Service> {
+        self.attributes.lock().unwrap().services()
     }
 
-    fn connect(&self) -> Result<()> {
-        let started = Instant::now();
-        if let Err(error) = self.proxy().connect() {
-            match error.name() {
-                Some("org.bluez.Error.AlreadyConnected") => Ok(()),
-                Some("org.bluez.Error.Failed") => {
-                    error!(
-                        "BlueZ Failed to connect to \"{:?}\": {}",
-                        self.address,
-                        error.message().unwrap()
-                    );
-                    Err(Error::NotConnected)
-                }
-                Some("org.freedesktop.DBus.Error.NoReply") => Err(Error::TimedOut(DEFAULT_TIMEOUT)),
-                _ => Err(error)?,
-            }
-        } else {
-            Ok(())
-        }?;
-        // For somereason, BlueZ may return an Okay result before the the device is actually connected...
-        // So lets wait for the "connected" property to update to true
-        let (ref lock, ref cvar) = *self.state;
-        let timeout = DEFAULT_TIMEOUT - Instant::now().duration_since(started);
-        // Map the result of the wait_timeout_while(...) to match our Result<()>
-        cvar.wait_timeout_while(lock.lock().unwrap(), timeout, |c| {
-            *c < PeripheralState::Connected
-        })
-        .map_err(|_| Error::TimedOut(DEFAULT_TIMEOUT))
-        .map(|_| ())
+    fn descriptors(&self, characteristic: &Characteristic) -> BTreeSet<Descriptor> {
+        self.attributes.lock().unwrap().descriptors(characteristic)
     }
 
-    fn disconnect(&self) -> Result<()> {
-        Ok(self.proxy().disconnect()?)
+    fn is_connected(&self) -> bool {
+        self.state.get() >= PeripheralState::Connected
     }
 
-    fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
-        let (ref lock, ref cvar) = *self.state;
-        trace!("Waiting for all services to be resolved");
-        let _guard = cvar
-            .wait_while(lock.lock().unwrap(), |b| *b == PeripheralState::Connected)
-            .unwrap();
+    async fn connect(&self) -> Result<()> {
+        self.connect_with_timeout(self.timeouts().connect).await
+    }
 
-        if *_guard == PeripheralState::NotConnected {
-            return Err(Error::NotConnected);
-        }
+    async fn disconnect(&self) -> Result<()> {
+        self.reconnect.update(|r| {
+            r.disconnecting = true;
+            r.generation += 1;
+        });
+        let timeout = self.timeouts().calls;
+        self.calls
+            .call(
+                &self.path,
+                ORG_BLUEZ_DEVICE1_NAME,
+                "Disconnect",
+                (),
+                timeout,
+            )
+            .await
+            .map_err(call_error(timeout))
+    }
 
-        debug!("All services are now resolved!");
+    async fn pair(&self) -> Result<()> {
+        self.pair_with_timeout(self.timeouts().pairing).await
+    }
 
-        Ok(self
-            .characteristics
-            .lock()
-            .unwrap()
-            .clone()
-            .into_iter()
-            .collect())
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        self.discover_characteristics_with_timeout(self.timeouts().service_discovery)
+            .await
     }
 
-    fn write(
+    async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        let mut options: PropMap = HashMap::new();
-        options.insert(
-            "type".to_string(),
-            Variant(Box::new(
-                match write_type {
-                    WriteType::WithResponse => "request",
-                    WriteType::WithoutResponse => "command",
-                }
-                .to_string(),
-            )),
-        );
-        Ok(self
-            .proxy_for(&characteristic)
-            .map(|p| p.write_value(Vec::from(data), options))
-            .ok_or(Error::NotSupported("write".to_string()))??)
+        let path = self.characteristic_path(characteristic)?;
+        let timeout = self.timeouts().gatt;
+        let call = {
+            let mut options: PropMap = HashMap::new();
+            options.insert(
+                "type".to_string(),
+                Variant(Box::new(
+                    match write_type {
+                        WriteType::WithResponse => "request",
+                        WriteType::WithoutResponse => "command",
+                    }
+                    .to_string(),
+                )),
+            );
+            self.calls.call(
+                &path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "WriteValue",
+                (Vec::from(data), options),
+                timeout,
+            )
+        };
+        call.await.map_err(call_error(timeout))
     }
 
-    fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        Ok(self
-            .proxy_for(&characteristic)
-            .map(|p| p.read_value(HashMap::new()))
-            .ok_or(Error::NotSupported("read".to_string()))??)
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let path = self.characteristic_path(characteristic)?;
+        let timeout = self.timeouts().gatt;
+        let (value,): (Vec<u8>,) = self
+            .calls
+            .call(
+                &path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "ReadValue",
+                (PropMap::new(),),
+                timeout,
+            )
+            .await
+            .map_err(call_error(timeout))?;
+        Ok(value)
     }
 
-    // Is this looking for a characteristic with a descriptor? or a service with a characteristic?
-    fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>> {
-        if let Some(characteristic) =
-            self.attributes_map
-                .lock()
-                .unwrap()
-                .iter()
-                .find_map(|(_k, (_p, _h, c))| {
-                    if c.uuid == uuid
-                        && c.value_handle >= characteristic.start_handle
-                        && c.value_handle <= characteristic.end_handle
-                    {
-                        Some(c)
-                    } else {
-                        None
-                    }
-                })
-        {
-            self.read(characteristic)
-        } else {
-            Err(Error::NotSupported("read_by_type".to_string()))
-        }
+    async fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>> {
+        self.characteristic_path(characteristic)?;
+        let found = self
+            .attributes
+            .lock()
+            .unwrap()
+            .find_by_type(characteristic, uuid)
+            .map(|a| (a.path.clone(), a.handle.typ));
+        let (path, typ) = found.ok_or_else(|| Error::NotSupported("read_by_type".to_string()))?;
+        let timeout = self.timeouts().gatt;
+        let interface = match typ {
+            AttributeType::Descriptor => ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+            _ => ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+        };
+        let (value,): (Vec<u8>,) = self
+            .calls
+            .call(&path, interface, "ReadValue", (PropMap::new(),), timeout)
+            .await
+            .map_err(call_error(timeout))?;
+        Ok(value)
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let path = self.descriptor_path(descriptor)?;
+        let timeout = self.timeouts().gatt;
+        let (value,): (Vec<u8>,) = self
+            .calls
+            .call(
+                &path,
+                ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+                "ReadValue",
+                (PropMap::new(),),
+                timeout,
+            )
+            .await
+            .map_err(call_error(timeout))?;
+        Ok(value)
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let path = self.descriptor_path(descriptor)?;
+        let timeout = self.timeouts().gatt;
+        self.calls
+            .call(
+                &path,
+                ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+                "WriteValue",
+                (Vec::from(data), PropMap::new()),
+                timeout,
+            )
+            .await
+            .map_err(call_error(timeout))
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let path = self.characteristic_path(characteristic)?;
+        let timeout = self.timeouts().gatt;
+        self.calls
+            .call::<_, ()>(
+                &path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "StartNotify",
+                (),
+                timeout,
+            )
+            .await
+            .map_err(call_error(timeout))?;
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .insert(characteristic.value_handle, characteristic.clone());
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let path = self.characteristic_path(characteristic)?;
+        let timeout = self.timeouts().gatt;
+        self.calls
+            .call::<_, ()>(
+                &path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "StopNotify",
+                (),
+                timeout,
+            )
+            .await
+            .map_err(call_error(timeout))?;
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .remove(&characteristic.value_handle);
+        Ok(())
+    }
+
+    fn on_notification(&self, handler: NotificationHandler) -> NotificationHandlerId {
+        self.notification_handlers.add(handler)
     }
 
-    fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        Ok(self
-            .proxy_for(characteristic)
-            .ok_or(Error::NotSupported("subscribe".to_string()))?
-            .start_notify()?)
+    fn remove_notification_handler(&self, id: NotificationHandlerId) -> bool {
+        self.notification_handlers.remove(id)
     }
 
-    fn unsubscribe(&self, _characteristic: &Characteristic) -> Result<()> {
-        Ok(self
-            .proxy_for(_characteristic)
-            .ok_or(Error::NotSupported("unsubscribe".to_string()))?
-            .stop_notify()?)
+    fn notifications(&self, characteristic: &Characteristic) -> NotificationReceiver {
+        NotificationReceiver::new(&self.notification_handlers, characteristic)
     }
 
-    fn on_notification(&self, handler: NotificationHandler) {
-        let mut list = self.notification_handlers.lock().unwrap();
-        list.push(handler);
+    fn set_notification_queue(&self, capacity: usize, overflow: OverflowPolicy) -> Result<()> {
+        self.notification_handlers.set_queue(capacity, overflow)
     }
 }
@@ -0,0 +1,157 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// How a peripheral reconnects on its own after an unexpected disconnection, set with
+/// `Peripheral::set_reconnect_policy`.
+///
+/// Attempts are spaced with an exponential backoff: the first one waits `initial_delay`, and each
+/// following one waits `multiplier` times longer, up to `max_delay`. Every delay is then moved by
+/// up to `jitter` of itself in either direction, so devices that dropped together don't all
+/// reconnect at the same time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    /// The number of attempts before giving up with `CentralEvent::DeviceReconnectFailed`, or
+    /// `None` to keep trying until the policy is removed.
+    pub max_attempts: Option<u32>,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// A fraction of each delay, between 0 and 1.
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: Some(10),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Whether another attempt is allowed after `attempts` failed ones.
+    pub(crate) fn allows(&self, attempts: u32) -> bool {
+        !matches!(self.max_attempts, Some(max) if attempts >= max)
+    }
+
+    /// The time to wait before the attempt numbered `attempt`, starting from 1.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, random())
+    }
+
+    /// The delay before `attempt`, where `random` is between 0 and 1, and picks the jitter.
+    fn delay_with(&self, attempt: u32, random: f64) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_delay.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        let delay = backoff.min(self.max_delay.as_secs_f64());
+        let jitter = if self.jitter.is_nan() {
+            0.0
+        } else {
+            self.jitter.clamp(0.0, 1.0)
+        };
+        // Delays that aren't a number, or are too long for a `Duration`, wait as long as allowed.
+        Duration::try_from_secs_f64(delay * (1.0 + jitter * (2.0 * random - 1.0)))
+            .unwrap_or(self.max_delay)
+    }
+}
+
+/// A number between 0 and 1 that is good enough for jitter, from the random keys std generates
+/// for hash maps.
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: Some(4),
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn delays_back_off_exponentially_up_to_the_maximum() {
+        let policy = policy();
+        let delays: Vec<u128> = (1..=5)
+            .map(|attempt| policy.delay_with(attempt, 0.5).as_millis())
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+        assert_eq!(policy.delay_with(u32::MAX, 0.5), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jitter_spreads_delays_around_the_backoff() {
+        let policy = policy();
+        assert_eq!(policy.delay_with(2, 0.0), Duration::from_millis(100));
+        assert_eq!(policy.delay_with(2, 1.0), Duration::from_millis(300));
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn unusable_fields_dont_panic() {
+        let nan_jitter = ReconnectPolicy {
+            jitter: f64::NAN,
+            ..policy()
+        };
+        assert_eq!(nan_jitter.delay_with(2, 1.0), Duration::from_millis(200));
+        let unbounded = ReconnectPolicy {
+            max_attempts: None,
+            max_delay: Duration::MAX,
+            ..policy()
+        };
+        assert_eq!(unbounded.delay_with(u32::MAX, 1.0), Duration::MAX);
+        let nan_multiplier = ReconnectPolicy {
+            multiplier: f64::NAN,
+            ..policy()
+        };
+        assert_eq!(
+            nan_multiplier.delay_with(3, 0.5),
+            Duration::from_millis(100)
+        );
+        let infinite_multiplier = ReconnectPolicy {
+            initial_delay: Duration::from_secs(0),
+            multiplier: f64::INFINITY,
+            ..policy()
+        };
+        assert_eq!(
+            infinite_multiplier.delay_with(3, 0.5),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn attempts_are_limited() {
+        let policy = policy();
+        assert!(policy.allows(3));
+        assert!(!policy.allows(4));
+        let unlimited = ReconnectPolicy {
+            max_attempts: None,
+            ..policy
+        };
+        assert!(unlimited.allows(u32::MAX));
+    }
+}
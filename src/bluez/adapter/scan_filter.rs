@@ -0,0 +1,154 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{Error, Result};
+use dbus::arg::{PropMap, Variant};
+use uuid::Uuid;
+
+/// The kind of devices a scan should look for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// Interleaved scan for both Low Energy and BR/EDR devices, depending on what the adapter
+    /// supports.
+    Auto,
+    /// Only look for BR/EDR ("classic") devices.
+    BrEdr,
+    /// Only look for Low Energy devices.
+    Le,
+}
+
+impl Transport {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Auto => "auto",
+            Transport::BrEdr => "bredr",
+            Transport::Le => "le",
+        }
+    }
+}
+
+/// Discovery filter applied by BlueZ while scanning. This maps onto the parameters of
+/// `org.bluez.Adapter1.SetDiscoveryFilter`, see the
+/// [BlueZ documentation](https://git.kernel.org/pub/scm/bluetooth/bluez.git/tree/doc/adapter-api.txt)
+/// for the details of each field.
+///
+/// Fields left empty are not sent to BlueZ, so `ScanFilter::default()` clears any previously set
+/// filter.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScanFilter {
+    /// Only report devices advertising at least one of these service UUIDs.
+    pub services: Vec<Uuid>,
+    /// Only report devices with an RSSI (in dBm) above this threshold. Can't be combined with
+    /// `pathloss`.
+    pub rssi: Option<i16>,
+    /// Only report devices with a pathloss (in dB) below this threshold. Can't be combined with
+    /// `rssi`.
+    pub pathloss: Option<u16>,
+    /// The kind of devices to scan for. `None` leaves BlueZ's default, which is `Transport::Auto`.
+    pub transport: Option<Transport>,
+    /// When `Some(true)`, every advertisement is reported, even when the advertising data hasn't
+    /// changed. When `Some(false)`, duplicates are filtered out. `None` leaves BlueZ's default.
+    pub duplicate_data: Option<bool>,
+    /// Only report devices whose address or name starts with this pattern.
+    pub pattern: Option<String>,
+    /// Scan passively, only listening to advertisements instead of asking devices for their scan
+    /// response. BlueZ always scans actively when discovering, so a filter asking for passive
+    /// scanning is rejected with `Error::NotSupported`.
+    pub passive: bool,
+}
+
+impl ScanFilter {
+    /// Converts the filter into the dictionary expected by `SetDiscoveryFilter`.
+    pub(crate) fn to_prop_map(&self) -> Result<PropMap> {
+        if self.passive {
+            return Err(Error::NotSupported("Passive scanning".to_string()));
+        }
+        if self.rssi.is_some() && self.pathloss.is_some() {
+            return Err(Error::Other(
+                "A scan filter can't have both an RSSI and a pathloss threshold".to_string(),
+            ));
+        }
+
+        let mut map = PropMap::new();
+        if !self.services.is_empty() {
+            let uuids: Vec<String> = self.services.iter().map(|u| u.to_string()).collect();
+            map.insert("UUIDs".to_string(), Variant(Box::new(uuids)));
+        }
+        if let Some(rssi) = self.rssi {
+            map.insert("RSSI".to_string(), Variant(Box::new(rssi)));
+        }
+        if let Some(pathloss) = self.pathloss {
+            map.insert("Pathloss".to_string(), Variant(Box::new(pathloss)));
+        }
+        if let Some(transport) = self.transport {
+            map.insert(
+                "Transport".to_string(),
+                Variant(Box::new(transport.as_str().to_string())),
+            );
+        }
+        if let Some(duplicate_data) = self.duplicate_data {
+            map.insert("DuplicateData".to_string(), Variant(Box::new(duplicate_data)));
+        }
+        if let Some(pattern) = &self.pattern {
+            map.insert("Pattern".to_string(), Variant(Box::new(pattern.clone())));
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::bleuuid::uuid_from_u16;
+    use dbus::arg::prop_cast;
+
+    #[test]
+    fn default_filter_is_empty() {
+        assert!(ScanFilter::default().to_prop_map().unwrap().is_empty());
+    }
+
+    #[test]
+    fn filter_to_prop_map() {
+        let filter = ScanFilter {
+            services: vec![uuid_from_u16(0x180D)],
+            rssi: Some(-70),
+            transport: Some(Transport::Le),
+            duplicate_data: Some(false),
+            pattern: Some("Light".to_string()),
+            ..Default::default()
+        };
+        let map = filter.to_prop_map().unwrap();
+        assert_eq!(
+            prop_cast::<Vec<String>>(&map, "UUIDs"),
+            Some(&vec!["0000180d-0000-1000-8000-00805f9b34fb".to_string()])
+        );
+        assert_eq!(prop_cast::<i16>(&map, "RSSI"), Some(&-70));
+        assert_eq!(prop_cast::<String>(&map, "Transport").unwrap(), "le");
+        assert_eq!(prop_cast::<bool>(&map, "DuplicateData"), Some(&false));
+        assert_eq!(prop_cast::<String>(&map, "Pattern").unwrap(), "Light");
+        assert!(!map.contains_key("Pathloss"));
+    }
+
+    #[test]
+    fn rssi_and_pathloss_are_exclusive() {
+        let filter = ScanFilter {
+            rssi: Some(-70),
+            pathloss: Some(10),
+            ..Default::default()
+        };
+        assert!(filter.to_prop_map().is_err());
+    }
+
+    #[test]
+    fn passive_scanning_is_rejected() {
+        let filter = ScanFilter {
+            passive: true,
+            ..Default::default()
+        };
+        assert!(matches!(filter.to_prop_map(), Err(Error::NotSupported(_))));
+    }
+}
@@ -0,0 +1,40 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::bluez::DEFAULT_TIMEOUT;
+use std::time::Duration;
+
+/// How long an adapter and its peripherals wait for BlueZ, or for a device, before failing with
+/// `Error::TimedOut`. Set them with `Adapter::set_timeouts`, which applies to the peripherals
+/// already discovered as well.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Connecting to a device, until it is reported as connected.
+    pub connect: Duration,
+    /// Waiting for the services of a connected device to be resolved, in
+    /// `discover_characteristics`.
+    pub service_discovery: Duration,
+    /// Reading, writing and subscribing to characteristics and descriptors.
+    pub gatt: Duration,
+    /// Pairing, which can wait for the user to enter or confirm a passkey on either side, so it
+    /// gets more time than other calls by default.
+    pub pairing: Duration,
+    /// Every other call, such as starting a scan or disconnecting.
+    pub calls: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: DEFAULT_TIMEOUT,
+            service_discovery: DEFAULT_TIMEOUT,
+            gatt: DEFAULT_TIMEOUT,
+            pairing: Duration::from_secs(60),
+            calls: DEFAULT_TIMEOUT,
+        }
+    }
+}
@@ -0,0 +1,339 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Method calls and signal matches on the connection shared by a [Manager] and its adapters.
+//!
+//! Only the [Dispatcher] thread reads from the connection. Calls are sent right away, and their
+//! reply is routed back by serial from the dispatcher, either to a future or to a thread blocked
+//! waiting for it. Nothing here depends on a particular async runtime.
+//!
+//! [Manager]: super::manager::Manager
+//! [Dispatcher]: super::dispatcher::Dispatcher
+
+use super::{dispatcher::Wakeup, BLUEZ_DEST};
+use dbus::{
+    arg::{AppendAll, ReadAll},
+    blocking::{BlockingSender, Proxy, SyncConnection},
+    channel::{MatchingReceiver, Sender, Token},
+    message::{MatchRule, MessageType},
+    Message,
+};
+use futures::{
+    channel::oneshot,
+    future::{self, Either},
+};
+use futures_timer::Delay;
+use parking_lot::ReentrantMutex;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, ThreadId},
+    time::Duration,
+};
+
+/// Calls made to the bus itself, to add and remove match rules, don't wait for a remote device.
+const BUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+type ReplyHandler = Box<dyn FnOnce(Message) + Send>;
+
+type PendingCalls = Arc<Mutex<HashMap<u32, ReplyHandler>>>;
+
+#[derive(Clone)]
+pub(crate) struct AsyncConnection {
+    connection: Arc<ReentrantMutex<SyncConnection>>,
+    pending: PendingCalls,
+    /// The matches added with `add_match`, whose handlers are dropped once the dispatcher stops.
+    matches: Arc<Mutex<HashSet<Token>>>,
+    dispatcher: Arc<Mutex<Option<ThreadId>>>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl AsyncConnection {
+    /// Wraps a connection that is processed by a dispatcher thread, which must call
+    /// `dispatcher_started` and `dispatcher_stopped`, and poll `wakeup` along with the connection.
+    pub fn new(connection: Arc<ReentrantMutex<SyncConnection>>, wakeup: Arc<Wakeup>) -> Self {
+        let pending: PendingCalls = Arc::new(Mutex::new(HashMap::new()));
+        {
+            let connection = connection.lock();
+            for msg_type in &[MessageType::MethodReturn, MessageType::Error] {
+                let mut rule = MatchRule::new();
+                rule.msg_type = Some(*msg_type);
+                let pending = pending.clone();
+                connection.start_receive(
+                    rule,
+                    Box::new(move |msg, _c| {
+                        let handler = msg
+                            .get_reply_serial()
+                            .and_then(|serial| pending.lock().unwrap().remove(&serial));
+                        if let Some(handler) = handler {
+                            handler(msg);
+                        }
+                        true
+                    }),
+                );
+            }
+        }
+        AsyncConnection {
+            connection,
+            pending,
+            matches: Arc::default(),
+            dispatcher: Arc::new(Mutex::new(None)),
+            wakeup,
+        }
+    }
+
+    /// Records the current thread as the one processing incoming messages.
+    pub fn dispatcher_started(&self) {
+        *self.dispatcher.lock().unwrap() = Some(thread::current().id());
+    }
+
+    /// Records that incoming messages are no longer processed. Signal handlers will never be
+    /// called again, so they are dropped along with whatever they hold, and the calls still
+    /// waiting for a reply fail right away.
+    pub fn dispatcher_stopped(&self) {
+        *self.dispatcher.lock().unwrap() = None;
+        let connection = self.connection.lock();
+        for token in self.matches.lock().unwrap().drain() {
+            connection.stop_receive(token);
+        }
+        self.pending.lock().unwrap().clear();
+    }
+
+    /// Returns a proxy to a BlueZ object, whose blocking calls are answered through the dispatcher.
+    pub fn proxy<'a, P: Into<dbus::Path<'a>>>(
+        &self,
+        path: P,
+        timeout: Duration,
+    ) -> Proxy<'a, &Self> {
+        Proxy::new(BLUEZ_DEST, path, timeout, self)
+    }
+
+    /// Calls a method on a BlueZ object. The returned future doesn't borrow the arguments, and
+    /// resolves to a `NoReply` error if BlueZ doesn't answer within `timeout`, just like blocking
+    /// calls do.
+    pub fn call<A: AppendAll, R: ReadAll>(
+        &self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: A,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<R, dbus::Error>> + Send + 'static {
+        let msg = Message::new_method_call(BLUEZ_DEST, path, interface, member).map(|mut msg| {
+            msg.append_all(args);
+            msg
+        });
+        let this = self.clone();
+        async move {
+            let msg = msg.map_err(|e| dbus::Error::new_failed(&e))?;
+            let mut reply = this.send_with_reply(msg, timeout).await?;
+            reply.as_result()?;
+            reply.read_all()
+        }
+    }
+
+    /// Calls a method on a BlueZ object and waits for the reply on the calling thread, without an
+    /// executor, so it can be used from `Drop` and from within async code.
+    pub fn call_blocking<A: AppendAll, R: ReadAll>(
+        &self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: A,
+        timeout: Duration,
+    ) -> Result<R, dbus::Error> {
+        let path = dbus::Path::new(path).map_err(|e| dbus::Error::new_failed(&e))?;
+        self.proxy(path, timeout)
+            .method_call(interface, member, args)
+    }
+
+    /// Whether this is the dispatcher thread, which runs event handlers and serves exported
+    /// objects. BlueZ can't call back into an object while that thread waits for it.
+    pub fn is_dispatcher_thread(&self) -> bool {
+        *self.dispatcher.lock().unwrap() == Some(thread::current().id())
+    }
+
+    /// Sends a message that expects no reply, such as a signal.
+    pub fn send(&self, msg: Message) -> Result<u32, dbus::Error> {
+        let serial = self
+            .connection
+            .lock()
+            .send(msg)
+            .map_err(|_| dbus::Error::new_failed("Could not send message"))?;
+        // The message may not fit in the socket, in which case the dispatcher has to write it.
+        self.wakeup.notify();
+        Ok(serial)
+    }
+
+    /// Calls `f` with the arguments of every signal matching `rule`, until it returns false or the
+    /// match is removed with [AsyncConnection::remove_match]. Unlike `SyncConnection::add_match`,
+    /// this never reads from the connection outside of the dispatcher.
+    pub fn add_match<S: ReadAll, F>(
+        &self,
+        rule: MatchRule<'static>,
+        mut f: F,
+    ) -> Result<Token, dbus::Error>
+    where
+        F: FnMut(S, &SyncConnection, &Message) -> bool + Send + Sync + 'static,
+    {
+        let match_str = rule.match_str();
+        let token = self.connection.lock().start_receive(
+            rule,
+            Box::new(move |msg, c| match S::read(&mut msg.iter_init()) {
+                Ok(args) => f(args, c, &msg),
+                // Not the signal we're after, keep the match.
+                Err(_) => true,
+            }),
+        );
+        if let Err(e) = self.call_bus("AddMatch", &match_str) {
+            self.connection.lock().stop_receive(token);
+            return Err(e);
+        }
+        self.matches.lock().unwrap().insert(token);
+        Ok(token)
+    }
+
+    /// Removes a match added with [AsyncConnection::add_match].
+    pub fn remove_match(&self, token: Token) -> Result<(), dbus::Error> {
+        self.matches.lock().unwrap().remove(&token);
+        let (rule, _) = self
+            .connection
+            .lock()
+            .stop_receive(token)
+            .ok_or_else(|| dbus::Error::new_failed("No match with that id found"))?;
+        self.call_bus("RemoveMatch", &rule.match_str())
+    }
+
+    fn call_bus(&self, member: &str, match_str: &str) -> Result<(), dbus::Error> {
+        let msg = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            member,
+        )
+        .map_err(|e| dbus::Error::new_failed(&e))?
+        .append1(match_str);
+        self.send_with_reply_and_block(msg, BUS_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// Whether replies have to be read right here, as nothing else routes them. This happens when
+    /// a call is made from an event or notification handler, as the dispatcher thread can't wait
+    /// for itself, or once the dispatcher has stopped.
+    fn must_read_reply(&self) -> bool {
+        let dispatcher = *self.dispatcher.lock().unwrap();
+        dispatcher.is_none() || dispatcher == Some(thread::current().id())
+    }
+
+    /// Sends `msg`, and has the dispatcher pass its reply to `handler`.
+    fn start_call(&self, msg: Message, handler: ReplyHandler) -> Result<u32, dbus::Error> {
+        let serial = {
+            // Holding the lock keeps the dispatcher from processing the reply before the call is
+            // registered.
+            let connection = self.connection.lock();
+            let serial = connection
+                .send(msg)
+                .map_err(|_| dbus::Error::new_failed("Could not send message to BlueZ"))?;
+            self.pending.lock().unwrap().insert(serial, handler);
+            serial
+        };
+        self.wakeup.notify();
+        Ok(serial)
+    }
+
+    async fn send_with_reply(
+        &self,
+        msg: Message,
+        timeout: Duration,
+    ) -> Result<Message, dbus::Error> {
+        if self.must_read_reply() {
+            return self
+                .connection
+                .lock()
+                .channel()
+                .send_with_reply_and_block(msg, timeout);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let serial = self.start_call(
+            msg,
+            Box::new(move |reply| {
+                // The caller may have given up waiting, that's fine.
+                let _ = sender.send(reply);
+            }),
+        )?;
+
+        match with_timeout(receiver, timeout).await {
+            Some(Ok(reply)) => Ok(reply),
+            Some(Err(_)) => Err(closed_error()),
+            None => {
+                self.pending.lock().unwrap().remove(&serial);
+                Err(no_reply_error())
+            }
+        }
+    }
+}
+
+impl BlockingSender for AsyncConnection {
+    /// Waits for the reply on the calling thread, without an executor, so blocking calls can be
+    /// made from within async code.
+    fn send_with_reply_and_block(
+        &self,
+        msg: Message,
+        timeout: Duration,
+    ) -> Result<Message, dbus::Error> {
+        if self.must_read_reply() {
+            return self
+                .connection
+                .lock()
+                .channel()
+                .send_with_reply_and_block(msg, timeout);
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let serial = self.start_call(
+            msg,
+            Box::new(move |reply| {
+                let _ = sender.send(reply);
+            }),
+        )?;
+        match receiver.recv_timeout(timeout) {
+            Ok(mut reply) => {
+                reply.as_result()?;
+                Ok(reply)
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(closed_error()),
+            Err(RecvTimeoutError::Timeout) => {
+                self.pending.lock().unwrap().remove(&serial);
+                Err(no_reply_error())
+            }
+        }
+    }
+}
+
+fn closed_error() -> dbus::Error {
+    dbus::Error::new_failed("Connection to BlueZ closed before a reply was received")
+}
+
+fn no_reply_error() -> dbus::Error {
+    dbus::Error::new_custom(
+        "org.freedesktop.DBus.Error.NoReply",
+        "Did not receive a reply from BlueZ in time",
+    )
+}
+
+/// Resolves to the output of `future`, or to `None` if it takes longer than `timeout`.
+pub(crate) async fn with_timeout<F: Future>(future: F, timeout: Duration) -> Option<F::Output> {
+    match future::select(Box::pin(future), Delay::new(timeout)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
@@ -0,0 +1,174 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The thread processing the connection shared by a [Manager] and its adapters.
+//!
+//! The dispatcher sleeps in `poll()` until the connection's socket has something to read, or until
+//! it is woken up through a [Wakeup], so signals are handled as soon as they arrive without
+//! spending any time while idle. Everything arriving on the connection, signals, method calls to
+//! exported objects and the replies to our own calls, is read and routed from this one thread, so
+//! nothing else ever races with it for incoming messages.
+//!
+//! [Manager]: super::manager::Manager
+
+use super::async_connection::AsyncConnection;
+use dbus::blocking::SyncConnection;
+use log::{error, trace};
+use parking_lot::ReentrantMutex;
+use std::{
+    io,
+    os::unix::io::RawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Wakes up the dispatcher from another thread, so it looks at the connection again. This is a
+/// non-blocking pipe, whose read end is polled along with the connection.
+pub(crate) struct Wakeup {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl Wakeup {
+    pub fn new() -> io::Result<Wakeup> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Wakeup {
+            read: fds[0],
+            write: fds[1],
+        })
+    }
+
+    pub fn notify(&self) {
+        // A full pipe already has a wakeup pending, so the result doesn't matter.
+        unsafe { libc::write(self.write, [0u8].as_ptr() as *const libc::c_void, 1) };
+    }
+
+    fn clear(&self) {
+        let mut buffer = [0u8; 64];
+        while unsafe {
+            libc::read(
+                self.read,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        } > 0
+        {}
+    }
+}
+
+impl Drop for Wakeup {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+/// Processes a connection from a new thread until stopped or dropped, or until the connection is
+/// lost.
+pub(crate) struct Dispatcher {
+    should_stop: Arc<AtomicBool>,
+    wakeup: Arc<Wakeup>,
+    thread_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Dispatcher {
+    /// Starts processing `connection`, whose socket is `fd`, routing the replies to the calls made
+    /// through `calls`.
+    pub fn start(
+        connection: Arc<ReentrantMutex<SyncConnection>>,
+        fd: RawFd,
+        calls: AsyncConnection,
+        wakeup: Arc<Wakeup>,
+    ) -> Dispatcher {
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let stop = should_stop.clone();
+        let woken = wakeup.clone();
+        let thread_handle = thread::spawn(move || {
+            calls.dispatcher_started();
+            while let Some(events) = dispatch(&connection, &stop) {
+                let mut fds = [
+                    libc::pollfd {
+                        fd,
+                        events,
+                        revents: 0,
+                    },
+                    libc::pollfd {
+                        fd: woken.read,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                ];
+                // Interruptions and errors alike are sorted out by processing the connection.
+                unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+                woken.clear();
+            }
+            calls.dispatcher_stopped();
+            trace!("Dispatcher stopped");
+        });
+        Dispatcher {
+            should_stop,
+            wakeup,
+            thread_handle: Mutex::new(Some(thread_handle)),
+        }
+    }
+
+    /// Stops processing the connection, and waits for the thread to finish, unless called from
+    /// the thread itself. Calls made from then on read their own reply, but signals are no longer
+    /// handled.
+    pub fn stop(&self) {
+        self.should_stop.store(true, Ordering::SeqCst);
+        self.wakeup.notify();
+        if let Some(handle) = self.thread_handle.lock().unwrap().take() {
+            // The last handle can be dropped by an event handler, on the thread itself.
+            if handle.thread().id() != thread::current().id() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// Handles every message available on the connection, and returns the events to poll for next,
+/// or `None` once the dispatcher has to stop.
+fn dispatch(connection: &ReentrantMutex<SyncConnection>, should_stop: &AtomicBool) -> Option<i16> {
+    // Method calls lock the connection while sending, so they can't race with the processing.
+    let connection = connection.lock();
+    loop {
+        match connection.process(Duration::from_secs(0)) {
+            Ok(true) => continue,
+            Ok(false) => break,
+            Err(e) => {
+                if !should_stop.load(Ordering::SeqCst) {
+                    error!("Stopped processing D-Bus messages: {}", e);
+                }
+                return None;
+            }
+        }
+    }
+    if should_stop.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(if connection.channel().has_messages_to_send() {
+        libc::POLLIN | libc::POLLOUT
+    } else {
+        libc::POLLIN
+    })
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
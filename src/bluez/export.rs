@@ -0,0 +1,143 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Objects exported on the bus for BlueZ to call into, such as GATT applications.
+//!
+//! BlueZ calls these objects from the connection that registered them, so their method calls are
+//! received by the dispatcher thread of the manager's connection. They are answered from a thread
+//! of each export, so a handler waiting for the application or the user doesn't hold up the
+//! signals and replies of every adapter.
+
+use super::{async_connection::AsyncConnection, util::to_dbus_error};
+use crate::{api::BDAddr, Error};
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::SyncConnection,
+    channel::{MatchingReceiver, Token},
+    message::MatchRule,
+    Message, Path,
+};
+use parking_lot::ReentrantMutex;
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+/// The properties of an exported object, by interface.
+pub(crate) type Interfaces = HashMap<String, PropMap>;
+
+/// Serves the method calls made to the objects under a path, until dropped.
+pub(crate) struct Export {
+    connection: Arc<ReentrantMutex<SyncConnection>>,
+    token: Token,
+}
+
+impl Export {
+    /// Answers every method call made to `root`, or to any object below it, with the reply built
+    /// by `handler`. Calls are handled one at a time, in the order they arrive, and the replies
+    /// are sent through `calls`.
+    pub fn new<F>(
+        connection: Arc<ReentrantMutex<SyncConnection>>,
+        calls: AsyncConnection,
+        root: &str,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(&Message) -> Message + Send + 'static,
+    {
+        let (sender, received) = mpsc::channel::<Message>();
+        // The thread stops once the export is dropped, along with the sender.
+        thread::spawn(move || {
+            for msg in received {
+                let reply = handler(&msg);
+                if !msg.get_no_reply() {
+                    let _ = calls.send(reply);
+                }
+            }
+        });
+        let mut rule = MatchRule::new_method_call();
+        rule.path = Some(Path::from(root.to_string()));
+        rule.path_is_namespace = true;
+        let sender = Mutex::new(sender);
+        let token = connection.lock().start_receive(
+            rule,
+            Box::new(move |msg, _c| {
+                let _ = sender.lock().unwrap().send(msg);
+                true
+            }),
+        );
+        Export { connection, token }
+    }
+}
+
+impl Drop for Export {
+    fn drop(&mut self) {
+        self.connection.lock().stop_receive(self.token);
+    }
+}
+
+/// Builds the error reply reporting `error` to the caller of `msg`.
+pub(crate) fn error_reply(msg: &Message, error: &Error) -> Message {
+    let (name, message) = to_dbus_error(error);
+    msg.error(
+        &name.into(),
+        &CString::new(message.replace('\0', "")).unwrap(),
+    )
+}
+
+/// Builds the reply to a call to `org.freedesktop.DBus.Properties`, from the properties of the
+/// called object. Exported objects are read-only, so `Set` is refused.
+pub(crate) fn properties_reply(msg: &Message, mut interfaces: Interfaces) -> Message {
+    let mut properties = |interface: &str| interfaces.remove(interface).unwrap_or_default();
+    match msg.member().as_deref() {
+        Some("GetAll") => match msg.read1::<&str>() {
+            Ok(interface) => msg.method_return().append1(properties(interface)),
+            Err(_) => invalid_args(msg, "Expected an interface name"),
+        },
+        Some("Get") => match msg.read2::<&str, &str>() {
+            Ok((interface, name)) => match properties(interface).remove(name) {
+                Some(value) => msg.method_return().append1(value),
+                None => invalid_args(msg, &format!("No such property '{}'", name)),
+            },
+            Err(_) => invalid_args(msg, "Expected an interface and a property name"),
+        },
+        _ => error_reply(
+            msg,
+            &Error::NotPermitted("Properties are read-only".to_string()),
+        ),
+    }
+}
+
+/// Wraps a property value for a `PropMap`.
+pub(crate) fn variant<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
+}
+
+/// Reads the address of a device from its object path, as passed by BlueZ to exported objects.
+pub(crate) fn device_address(path: &str) -> Option<BDAddr> {
+    path.rsplit('/')
+        .next()
+        .and_then(|name| name.strip_prefix("dev_"))
+        .and_then(|address| address.replace("_", ":").parse().ok())
+}
+
+/// Builds the error reply to a method the called object doesn't have.
+pub(crate) fn unknown_method(msg: &Message) -> Message {
+    msg.error(
+        &"org.freedesktop.DBus.Error.UnknownMethod".into(),
+        &CString::new("Unknown method").unwrap(),
+    )
+}
+
+fn invalid_args(msg: &Message, message: &str) -> Message {
+    msg.error(
+        &"org.freedesktop.DBus.Error.InvalidArgs".into(),
+        &CString::new(message).unwrap(),
+    )
+}
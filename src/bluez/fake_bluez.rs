@@ -0,0 +1,529 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! A stand-in for `bluetoothd`, used by the BlueZ tests.
+//!
+//! [PrivateBus] starts a throwaway `dbus-daemon`, and [FakeBluez] claims the `org.bluez` name on
+//! it. The fake keeps a simple object tree that is served through `ObjectManager` and
+//! `Properties`, records every method call and `PropertiesChanged` signal it receives, and answers
+//! all other method calls with an empty reply unless a custom handler has been installed for them.
+
+use super::BLUEZ_DEST;
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::{
+        stdintf::org_freedesktop_dbus::{
+            ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved,
+            PropertiesPropertiesChanged,
+        },
+        SyncConnection,
+    },
+    channel::{MatchingReceiver, Sender},
+    message::{MatchRule, MessageType, SignalArgs},
+    Message, Path,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const BUS_CONFIG: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:tmpdir=/tmp</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#;
+
+static BUS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A private `dbus-daemon`, killed when dropped.
+pub(crate) struct PrivateBus {
+    daemon: Child,
+    address: String,
+    config: std::path::PathBuf,
+}
+
+impl PrivateBus {
+    /// Starts a new bus.
+    ///
+    /// Panics if `dbus-daemon` can't be run, so the tests using it fail instead of passing
+    /// without running anything.
+    pub fn start() -> PrivateBus {
+        let config = std::env::temp_dir().join(format!(
+            "btleplug-test-bus-{}-{}.conf",
+            std::process::id(),
+            BUS_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::write(&config, BUS_CONFIG).expect("Failed to write the dbus-daemon config");
+        let mut daemon = match Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .arg("--print-address")
+            .arg("--nofork")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                let _ = std::fs::remove_file(&config);
+                panic!(
+                    "dbus-daemon is needed to run the BlueZ tests, but couldn't be started: {}",
+                    e
+                );
+            }
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .expect("Failed to read the address of dbus-daemon");
+        assert!(
+            !address.trim().is_empty(),
+            "dbus-daemon is needed to run the BlueZ tests, but exited without listening"
+        );
+        PrivateBus {
+            daemon,
+            address: address.trim().to_string(),
+            config,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        let _ = std::fs::remove_file(&self.config);
+    }
+}
+
+/// A property value stored by the fake. Only types that are `Send` can be stored, they are turned
+/// into a D-Bus variant when served.
+pub(crate) type FakeValue = Box<dyn RefArg + Send + Sync>;
+
+type Interfaces = BTreeMap<String, BTreeMap<String, FakeValue>>;
+
+type MethodHandler = Box<dyn FnMut(&Message, &FakeBluez) -> Message + Send>;
+
+#[derive(Default)]
+struct FakeState {
+    objects: BTreeMap<String, Interfaces>,
+    calls: Vec<Message>,
+    signals: Vec<Message>,
+    replies: HashMap<u32, mpsc::Sender<Message>>,
+    handlers: HashMap<(String, String), MethodHandler>,
+}
+
+/// The fake `org.bluez` service. Clones share the same state.
+#[derive(Clone)]
+pub(crate) struct FakeBluez {
+    connection: Arc<SyncConnection>,
+    state: Arc<Mutex<FakeState>>,
+    should_stop: Arc<AtomicBool>,
+    thread_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl FakeBluez {
+    pub fn new(bus: &PrivateBus) -> FakeBluez {
+        let connection = Arc::new(SyncConnection::new_address(bus.address()).unwrap());
+        connection
+            .request_name(BLUEZ_DEST, false, true, true)
+            .unwrap();
+        let bluez = FakeBluez {
+            connection: connection.clone(),
+            state: Arc::new(Mutex::new(FakeState::default())),
+            should_stop: Arc::new(AtomicBool::new(false)),
+            thread_handle: Arc::new(Mutex::new(None)),
+        };
+
+        let handler = bluez.clone();
+        connection.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |msg, _c| {
+                handler.handle(msg);
+                true
+            }),
+        );
+
+        // Record the signals sent by everyone else, such as notifications from exported objects.
+        let own_name = connection.unique_name().to_string();
+        let signals = bluez.state.clone();
+        let signal_rule =
+            MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged");
+        connection
+            .add_match_no_cb(&signal_rule.match_str())
+            .unwrap();
+        connection.start_receive(
+            signal_rule,
+            Box::new(move |msg, _c| {
+                if msg.sender().map_or(false, |s| *s != *own_name) {
+                    signals.lock().unwrap().signals.push(msg);
+                }
+                true
+            }),
+        );
+
+        // Route the replies to calls made from other threads, see `call`.
+        for msg_type in &[MessageType::MethodReturn, MessageType::Error] {
+            let mut rule = MatchRule::new();
+            rule.msg_type = Some(*msg_type);
+            let replies = bluez.state.clone();
+            connection.start_receive(
+                rule,
+                Box::new(move |msg, _c| {
+                    let waiter = msg
+                        .get_reply_serial()
+                        .and_then(|serial| replies.lock().unwrap().replies.remove(&serial));
+                    if let Some(waiter) = waiter {
+                        let _ = waiter.send(msg);
+                    }
+                    true
+                }),
+            );
+        }
+
+        let should_stop = bluez.should_stop.clone();
+        *bluez.thread_handle.lock().unwrap() = Some(thread::spawn(move || {
+            while !should_stop.load(Ordering::SeqCst) {
+                // The bus going away ends the test, there's nothing left to serve.
+                if connection.process(Duration::from_millis(10)).is_err() {
+                    break;
+                }
+            }
+        }));
+
+        bluez
+    }
+
+    /// Stops serving requests. Clones share the serving thread, so this stops all of them.
+    pub fn stop(&self) {
+        self.should_stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.thread_handle.lock().unwrap().take() {
+            handle.join().unwrap();
+        }
+    }
+
+    /// Gives up the `org.bluez` name, as `bluetoothd` stopping does. The objects are kept, so
+    /// they're served again by [FakeBluez::acquire_name].
+    pub fn release_name(&self) {
+        self.call(bus_call("ReleaseName").append1(BLUEZ_DEST))
+            .unwrap();
+    }
+
+    /// Takes the `org.bluez` name back after [FakeBluez::release_name], as `bluetoothd` starting
+    /// does.
+    pub fn acquire_name(&self) {
+        self.call(bus_call("RequestName").append2(BLUEZ_DEST, 0u32))
+            .unwrap();
+    }
+
+    /// Adds an `org.bluez.Adapter1` object at `/org/bluez/<name>`.
+    pub fn add_adapter(&self, name: &str, address: &str) -> String {
+        let path = format!("/org/bluez/{}", name);
+        self.add_object(
+            &path,
+            "org.bluez.Adapter1",
+            vec![
+                ("Address", Box::new(address.to_string())),
+                ("Name", Box::new(name.to_string())),
+                ("Alias", Box::new(name.to_string())),
+                ("Powered", Box::new(true)),
+                ("Discoverable", Box::new(false)),
+                ("Discovering", Box::new(false)),
+            ],
+        );
+        path
+    }
+
+    /// Adds (or extends) an object with an interface and its properties.
+    pub fn add_object(&self, path: &str, interface: &str, properties: Vec<(&str, FakeValue)>) {
+        let mut state = self.state.lock().unwrap();
+        let interfaces = state.objects.entry(path.to_string()).or_default();
+        let props = interfaces.entry(interface.to_string()).or_default();
+        for (name, value) in properties {
+            props.insert(name.to_string(), value);
+        }
+    }
+
+    /// Announces an object added with [FakeBluez::add_object] through `InterfacesAdded`, the way
+    /// BlueZ reports new adapters and devices.
+    pub fn announce(&self, path: &str) {
+        let interfaces = self
+            .managed_objects()
+            .remove(&Path::from(path))
+            .unwrap_or_default();
+        let signal = ObjectManagerInterfacesAdded {
+            object: Path::from(path.to_string()),
+            interfaces,
+        };
+        self.send(signal.to_emit_message(&Path::from("/")));
+    }
+
+    /// Removes an object, and reports it through `InterfacesRemoved`.
+    pub fn remove_object(&self, path: &str) {
+        let removed = self.state.lock().unwrap().objects.remove(path);
+        let signal = ObjectManagerInterfacesRemoved {
+            object: Path::from(path.to_string()),
+            interfaces: removed.map_or_else(Vec::new, |i| i.keys().cloned().collect()),
+        };
+        self.send(signal.to_emit_message(&Path::from("/")));
+    }
+
+    /// Sets a property on an object, without emitting any signal.
+    pub fn set_property(&self, path: &str, interface: &str, name: &str, value: FakeValue) {
+        self.add_object(path, interface, vec![(name, value)]);
+    }
+
+    /// Reports changed properties of an object through `PropertiesChanged`, without storing them.
+    pub fn emit_properties_changed(
+        &self,
+        path: &str,
+        interface: &str,
+        properties: Vec<(&str, FakeValue)>,
+    ) {
+        let signal = PropertiesPropertiesChanged {
+            interface_name: interface.to_string(),
+            changed_properties: properties
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), Variant(value as Box<dyn RefArg>)))
+                .collect(),
+            invalidated_properties: vec![],
+        };
+        self.send(signal.to_emit_message(&Path::from(path.to_string())));
+    }
+
+    /// Installs a handler for a method, replacing the default empty reply.
+    pub fn on_method<F>(&self, interface: &str, member: &str, handler: F)
+    where
+        F: FnMut(&Message, &FakeBluez) -> Message + Send + 'static,
+    {
+        self.state.lock().unwrap().handlers.insert(
+            (interface.to_string(), member.to_string()),
+            Box::new(handler),
+        );
+    }
+
+    /// Returns copies of every call received for the given method, in order.
+    pub fn calls(&self, member: &str) -> Vec<Message> {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .iter()
+            .filter(|m| m.member().map_or(false, |m| &*m == member))
+            .map(|m| m.duplicate().unwrap())
+            .collect()
+    }
+
+    /// Returns copies of every `PropertiesChanged` signal received from other connections, in
+    /// order.
+    pub fn signals(&self) -> Vec<Message> {
+        self.state
+            .lock()
+            .unwrap()
+            .signals
+            .iter()
+            .map(|m| m.duplicate().unwrap())
+            .collect()
+    }
+
+    /// Returns the names of every method called so far, in order.
+    pub fn call_names(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .calls
+            .iter()
+            .filter_map(|m| m.member().map(|m| m.to_string()))
+            .collect()
+    }
+
+    /// Waits until `f` returns true, or panics after a few seconds.
+    pub fn wait_for<F: FnMut() -> bool>(mut f: F) {
+        let started = Instant::now();
+        while !f() {
+            if started.elapsed() > Duration::from_secs(5) {
+                panic!("Timed out waiting for a condition");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Sends an arbitrary message (generally a signal) from the `org.bluez` connection.
+    pub fn send(&self, msg: Message) {
+        self.connection.send(msg).unwrap();
+    }
+
+    /// Performs a blocking method call from the `org.bluez` connection, the way `bluetoothd`
+    /// calls into objects exported by applications.
+    pub fn call(&self, msg: Message) -> std::result::Result<Message, dbus::Error> {
+        let serving_thread = self
+            .thread_handle
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|handle| handle.thread().id());
+        if serving_thread.map_or(true, |id| id == thread::current().id()) {
+            return self
+                .connection
+                .channel()
+                .send_with_reply_and_block(msg, Duration::from_secs(5));
+        }
+
+        // The serving thread would take the reply from under a blocking call made from another
+        // thread, so have it hand the reply over instead.
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            let serial = self.connection.send(msg).unwrap();
+            state.replies.insert(serial, sender);
+        }
+        let mut reply = receiver.recv_timeout(Duration::from_secs(5)).map_err(|_| {
+            dbus::Error::new_custom("org.freedesktop.DBus.Error.NoReply", "No reply")
+        })?;
+        reply.as_result()?;
+        Ok(reply)
+    }
+
+    fn handle(&self, msg: Message) {
+        let interface = msg.interface().map(|i| i.to_string()).unwrap_or_default();
+        let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+        let path = msg.path().map(|p| p.to_string()).unwrap_or_default();
+
+        let handler = {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(msg.duplicate().unwrap());
+            state.handlers.remove(&(interface.clone(), member.clone()))
+        };
+
+        let reply = if let Some(mut handler) = handler {
+            let reply = handler(&msg, self);
+            self.state
+                .lock()
+                .unwrap()
+                .handlers
+                .entry((interface, member))
+                .or_insert(handler);
+            reply
+        } else {
+            match (interface.as_str(), member.as_str()) {
+                ("org.freedesktop.DBus.ObjectManager", "GetManagedObjects") => {
+                    msg.method_return().append1(self.managed_objects())
+                }
+                ("org.freedesktop.DBus.Properties", "GetAll") => {
+                    let iface: String = msg.read1().unwrap();
+                    msg.method_return()
+                        .append1(self.properties(&path, &iface).unwrap_or_default())
+                }
+                ("org.freedesktop.DBus.Properties", "Get") => {
+                    let (iface, name): (String, String) = msg.read2().unwrap();
+                    match self
+                        .properties(&path, &iface)
+                        .and_then(|mut props| props.remove(&name))
+                    {
+                        Some(value) => msg.method_return().append1(value),
+                        None => msg.error(
+                            &"org.freedesktop.DBus.Error.InvalidArgs".into(),
+                            &std::ffi::CString::new(format!("No such property '{}'", name))
+                                .unwrap(),
+                        ),
+                    }
+                }
+                ("org.freedesktop.DBus.Properties", "Set") => {
+                    let (iface, name, value): (String, String, Variant<Box<dyn RefArg>>) =
+                        msg.read3().unwrap();
+                    self.set_from_variant(&path, &iface, &name, value);
+                    msg.method_return()
+                }
+                _ => msg.method_return(),
+            }
+        };
+        if !msg.get_no_reply() {
+            let _ = self.connection.send(reply);
+        }
+    }
+
+    fn set_from_variant(
+        &self,
+        path: &str,
+        interface: &str,
+        name: &str,
+        value: Variant<Box<dyn RefArg>>,
+    ) {
+        let value: Option<FakeValue> = if let Some(v) = value.0.as_str() {
+            Some(Box::new(v.to_string()))
+        } else if let Some(v) = dbus::arg::cast::<bool>(&value.0) {
+            Some(Box::new(*v))
+        } else {
+            value.0.as_u64().map(|v| Box::new(v as u32) as FakeValue)
+        };
+        if let Some(value) = value {
+            self.set_property(path, interface, name, value);
+        }
+    }
+
+    fn properties(&self, path: &str, interface: &str) -> Option<PropMap> {
+        let state = self.state.lock().unwrap();
+        state
+            .objects
+            .get(path)
+            .and_then(|i| i.get(interface))
+            .map(to_prop_map)
+    }
+
+    fn managed_objects(&self) -> HashMap<Path<'static>, HashMap<String, PropMap>> {
+        let state = self.state.lock().unwrap();
+        state
+            .objects
+            .iter()
+            .map(|(path, interfaces)| {
+                (
+                    Path::from(path.clone()),
+                    interfaces
+                        .iter()
+                        .map(|(name, props)| (name.clone(), to_prop_map(props)))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Builds a call to the bus itself.
+fn bus_call(member: &str) -> Message {
+    Message::new_method_call(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        member,
+    )
+    .unwrap()
+}
+
+fn to_prop_map(props: &BTreeMap<String, FakeValue>) -> PropMap {
+    props
+        .iter()
+        .map(|(k, v)| (k.clone(), Variant(v.box_clone())))
+        .collect()
+}
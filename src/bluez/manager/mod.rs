@@ -11,16 +11,59 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::{bluez_dbus::adapter::ORG_BLUEZ_ADAPTER1_NAME, BLUEZ_DEST, DEFAULT_TIMEOUT};
-use crate::{bluez::adapter::Adapter, Result};
-use dbus::blocking::{stdintf::org_freedesktop_dbus::ObjectManager, SyncConnection};
+use super::{
+    bluez_dbus::adapter::ORG_BLUEZ_ADAPTER1_NAME, BusAddress, Connection, DEFAULT_TIMEOUT,
+};
+use crate::{bluez::adapter::Adapter, Error, Result};
+use dbus::{
+    blocking::stdintf::org_freedesktop_dbus::{
+        ObjectManager, ObjectManagerInterfacesAdded as InterfacesAdded,
+        ObjectManagerInterfacesRemoved as InterfacesRemoved,
+    },
+    channel::Token,
+    message::{MatchRule, SignalArgs},
+    Path,
+};
+use log::debug;
 use static_assertions::assert_impl_all;
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+/// An adapter appearing or disappearing, such as a USB dongle being plugged in or removed. Each
+/// event carries the D-Bus object path of the adapter, such as `/org/bluez/hci1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManagerEvent {
+    /// A new adapter is available, [Manager::adapter] opens it.
+    AdapterAdded(String),
+    /// The adapter is gone. Operations on an [Adapter] for it fail with `Error::AdapterNotFound`
+    /// until it comes back with the same path, in which case it is announced again with
+    /// `AdapterAdded` and the [Adapter] works again.
+    AdapterRemoved(String),
+    /// `bluetoothd` has stopped, taking every adapter with it.
+    ServiceLost,
+    /// `bluetoothd` is running again. The adapters it finds are announced with `AdapterAdded`.
+    ServiceRestored,
+}
+
+type Subscribers = Arc<Mutex<Vec<Sender<ManagerEvent>>>>;
 
 /// This struct is the interface into BlueZ. It can be used to list, manage, and connect to bluetooth
 /// adapters.
+///
+/// The manager and its adapters share a single D-Bus connection, processed by one background
+/// thread that only wakes up when there is something to read. The thread runs until the manager
+/// and every adapter are dropped, or until [Manager::shutdown] is called.
 pub struct Manager {
-    dbus_conn: Arc<SyncConnection>,
+    connection: Connection,
+    subscribers: Subscribers,
+    match_tokens: Mutex<Vec<Token>>,
+    /// The adapters opened so far, by path, so each one is only set up once.
+    adapters: Mutex<HashMap<String, Adapter>>,
 }
 assert_impl_all!(Manager: Sync, Send);
 
@@ -28,25 +71,318 @@ impl Manager {
     /// Constructs a new manager to communicate with the BlueZ system. Only one Manager should be
     /// created by your application.
     pub fn new() -> Result<Manager> {
-        Ok(Manager {
-            dbus_conn: Arc::new(SyncConnection::new_system()?),
-        })
+        Manager::with_bus(BusAddress::System)
+    }
+
+    /// Constructs a manager that talks to BlueZ through the D-Bus daemon at `address` instead of
+    /// the system bus. This is mostly useful to run against a stand-in BlueZ service in tests.
+    pub fn with_bus_address(address: &str) -> Result<Manager> {
+        Manager::with_bus(BusAddress::Address(address.to_string()))
+    }
+
+    fn with_bus(bus: BusAddress) -> Result<Manager> {
+        let manager = Manager {
+            connection: bus.connect()?,
+            subscribers: Subscribers::default(),
+            match_tokens: Mutex::default(),
+            adapters: Mutex::default(),
+        };
+        manager.watch_adapters()?;
+        Ok(manager)
+    }
+
+    /// Listens for adapters being added and removed.
+    fn watch_adapters(&self) -> Result<()> {
+        let calls = &self.connection.calls;
+        let mut match_tokens = self.match_tokens.lock().unwrap();
+        let mut added_rule = InterfacesAdded::match_rule(None, None);
+        added_rule.path = Some(Path::from("/"));
+        let added = self.subscribers.clone();
+        match_tokens.push(calls.add_match(
+            added_rule,
+            move |args: InterfacesAdded, _c, _msg| {
+                if args.interfaces.contains_key(ORG_BLUEZ_ADAPTER1_NAME) {
+                    debug!("Adapter \"{}\" was added", args.object);
+                    emit(&added, ManagerEvent::AdapterAdded(args.object.to_string()));
+                }
+                true
+            },
+        )?);
+
+        let mut removed_rule = InterfacesRemoved::match_rule(None, None);
+        removed_rule.path = Some(Path::from("/"));
+        let removed = self.subscribers.clone();
+        match_tokens.push(calls.add_match(
+            removed_rule,
+            move |args: InterfacesRemoved, _c, _msg| {
+                if args.interfaces.iter().any(|i| i == ORG_BLUEZ_ADAPTER1_NAME) {
+                    debug!("Adapter \"{}\" was removed", args.object);
+                    emit(
+                        &removed,
+                        ManagerEvent::AdapterRemoved(args.object.to_string()),
+                    );
+                }
+                true
+            },
+        )?);
+
+        let mut service_rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+        service_rule.sender = Some("org.freedesktop.DBus".into());
+        let subscribers = self.subscribers.clone();
+        match_tokens.push(calls.add_match(
+            service_rule,
+            move |(name, old_owner, new_owner): (String, String, String), _c, _msg| {
+                if name == super::BLUEZ_DEST {
+                    if !old_owner.is_empty() {
+                        debug!("BlueZ has stopped");
+                        emit(&subscribers, ManagerEvent::ServiceLost);
+                    }
+                    if !new_owner.is_empty() {
+                        debug!("BlueZ has started");
+                        emit(&subscribers, ManagerEvent::ServiceRestored);
+                    }
+                }
+                true
+            },
+        )?);
+        Ok(())
     }
 
     /// Returns the list of adapters available on the system.
     pub fn adapters(&self) -> Result<Vec<Adapter>> {
-        // Create a convenience proxy connection that's already namespaced to org.bluez
-        let bluez = self.dbus_conn.with_proxy(BLUEZ_DEST, "/", DEFAULT_TIMEOUT);
+        let adapters = self
+            .adapter_paths()?
+            .iter()
+            .map(|path| self.open(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(adapters)
+    }
+
+    /// Returns the adapter at the D-Bus object path `path`, as reported by [ManagerEvent].
+    pub fn adapter(&self, path: &str) -> Result<Adapter> {
+        match self.adapter_paths()?.iter().find(|p| &***p == path) {
+            Some(path) => self.open(path),
+            None => Err(Error::AdapterNotFound),
+        }
+    }
+
+    /// Returns the adapter at `path`, which is the same `Adapter` every time it is asked for.
+    fn open(&self, path: &Path) -> Result<Adapter> {
+        let mut adapters = self.adapters.lock().unwrap();
+        if let Some(adapter) = adapters.get(&**path) {
+            return Ok(adapter.clone());
+        }
+        let adapter = Adapter::from_dbus_path(&self.connection, path)?;
+        adapters.insert(path.to_string(), adapter.clone());
+        Ok(adapter)
+    }
+
+    /// Shuts down every adapter returned by the manager, as with [Adapter::shutdown], stops
+    /// watching for adapters, and stops the thread processing the connection before returning.
+    /// Receivers of [ManagerEvent] are disconnected. The adapters still around can't be used
+    /// anymore, and their peripherals no longer get any event.
+    ///
+    /// Everything is cleaned up even if some of it fails, in which case the first error is
+    /// returned.
+    pub fn shutdown(self) -> Result<()> {
+        debug!("Shutting down");
+        let mut result = Ok(());
+        for (_path, adapter) in self.adapters.lock().unwrap().drain() {
+            result = result.and(adapter.shutdown());
+        }
+        for token in self.match_tokens.lock().unwrap().drain(..) {
+            result = result.and(
+                self.connection
+                    .calls
+                    .remove_match(token)
+                    .map_err(Error::from),
+            );
+        }
+        self.connection.dispatcher.stop();
+        result
+    }
+
+    /// Returns a receiver for the adapters added to and removed from the system from now on.
+    pub fn event_receiver(&self) -> Receiver<ManagerEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    fn adapter_paths(&self) -> Result<Vec<Path<'static>>> {
+        // Create a convenience proxy that's already namespaced to org.bluez
+        let bluez = self.connection.calls.proxy("/", DEFAULT_TIMEOUT);
 
         // First, use org.freedesktop.DBus.ObjectManager to query org.bluez
         // for adapters
-        let adapters = bluez
+        Ok(bluez
             .get_managed_objects()?
             .into_iter()
             .filter(|(_k, v)| v.keys().any(|i| i.starts_with(ORG_BLUEZ_ADAPTER1_NAME)))
-            .map(|(path, _v)| Adapter::from_dbus_path(&path))
-            .collect::<Result<Vec<_>>>()?;
+            .map(|(path, _v)| path)
+            .collect())
+    }
+}
 
-        Ok(adapters)
+/// Sends `event` to every subscriber, forgetting the ones that have dropped their receiver.
+fn emit(subscribers: &Subscribers, event: ManagerEvent) {
+    subscribers
+        .lock()
+        .unwrap()
+        .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{AdapterProperty, AsyncCentral, CentralEvent},
+        bluez::fake_bluez::{FakeBluez, PrivateBus},
+    };
+    use futures::executor::block_on;
+    use std::time::Duration;
+
+    #[test]
+    fn adapters_are_hotplugged() {
+        let bus = PrivateBus::start();
+        let bluez = FakeBluez::new(&bus);
+        bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        let manager = Manager::with_bus_address(bus.address()).unwrap();
+        let events = manager.event_receiver();
+        let adapter = manager.adapters().unwrap().remove(0);
+        assert!(matches!(
+            manager.adapter("/org/bluez/hci1"),
+            Err(Error::AdapterNotFound)
+        ));
+
+        let path = bluez.add_adapter("hci1", "00:11:22:33:44:66");
+        bluez.announce(&path);
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            ManagerEvent::AdapterAdded(path.clone())
+        );
+        let plugged = manager.adapter(&path).unwrap();
+        assert_eq!(plugged.name().unwrap(), "hci1");
+
+        bluez.remove_object("/org/bluez/hci0");
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            ManagerEvent::AdapterRemoved("/org/bluez/hci0".to_string())
+        );
+        FakeBluez::wait_for(|| matches!(adapter.is_powered(), Err(Error::AdapterNotFound)));
+        assert!(matches!(
+            adapter.known_devices(),
+            Err(Error::AdapterNotFound)
+        ));
+        assert!(plugged.is_powered().unwrap());
+
+        // The adapter works again once it is back.
+        bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        bluez.announce("/org/bluez/hci0");
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            ManagerEvent::AdapterAdded("/org/bluez/hci0".to_string())
+        );
+        FakeBluez::wait_for(|| adapter.is_powered().is_ok());
+
+        bluez.release_name();
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            ManagerEvent::ServiceLost
+        );
+        bluez.acquire_name();
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            ManagerEvent::ServiceRestored
+        );
+    }
+
+    #[test]
+    fn adapters_share_one_connection() {
+        let bus = PrivateBus::start();
+        let bluez = FakeBluez::new(&bus);
+        bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        bluez.add_adapter("hci1", "00:11:22:33:44:66");
+        let manager = Manager::with_bus_address(bus.address()).unwrap();
+        let adapters = manager.adapters().unwrap();
+        assert_eq!(adapters.len(), 2);
+        // Blocking calls are answered through the dispatcher, without needing an executor.
+        assert!(block_on(async { adapters[1].is_powered() }).unwrap());
+
+        // The fake BlueZ, the manager and its adapters, and the one counting them.
+        assert_eq!(connection_count(&bus), 3);
+    }
+
+    /// Counts the connections to `bus`, including the one made to count them.
+    fn connection_count(bus: &PrivateBus) -> usize {
+        use dbus::blocking::SyncConnection;
+
+        let connection = SyncConnection::new_address(bus.address()).unwrap();
+        let (names,): (Vec<String>,) = connection
+            .with_proxy("org.freedesktop.DBus", "/", Duration::from_secs(5))
+            .method_call("org.freedesktop.DBus", "ListNames", ())
+            .unwrap();
+        names.iter().filter(|n| n.starts_with(':')).count()
+    }
+
+    #[test]
+    fn connection_is_closed_once_unused() {
+        let bus = PrivateBus::start();
+        let bluez = FakeBluez::new(&bus);
+        bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        let manager = Manager::with_bus_address(bus.address()).unwrap();
+        let adapter = manager.adapters().unwrap().remove(0);
+        drop(manager);
+
+        // Dropping a clone of the adapter leaves the others working.
+        let events = adapter.event_receiver();
+        drop(adapter.clone());
+        bluez.emit_properties_changed(
+            "/org/bluez/hci0",
+            "org.bluez.Adapter1",
+            vec![("Powered", Box::new(false))],
+        );
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CentralEvent::AdapterUpdated(AdapterProperty::Powered(false))
+        ));
+
+        drop(adapter);
+        FakeBluez::wait_for(|| connection_count(&bus) == 2);
+    }
+
+    #[test]
+    fn shutdown_cleans_up_every_adapter() {
+        let bus = PrivateBus::start();
+        let bluez = FakeBluez::new(&bus);
+        bluez.add_adapter("hci0", "00:11:22:33:44:55");
+        bluez.add_adapter("hci1", "00:11:22:33:44:66");
+        let manager = Manager::with_bus_address(bus.address()).unwrap();
+        let events = manager.event_receiver();
+        let adapters = manager.adapters().unwrap();
+        // Adapters are only set up once.
+        let hci0 = manager.adapter("/org/bluez/hci0").unwrap();
+        block_on(hci0.start_scan()).unwrap();
+        assert!(block_on(adapters[0].stop_scan()).is_ok());
+        block_on(adapters[1].start_scan()).unwrap();
+
+        manager.shutdown().unwrap();
+        assert_eq!(bluez.calls("StopDiscovery").len(), 2);
+        assert!(events.recv().is_err());
+        for adapter in adapters.iter().chain(Some(&hci0)) {
+            assert!(matches!(adapter.is_powered(), Err(Error::AdapterNotFound)));
+            assert!(matches!(
+                block_on(adapter.start_scan()),
+                Err(Error::AdapterNotFound)
+            ));
+        }
+        // Shutting down an adapter again does nothing.
+        hci0.shutdown().unwrap();
+
+        // Everything the connection held is gone, so it closes with the last adapter.
+        drop(adapters);
+        drop(hci0);
+        FakeBluez::wait_for(|| connection_count(&bus) == 2);
     }
 }
@@ -12,14 +12,79 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 pub mod adapter;
+mod async_connection;
 mod bluez_dbus;
+mod dispatcher;
+mod export;
+#[cfg(test)]
+mod fake_bluez;
 pub mod manager;
 mod util;
 
+use self::{
+    async_connection::AsyncConnection,
+    dispatcher::{Dispatcher, Wakeup},
+};
+use dbus::{
+    blocking::SyncConnection,
+    channel::{BusType, Channel},
+};
+use parking_lot::ReentrantMutex;
+use std::sync::Arc;
+
 const BLUEZ_DEST: &str = "org.bluez";
 
 const DEFAULT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
+/// The D-Bus daemon through which BlueZ is reached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BusAddress {
+    System,
+    Address(String),
+}
+
+impl BusAddress {
+    /// Opens a new connection, and starts processing it from a new thread.
+    fn connect(&self) -> crate::Result<Connection> {
+        let mut channel = match self {
+            BusAddress::System => Channel::get_private(BusType::System)?,
+            BusAddress::Address(address) => {
+                let mut channel = Channel::open_private(address)?;
+                channel.register()?;
+                channel
+            }
+        };
+        channel.set_watch_enabled(true);
+        let fd = channel.watch().fd;
+        let connection = SyncConnection::from(channel);
+        // Adapters and the manager match the same signals, such as `InterfacesAdded`, so every
+        // matching rule has to get them.
+        connection.set_signal_match_mode(true);
+
+        let listener = Arc::new(ReentrantMutex::new(connection));
+        let wakeup = Arc::new(
+            Wakeup::new()
+                .map_err(|e| crate::Error::Other(format!("Could not create pipe: {}", e)))?,
+        );
+        let calls = AsyncConnection::new(listener.clone(), wakeup.clone());
+        let dispatcher = Dispatcher::start(listener.clone(), fd, calls.clone(), wakeup);
+        Ok(Connection {
+            listener,
+            calls,
+            dispatcher: Arc::new(dispatcher),
+        })
+    }
+}
+
+/// The connection shared by a `Manager` and its adapters.
+#[derive(Clone)]
+pub(crate) struct Connection {
+    listener: Arc<ReentrantMutex<SyncConnection>>,
+    calls: AsyncConnection,
+    /// Keeps the thread processing the connection running for as long as it is used.
+    dispatcher: Arc<Dispatcher>,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 enum AttributeType {
     Service,
@@ -33,56 +98,56 @@ struct Handle {
     handle: u16,
 }
 
-impl PartialOrd for Handle {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        if self.handle == other.parent {
-            return Some(std::cmp::Ordering::Greater);
-        }
-        if self.handle < other.handle {
-            return Some(std::cmp::Ordering::Less);
-        }
-        if self.handle == other.handle {
-            return Some(std::cmp::Ordering::Equal);
-        }
-        if self.handle > other.handle {
-            return Some(std::cmp::Ordering::Greater);
-        }
-        return None;
-    }
-}
-impl Ord for Handle {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
-    }
-}
 impl std::str::FromStr for Handle {
     type Err = crate::Error;
 
     fn from_str(s: &str) -> std::result::Result<Handle, crate::Error> {
-        // serviceXXXX/charYYYY/descriptorZZZZ
+        // serviceXXXX/charYYYY/descZZZZ
         let mut handle = Handle {
             typ: AttributeType::Service,
             parent: 0,
             handle: 0,
         };
-        let get_handle = |p| {
-            u16::from_str_radix(&s[p..].trim_start_matches(char::is_alphabetic)[..4], 16).unwrap()
+        let invalid = || crate::Error::Other(format!("Invalid attribute path \"{}\"", s));
+        // The four hex digits starting at `p`, which end the path element.
+        let get_digits = |p: usize| {
+            s.get(p..p + 4)
+                .filter(|h| h.chars().all(|c| c.is_ascii_hexdigit()))
+                .filter(|_h| s[p + 4..].is_empty() || s[p + 4..].starts_with('/'))
+                .and_then(|h| u16::from_str_radix(h, 16).ok())
+                .ok_or_else(invalid)
+        };
+        // The handle of the element `name` starting at `p`.
+        let get_handle = |p: usize, name: &str| get_digits(p + name.len());
+        // The handle of the element `name` before the one starting at `p`.
+        let get_parent = |p: usize, name: &str| {
+            p.checked_sub(name.len() + 5)
+                .filter(|&q| s.is_char_boundary(q) && s[q..].starts_with(name))
+                .filter(|&q| q == 0 || s[..q].ends_with('/'))
+                .ok_or_else(invalid)
+                .and_then(|q| get_handle(q, name))
+        };
+        // The start of the path element starting with `name`.
+        let find = |name: &str| {
+            s.match_indices(name)
+                .map(|(p, _name)| p)
+                .find(|&p| p == 0 || s[..p].ends_with('/'))
         };
 
-        if let Some(descriptor) = s.find("descriptor") {
+        if let Some(descriptor) = find("desc") {
             handle.typ = AttributeType::Descriptor;
-            handle.handle = get_handle(descriptor);
-            handle.parent = get_handle(descriptor - 5);
-        } else if let Some(characteristic) = s.find("char") {
+            handle.handle = get_handle(descriptor, "desc")?;
+            handle.parent = get_parent(descriptor, "char")?;
+        } else if let Some(characteristic) = find("char") {
             handle.typ = AttributeType::Characteristic;
-            handle.handle = get_handle(characteristic);
-            handle.parent = get_handle(characteristic - 5);
-        } else if let Some(service) = s.find("service") {
+            handle.handle = get_handle(characteristic, "char")?;
+            handle.parent = get_parent(characteristic, "service")?;
+        } else if let Some(service) = find("service") {
             handle.typ = AttributeType::Service;
-            handle.handle = get_handle(service);
+            handle.handle = get_handle(service, "service")?;
             handle.parent = 0
         } else {
-            return Err(crate::Error::Other("Can't parse".to_string()));
+            return Err(invalid());
         }
 
         Ok(handle)
@@ -95,10 +160,9 @@ mod tests {
 
     #[test]
     fn test_parse_descriptor_handle() {
-        let handle: Handle =
-            "/org/bluez/hci0/dev_01_02_03_04_05_06/service0025/char0026/descriptor0027"
-                .parse()
-                .unwrap();
+        let handle: Handle = "/org/bluez/hci0/dev_01_02_03_04_05_06/service0025/char0026/desc0027"
+            .parse()
+            .unwrap();
         assert_eq!(
             handle,
             Handle {
@@ -136,4 +200,23 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_parse_malformed_handle() {
+        for path in &[
+            "/org/bluez/hci0/dev_01_02_03_04_05_06",
+            "/org/bluez/hci0/dev_01_02_03_04_05_06/service",
+            "/org/bluez/hci0/dev_01_02_03_04_05_06/service00zz",
+            "/org/bluez/hci0/dev_01_02_03_04_05_06/service0025/char002",
+            "char0026",
+            "/desc0027",
+            "/org/bluez/hci0/dev_01_02_03_04_05_06/service0025/char0026/desc",
+            "/org/bluez/hci0/dev_01_02_03_04_05_06/service0025/char00261",
+            "/org/bluez/hci0/dev_01_02_03_04_05_06/service00251/char0026",
+            "/org/bluez/hci0/dev_01_02_03_04_05_06/service0025/char+026",
+            "/org/bluez/hci0/dev_01_02_03_04_05_06/servicé0025/char0026",
+        ] {
+            assert!(path.parse::<Handle>().is_err(), "{} was parsed", path);
+        }
+    }
 }
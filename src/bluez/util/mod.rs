@@ -11,18 +11,183 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::Error;
+use crate::{api::AttErrorCode, Error};
 
 impl From<dbus::Error> for Error {
     fn from(e: dbus::Error) -> Self {
+        let message = e.message().unwrap_or("Unknown DBus error.").to_string();
         match e.name() {
-            // Some("org.freedesktop.DBus.Error.NoReply") => Error::TimedOut(Duration::new(0,0)), // How to express a TimedOut error without a duration?
-            // TODO: translate other dbus errors into relevant btleplug::Error kind
+            // BlueZ reports these ATT errors of a remote device as NotPermitted, as it does for
+            // its own checks, which only the message tells apart.
+            Some("org.bluez.Error.NotPermitted") => match message.as_str() {
+                "Read not permitted" => Error::Att(AttErrorCode::ReadNotPermitted),
+                "Write not permitted" => Error::Att(AttErrorCode::WriteNotPermitted),
+                _ => Error::NotPermitted(message),
+            },
+            Some("org.bluez.Error.NotAuthorized") => Error::NotAuthorized(message),
+            Some("org.bluez.Error.InProgress") => Error::InProgress(message),
+            Some("org.bluez.Error.NotReady") => Error::NotReady(message),
+            Some("org.bluez.Error.InvalidValueLength") => Error::InvalidValueLength(message),
+            Some("org.bluez.Error.NotSupported") => Error::NotSupported(message),
+            Some("org.bluez.Error.NotConnected") => Error::NotConnected,
+            Some("org.bluez.Error.DoesNotExist") => Error::DeviceNotFound,
+            Some("org.bluez.Error.AuthenticationFailed") => Error::AuthenticationFailed(message),
+            Some("org.bluez.Error.AuthenticationCanceled") | Some("org.bluez.Error.Canceled") => {
+                Error::AuthenticationCanceled(message)
+            }
+            Some("org.bluez.Error.AuthenticationRejected") | Some("org.bluez.Error.Rejected") => {
+                Error::AuthenticationRejected(message)
+            }
+            Some("org.bluez.Error.AuthenticationTimeout") => Error::AuthenticationTimeout(message),
+            Some("org.bluez.Error.ConnectionAttemptFailed") => Error::ConnectionFailed(message),
+            // BlueZ reports ATT errors it has no better name for as "Operation failed with ATT
+            // error: 0x0e".
+            Some("org.bluez.Error.Failed") => match parse_att_error(&message) {
+                Some(code) => Error::Att(code),
+                None => Error::Failed(message),
+            },
+            Some("org.bluez.Error.AccessDenied")
+            | Some("org.freedesktop.DBus.Error.AccessDenied") => Error::AccessDenied(message),
+            Some("org.freedesktop.DBus.Error.NoReply") => Error::NoReply(message),
             _ => Error::Other(format!(
                 "{}: {}",
                 e.name().unwrap_or("Unknown DBus error"),
-                e.message().unwrap_or("Unknown DBus error.")
+                message
             )),
         }
     }
 }
+
+/// The D-Bus error, with its name and message, used to report `error` to BlueZ from an object we
+/// export. BlueZ turns these names back into ATT error codes for the remote device.
+pub(crate) fn to_dbus_error(error: &Error) -> (&'static str, String) {
+    let name = match error {
+        Error::PermissionDenied | Error::NotPermitted(_) | Error::AccessDenied(_) => {
+            "org.bluez.Error.NotPermitted"
+        }
+        Error::NotAuthorized(_) => "org.bluez.Error.NotAuthorized",
+        Error::InProgress(_) => "org.bluez.Error.InProgress",
+        Error::NotSupported(_) => "org.bluez.Error.NotSupported",
+        Error::InvalidValueLength(_) => "org.bluez.Error.InvalidValueLength",
+        // The names agents answer with.
+        Error::AuthenticationRejected(_) => "org.bluez.Error.Rejected",
+        Error::AuthenticationCanceled(_) => "org.bluez.Error.Canceled",
+        Error::Att(code) => match code {
+            AttErrorCode::ReadNotPermitted | AttErrorCode::WriteNotPermitted => {
+                "org.bluez.Error.NotPermitted"
+            }
+            AttErrorCode::InsufficientAuthorization => "org.bluez.Error.NotAuthorized",
+            AttErrorCode::RequestNotSupported => "org.bluez.Error.NotSupported",
+            AttErrorCode::InvalidOffset => "org.bluez.Error.InvalidOffset",
+            AttErrorCode::InvalidAttributeValueLength => "org.bluez.Error.InvalidValueLength",
+            // BlueZ sends the code in the message of `Failed` errors if it is an application
+            // error (0x80 to 0x9F), and 0x80 for anything else.
+            _ => return ("org.bluez.Error.Failed", format!("0x{:02x}", code.num())),
+        },
+        _ => "org.bluez.Error.Failed",
+    };
+    (name, error.to_string())
+}
+
+fn parse_att_error(message: &str) -> Option<AttErrorCode> {
+    let code = message.split("ATT error: 0x").nth(1)?.get(..2)?;
+    u8::from_str_radix(code, 16).ok().map(AttErrorCode::from_u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(name: &str, message: &str) -> Error {
+        dbus::Error::new_custom(name, message).into()
+    }
+
+    #[test]
+    fn bluez_errors_are_typed() {
+        assert!(matches!(
+            convert("org.bluez.Error.NotPermitted", "Not paired"),
+            Error::NotPermitted(m) if m == "Not paired"
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.InProgress", "In Progress"),
+            Error::InProgress(_)
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.Failed", "Not connected"),
+            Error::Failed(_)
+        ));
+        assert!(matches!(
+            convert(
+                "org.freedesktop.DBus.Error.NoReply",
+                "Did not receive a reply"
+            ),
+            Error::NoReply(_)
+        ));
+        assert!(matches!(
+            convert(
+                "org.bluez.Error.AuthenticationRejected",
+                "Authentication Rejected"
+            ),
+            Error::AuthenticationRejected(_)
+        ));
+        assert!(matches!(
+            convert(
+                "org.bluez.Error.AuthenticationTimeout",
+                "Authentication Timeout"
+            ),
+            Error::AuthenticationTimeout(_)
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.DoesNotExist", "Does Not Exist"),
+            Error::DeviceNotFound
+        ));
+        assert!(matches!(
+            convert("org.example.Error", "Something"),
+            Error::Other(m) if m == "org.example.Error: Something"
+        ));
+    }
+
+    #[test]
+    fn att_errors_are_parsed() {
+        assert!(matches!(
+            convert(
+                "org.bluez.Error.Failed",
+                "Operation failed with ATT error: 0x05"
+            ),
+            Error::Att(AttErrorCode::InsufficientAuthentication)
+        ));
+        assert!(matches!(
+            convert(
+                "org.bluez.Error.Failed",
+                "Operation failed with ATT error: 0x80"
+            ),
+            Error::Att(AttErrorCode::Application(0x80))
+        ));
+        // The ATT errors BlueZ has a name for.
+        assert!(matches!(
+            convert("org.bluez.Error.NotPermitted", "Read not permitted"),
+            Error::Att(AttErrorCode::ReadNotPermitted)
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.NotPermitted", "Write not permitted"),
+            Error::Att(AttErrorCode::WriteNotPermitted)
+        ));
+    }
+
+    #[test]
+    fn errors_are_reported_to_bluez() {
+        assert_eq!(
+            to_dbus_error(&Error::NotPermitted("Read only".into())).0,
+            "org.bluez.Error.NotPermitted"
+        );
+        assert_eq!(
+            to_dbus_error(&Error::Att(AttErrorCode::InvalidOffset)).0,
+            "org.bluez.Error.InvalidOffset"
+        );
+        // Errors without a BlueZ name are sent as the bare code, which is all BlueZ parses.
+        assert_eq!(
+            to_dbus_error(&Error::Att(AttErrorCode::Application(0x9f))),
+            ("org.bluez.Error.Failed", "0x9f".to_string())
+        );
+    }
+}
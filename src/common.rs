@@ -1 +1,2 @@
-pub mod util;
+pub mod blocking;
+pub mod queue;
@@ -0,0 +1,70 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Waits for a future on the calling thread, which is how the blocking traits run the operations
+//! of the async ones.
+//!
+//! Unlike `futures::executor::block_on`, this isn't an executor, so it doesn't panic when called
+//! from code already running in one. The futures of the backends are completed by the threads
+//! receiving replies from the Bluetooth stack, so parking the calling thread until then is enough.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+struct Unparker(Thread);
+
+impl Wake for Unparker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Blocks the calling thread until `future` resolves.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(Unparker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = Pin::as_mut(&mut future).poll(&mut context) {
+            return output;
+        }
+        // Wakeups that happened since the poll are kept by the thread, so none is missed.
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::oneshot;
+    use std::time::Duration;
+
+    #[test]
+    fn waits_for_other_threads() {
+        let (sender, receiver) = oneshot::channel();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send(42).unwrap();
+        });
+        assert_eq!(block_on(receiver), Ok(42));
+    }
+
+    #[test]
+    fn works_within_an_executor() {
+        let output = futures::executor::block_on(async { block_on(async { 42 }) });
+        assert_eq!(output, 42);
+    }
+}
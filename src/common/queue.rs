@@ -0,0 +1,184 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! A bounded queue between one sender and one receiver, which can be read from both blocking code
+//! and futures. This backs event and notification receivers, and the delivery of notifications
+//! to handlers.
+
+use crate::api::OverflowPolicy;
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{RecvError, RecvTimeoutError, TryRecvError},
+        Condvar, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct QueueState<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    dropped: u64,
+    /// Set when the receiver is dropped, nothing will be read anymore.
+    receiver_gone: bool,
+    /// Set when the sender is gone, nothing will be written anymore.
+    sender_gone: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug)]
+pub struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> Queue<T> {
+    /// Creates a queue holding up to `capacity` items, at least 1.
+    pub fn new(capacity: usize, overflow: OverflowPolicy) -> Self {
+        Queue {
+            state: Mutex::new(QueueState {
+                items: VecDeque::new(),
+                capacity: capacity.max(1),
+                overflow,
+                dropped: 0,
+                receiver_gone: false,
+                sender_gone: false,
+                waker: None,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    /// Queues an item, returns false if the receiver is gone.
+    pub fn push(&self, item: T) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while state.items.len() >= state.capacity && !state.receiver_gone {
+            match state.overflow {
+                OverflowPolicy::DropOldest => {
+                    state.items.pop_front();
+                    state.dropped += 1;
+                }
+                OverflowPolicy::Block => state = self.not_full.wait(state).unwrap(),
+            }
+        }
+        if state.receiver_gone {
+            return false;
+        }
+        state.items.push_back(item);
+        self.not_empty.notify_one();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    /// Changes the capacity and overflow policy. Items already queued beyond the new capacity are
+    /// kept.
+    pub fn set_limits(&self, capacity: usize, overflow: OverflowPolicy) {
+        let mut state = self.state.lock().unwrap();
+        state.capacity = capacity.max(1);
+        state.overflow = overflow;
+        // A sender waiting for room may not have to anymore.
+        self.not_full.notify_all();
+    }
+
+    /// Marks the sender as gone. The receiver gets what is still queued, and then nothing.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.sender_gone = true;
+        self.not_empty.notify_all();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Marks the receiver as gone, which discards the queued items and unblocks the sender.
+    pub fn close_receiver(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.receiver_gone = true;
+        state.items.clear();
+        self.not_full.notify_all();
+    }
+
+    pub fn is_receiver_gone(&self) -> bool {
+        self.state.lock().unwrap().receiver_gone
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = self.pop(&mut state) {
+                return Ok(item);
+            }
+            if state.sender_gone {
+                return Err(RecvError);
+            }
+            state = self.not_empty.wait(state).unwrap();
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock().unwrap();
+        match self.pop(&mut state) {
+            Some(item) => Ok(item),
+            None if state.sender_gone => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = self.pop(&mut state) {
+                return Ok(item);
+            }
+            if state.sender_gone {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    pub fn poll_next(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(item) = self.pop(&mut state) {
+            Poll::Ready(Some(item))
+        } else if state.sender_gone {
+            Poll::Ready(None)
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Returns the number of items discarded so far because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+
+    fn pop(&self, state: &mut QueueState<T>) -> Option<T> {
+        let item = state.items.pop_front();
+        if item.is_some() {
+            self.not_full.notify_one();
+        }
+        item
+    }
+}
@@ -1,14 +1,16 @@
 use super::internal::{run_corebluetooth_thread, CoreBluetoothEvent, CoreBluetoothMessage};
 use super::peripheral::Peripheral;
-use crate::api::{AdapterManager, BDAddr, Central, CentralEvent};
+use crate::api::{
+    AdapterManager, AsyncCentral, BDAddr, CentralEvent, EventReceiver, EventSubscription,
+};
 use crate::Result;
 use async_std::task;
+use async_trait::async_trait;
 use futures::channel::mpsc::{self, Sender};
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 use log::info;
 use std::convert::TryInto;
-use std::sync::mpsc::Receiver;
 
 #[derive(Clone, Debug)]
 pub struct Adapter {
@@ -81,27 +83,24 @@ impl Adapter {
     }
 }
 
-impl Central<Peripheral> for Adapter {
-    fn event_receiver(&self) -> Option<Receiver<CentralEvent>> {
-        self.manager.event_receiver()
+#[async_trait]
+impl AsyncCentral<Peripheral> for Adapter {
+    fn subscribe_events(&self, subscription: EventSubscription) -> EventReceiver {
+        self.manager.subscribe_events(subscription)
     }
 
-    fn start_scan(&self) -> Result<()> {
+    async fn start_scan(&self) -> Result<()> {
         info!("Starting CoreBluetooth Scan");
-        task::block_on(async {
-            let mut sender = self.sender.clone();
-            sender.send(CoreBluetoothMessage::StartScanning).await?;
-            Ok(())
-        })
+        let mut sender = self.sender.clone();
+        sender.send(CoreBluetoothMessage::StartScanning).await?;
+        Ok(())
     }
 
-    fn stop_scan(&self) -> Result<()> {
+    async fn stop_scan(&self) -> Result<()> {
         info!("Stopping CoreBluetooth Scan");
-        task::block_on(async {
-            let mut sender = self.sender.clone();
-            sender.send(CoreBluetoothMessage::StopScanning).await?;
-            Ok(())
-        })
+        let mut sender = self.sender.clone();
+        sender.send(CoreBluetoothMessage::StopScanning).await?;
+        Ok(())
     }
 
     fn peripherals(&self) -> Vec<Peripheral> {
@@ -112,7 +111,11 @@ impl Central<Peripheral> for Adapter {
         self.manager.peripheral(address)
     }
 
-    fn active(&self, _enabled: bool) {}
+    fn active(&self, _enabled: bool) -> Result<()> {
+        Ok(())
+    }
 
-    fn filter_duplicates(&self, _enabled: bool) {}
+    fn filter_duplicates(&self, _enabled: bool) -> Result<()> {
+        Ok(())
+    }
 }
@@ -49,6 +49,8 @@ pub enum CentralDelegateEvent {
     ManufacturerData(Uuid, u16, Vec<u8>),
     ServiceData(Uuid, HashMap<Uuid, Vec<u8>>),
     Services(Uuid, Vec<Uuid>),
+    Rssi(Uuid, i16),
+    TxPowerLevel(Uuid, i8),
     // DiscoveredIncludedServices(Uuid, HashMap<Uuid, StrongPtr>),
     // Peripheral UUID, HashMap Characteristic Uuid to StrongPtr
     DiscoveredCharacteristics(Uuid, HashMap<Uuid, StrongPtr>),
@@ -123,6 +125,14 @@ impl Debug for CentralDelegateEvent {
                 .field(uuid)
                 .field(services)
                 .finish(),
+            CentralDelegateEvent::Rssi(uuid, rssi) => {
+                f.debug_tuple("Rssi").field(uuid).field(rssi).finish()
+            }
+            CentralDelegateEvent::TxPowerLevel(uuid, tx_power_level) => f
+                .debug_tuple("TxPowerLevel")
+                .field(uuid)
+                .field(tx_power_level)
+                .finish(),
         }
     }
 }
@@ -345,7 +355,7 @@ pub mod CentralDelegate {
         _central: *mut Object,
         peripheral: *mut Object,
         adv_data: *mut Object,
-        _rssi: *mut Object,
+        rssi: *mut Object,
     ) {
         trace!(
             "delegate_centralmanager_diddiscoverperipheral_advertisementdata_rssi {}",
@@ -420,6 +430,26 @@ pub mod CentralDelegate {
 
             send_delegate_event(delegate, CentralDelegateEvent::Services(puuid, result));
         }
+
+        let tx_power_level = ns::dictionary_objectforkey(adv_data, unsafe {
+            cb::ADVERTISEMENT_DATA_TX_POWER_LEVEL_KEY
+        });
+        if tx_power_level != nil {
+            // tx_power_level: NSNumber
+            send_delegate_event(
+                delegate,
+                CentralDelegateEvent::TxPowerLevel(
+                    puuid,
+                    ns::number_shortvalue(tx_power_level) as i8,
+                ),
+            );
+        }
+
+        // rssi: NSNumber, 127 when the signal strength isn't available
+        let rssi = ns::number_shortvalue(rssi);
+        if rssi != 127 {
+            send_delegate_event(delegate, CentralDelegateEvent::Rssi(puuid, rssi));
+        }
     }
 
     ////////////////////////////////////////////////////////////////
@@ -62,6 +62,13 @@ pub mod ns {
         }
     }
 
+    pub fn number_shortvalue(nsnumber: *mut Object) -> i16 {
+        unsafe {
+            let value: i16 = msg_send![nsnumber, shortValue];
+            value
+        }
+    }
+
     // NSString
 
     pub fn string(cstring: *const c_char) -> *mut Object /* NSString* */ {
@@ -274,6 +281,7 @@ pub mod cb {
             pub static CBAdvertisementDataManufacturerDataKey: *mut Object;
             pub static CBAdvertisementDataServiceDataKey: *mut Object;
             pub static CBAdvertisementDataServiceUUIDsKey: *mut Object;
+            pub static CBAdvertisementDataTxPowerLevelKey: *mut Object;
 
             pub static CBCentralManagerScanOptionAllowDuplicatesKey: *mut Object;
         }
@@ -527,4 +535,5 @@ pub mod cb {
     pub use self::link::CBAdvertisementDataManufacturerDataKey as ADVERTISEMENT_DATA_MANUFACTURER_DATA_KEY;
     pub use self::link::CBAdvertisementDataServiceDataKey as ADVERTISEMENT_DATA_SERVICE_DATA_KEY;
     pub use self::link::CBAdvertisementDataServiceUUIDsKey as ADVERTISEMENT_DATA_SERVICE_UUIDS_KEY;
+    pub use self::link::CBAdvertisementDataTxPowerLevelKey as ADVERTISEMENT_DATA_TX_POWER_LEVEL_KEY;
 }
@@ -119,6 +119,8 @@ pub enum CBPeripheralEvent {
     ManufacturerData(u16, Vec<u8>),
     ServiceData(HashMap<Uuid, Vec<u8>>),
     Services(Vec<Uuid>),
+    Rssi(i16),
+    TxPowerLevel(i8),
 }
 
 pub type CoreBluetoothReplyStateShared = BtlePlugFutureStateShared<CoreBluetoothReply>;
@@ -348,6 +350,28 @@ impl CoreBluetoothInternal {
         }
     }
 
+    async fn on_rssi(&mut self, peripheral_uuid: Uuid, rssi: i16) {
+        trace!("Got RSSI! {}", rssi);
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Err(e) = p.event_sender.send(CBPeripheralEvent::Rssi(rssi)).await {
+                error!("Error sending notification event: {}", e);
+            }
+        }
+    }
+
+    async fn on_tx_power_level(&mut self, peripheral_uuid: Uuid, tx_power_level: i8) {
+        trace!("Got TX power level! {}", tx_power_level);
+        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+            if let Err(e) = p
+                .event_sender
+                .send(CBPeripheralEvent::TxPowerLevel(tx_power_level))
+                .await
+            {
+                error!("Error sending notification event: {}", e);
+            }
+        }
+    }
+
     async fn on_discovered_peripheral(&mut self, peripheral: StrongPtr) {
         let uuid_nsstring = ns::uuid_uuidstring(cb::peer_identifier(*peripheral));
         let uuid = Uuid::from_str(&NSStringUtils::string_to_string(uuid_nsstring)).unwrap();
@@ -618,6 +642,12 @@ impl CoreBluetoothInternal {
                     CentralDelegateEvent::Services(peripheral_id, services) => {
                         self.on_services(peripheral_id, services).await
                     },
+                    CentralDelegateEvent::Rssi(peripheral_id, rssi) => {
+                        self.on_rssi(peripheral_id, rssi).await
+                    },
+                    CentralDelegateEvent::TxPowerLevel(peripheral_id, tx_power_level) => {
+                        self.on_tx_power_level(peripheral_id, tx_power_level).await
+                    },
                 };
             }
             adapter_msg = self.message_receiver.select_next_some() => {
@@ -13,13 +13,15 @@ use super::{
 };
 use crate::{
     api::{
-        AdapterManager, AddressType, BDAddr, CentralEvent, Characteristic, NotificationHandler,
-        Peripheral as ApiPeripheral, PeripheralProperties, ValueNotification, WriteType,
+        notifications::NotificationHandlers, AdapterManager, AddressType, AsyncPeripheral, BDAddr,
+        CentralEvent, Characteristic, Descriptor, NotificationHandler, NotificationHandlerId,
+        NotificationReceiver, OverflowPolicy, PeripheralProperties, Service, SignalStats,
+        ValueNotification, WriteType,
     },
-    common::util,
     Error, Result,
 };
 use async_std::task;
+use async_trait::async_trait;
 use futures::channel::mpsc::{Receiver, SendError, Sender};
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
@@ -34,7 +36,7 @@ use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct Peripheral {
-    notification_handlers: Arc<Mutex<Vec<NotificationHandler>>>,
+    notification_handlers: Arc<NotificationHandlers>,
     manager: AdapterManager<Self>,
     uuid: Uuid,
     characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
@@ -62,13 +64,15 @@ impl Peripheral {
             address_type: AddressType::Random,
             local_name: local_name,
             tx_power_level: None,
+            rssi: None,
+            signal: SignalStats::default(),
             manufacturer_data: HashMap::new(),
             service_data: HashMap::new(),
             services: Vec::new(),
             discovery_count: 1,
             has_scan_response: true,
         }));
-        let notification_handlers = Arc::new(Mutex::new(Vec::<NotificationHandler>::new()));
+        let notification_handlers = Arc::new(NotificationHandlers::default());
         let nh_clone = notification_handlers.clone();
         let p_clone = properties.clone();
         let m_clone = manager.clone();
@@ -77,14 +81,11 @@ impl Peripheral {
             loop {
                 match event_receiver.next().await {
                     Some(CBPeripheralEvent::Notification(uuid, data)) => {
-                        util::invoke_handlers(
-                            &nh_clone,
-                            &ValueNotification {
-                                uuid,
-                                handle: None,
-                                value: data,
-                            },
-                        );
+                        nh_clone.notify(ValueNotification {
+                            uuid,
+                            handle: None,
+                            value: data.into(),
+                        });
                     }
                     Some(CBPeripheralEvent::ManufacturerData(manufacturer_id, data)) => {
                         let mut properties = p_clone.lock().unwrap();
@@ -118,6 +119,17 @@ impl Peripheral {
                             services,
                         });
                     }
+                    Some(CBPeripheralEvent::Rssi(rssi)) => {
+                        let mut properties = p_clone.lock().unwrap();
+                        properties.rssi = Some(rssi);
+                        properties.signal.record(rssi);
+
+                        m_clone.emit(CentralEvent::DeviceUpdated(properties.address));
+                    }
+                    Some(CBPeripheralEvent::TxPowerLevel(tx_power_level)) => {
+                        let mut properties = p_clone.lock().unwrap();
+                        properties.tx_power_level = Some(tx_power_level);
+                    }
                     Some(CBPeripheralEvent::Disconnected) => (),
                     None => {
                         error!("Event receiver died, breaking out of corebluetooth device loop.");
@@ -163,7 +175,8 @@ impl Debug for Peripheral {
     }
 }
 
-impl ApiPeripheral for Peripheral {
+#[async_trait]
+impl AsyncPeripheral for Peripheral {
     /// Returns the address of the peripheral.
     fn address(&self) -> BDAddr {
         self.properties.lock().unwrap().address
@@ -181,46 +194,56 @@ impl ApiPeripheral for Peripheral {
         self.characteristics.lock().unwrap().clone()
     }
 
+    /// The set of services we've discovered for this device. Characteristics aren't grouped by
+    /// service on this platform yet, so this is always empty.
+    fn services(&self) -> BTreeSet<Service> {
+        BTreeSet::new()
+    }
+
+    /// The set of descriptors we've discovered for the given characteristic. Descriptor discovery
+    /// isn't implemented on this platform yet, so this is always empty.
+    fn descriptors(&self, _characteristic: &Characteristic) -> BTreeSet<Descriptor> {
+        BTreeSet::new()
+    }
+
     /// Returns true iff we are currently connected to the device.
     fn is_connected(&self) -> bool {
         false
     }
 
-    /// Creates a connection to the device. This is a synchronous operation; if this method returns
-    /// Ok there has been successful connection. Note that peripherals allow only one connection at
-    /// a time. Operations that attempt to communicate with a device will fail until it is connected.
-    fn connect(&self) -> Result<()> {
+    /// Creates a connection to the device. If the returned future resolves to Ok there has been a
+    /// successful connection. Note that peripherals allow only one connection at a time.
+    /// Operations that attempt to communicate with a device will fail until it is connected.
+    async fn connect(&self) -> Result<()> {
         info!("Trying device connect!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::ConnectDevice(
-                    self.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Connected(chars) => {
-                    *(self.characteristics.lock().unwrap()) = chars;
-                    self.emit(CentralEvent::DeviceConnected(
-                        self.properties.lock().unwrap().address,
-                    ));
-                }
-                _ => panic!("Shouldn't get anything but connected!"),
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::ConnectDevice(
+                self.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Connected(chars) => {
+                *(self.characteristics.lock().unwrap()) = chars;
+                self.emit(CentralEvent::DeviceConnected(
+                    self.properties.lock().unwrap().address,
+                ));
             }
-            info!("Device connected!");
-            Ok(())
-        })
+            _ => panic!("Shouldn't get anything but connected!"),
+        }
+        info!("Device connected!");
+        Ok(())
     }
 
-    /// Terminates a connection to the device. This is a synchronous operation.
-    fn disconnect(&self) -> Result<()> {
+    /// Terminates a connection to the device.
+    async fn disconnect(&self) -> Result<()> {
         Ok(())
     }
 
-    /// Discovers all characteristics for the device. This is a synchronous operation.
-    fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+    /// Discovers all characteristics for the device.
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
         let chrs = self.characteristics.lock().unwrap().clone();
         let v = Vec::from_iter(chrs.into_iter());
         Ok(v)
@@ -228,111 +251,122 @@ impl ApiPeripheral for Peripheral {
 
     /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
     /// the case of a write-with-response) if the device returns an error.
-    fn write(
+    async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::WriteValue(
-                    self.uuid,
-                    characteristic.uuid,
-                    Vec::from(data),
-                    write_type,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Ok => {}
-                reply => panic!("Unexpected reply: {:?}", reply),
-            }
-            Ok(())
-        })
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::WriteValue(
+                self.uuid,
+                characteristic.uuid,
+                Vec::from(data),
+                write_type,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => {}
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+        Ok(())
     }
 
     /// Sends a read-by-type request to device for the range of handles covered by the
     /// characteristic and for the specified declaration UUID. See
     /// [here](https://www.bluetooth.com/specifications/gatt/declarations) for valid UUIDs.
-    /// Synchronously returns either an error or the device response.
-    fn read_by_type(&self, _characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
+    /// Resolves to either an error or the device response.
+    async fn read_by_type(&self, _characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
         Err(Error::NotSupported("read_by_type".into()))
     }
 
+    /// Sends a read request for the descriptor to the device. Not supported on this platform yet.
+    async fn read_descriptor(&self, _descriptor: &Descriptor) -> Result<Vec<u8>> {
+        Err(Error::NotSupported("read_descriptor".into()))
+    }
+
+    /// Writes some data to the descriptor. Not supported on this platform yet.
+    async fn write_descriptor(&self, _descriptor: &Descriptor, _data: &[u8]) -> Result<()> {
+        Err(Error::NotSupported("write_descriptor".into()))
+    }
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
-    /// This is a synchronous call.
-    fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
         info!("Trying to subscribe!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::Subscribe(
-                    self.uuid,
-                    characteristic.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Ok => info!("subscribed!"),
-                _ => panic!("Didn't subscribe!"),
-            }
-            Ok(())
-        })
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::Subscribe(
+                self.uuid,
+                characteristic.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => info!("subscribed!"),
+            _ => panic!("Didn't subscribe!"),
+        }
+        Ok(())
     }
 
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
-    /// This is a synchronous call.
-    fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
         info!("Trying to unsubscribe!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::Unsubscribe(
-                    self.uuid,
-                    characteristic.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Ok => {}
-                _ => panic!("Didn't unsubscribe!"),
-            }
-            Ok(())
-        })
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::Unsubscribe(
+                self.uuid,
+                characteristic.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => {}
+            _ => panic!("Didn't unsubscribe!"),
+        }
+        Ok(())
     }
 
     /// Registers a handler that will be called when value notification messages are received from
     /// the device. This method should only be used after a connection has been established. Note
     /// that the handler will be called in a common thread, so it should not block.
-    fn on_notification(&self, handler: NotificationHandler) {
-        let mut list = self.notification_handlers.lock().unwrap();
-        list.push(handler);
+    fn on_notification(&self, handler: NotificationHandler) -> NotificationHandlerId {
+        self.notification_handlers.add(handler)
     }
 
-    fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+    fn remove_notification_handler(&self, id: NotificationHandlerId) -> bool {
+        self.notification_handlers.remove(id)
+    }
+
+    fn notifications(&self, characteristic: &Characteristic) -> NotificationReceiver {
+        NotificationReceiver::new(&self.notification_handlers, characteristic)
+    }
+
+    fn set_notification_queue(&self, capacity: usize, overflow: OverflowPolicy) -> Result<()> {
+        self.notification_handlers.set_queue(capacity, overflow)
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
         info!("Trying read!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::ReadValue(
-                    self.uuid,
-                    characteristic.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::ReadResult(chars) => Ok(chars),
-                _ => {
-                    panic!("Shouldn't get anything but read result!");
-                }
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::ReadValue(
+                self.uuid,
+                characteristic.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::ReadResult(chars) => Ok(chars),
+            _ => {
+                panic!("Shouldn't get anything but read result!");
             }
-        })
+        }
     }
 }
 
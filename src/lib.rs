@@ -74,6 +74,11 @@
 //!     }
 //! }
 //! ```
+//!
+//! Every operation is also available as a future, through the
+//! [`AsyncCentral`](api/trait.AsyncCentral.html) and
+//! [`AsyncPeripheral`](api/trait.AsyncPeripheral.html) traits. These don't depend on a particular
+//! async runtime. As the blocking and async traits share method names, import only one of them.
 
 // We won't actually use anything specifically out of this crate. However, if we
 // want the CoreBluetooth code to compile, we need the objc protocols
@@ -88,6 +93,8 @@ pub mod api;
 #[cfg(target_os = "linux")]
 pub mod bluez;
 mod common;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 pub mod corebluetooth;
 #[cfg(target_os = "windows")]
@@ -101,15 +108,66 @@ pub enum Error {
     #[error("Device not found")]
     DeviceNotFound,
 
+    #[error("Adapter not found")]
+    AdapterNotFound,
+
     #[error("Not connected")]
     NotConnected,
 
+    #[error("Characteristic {} not found, the services of the device may have changed", _0)]
+    CharacteristicNotFound(uuid::Uuid),
+
+    #[error("Descriptor {} not found, the services of the device may have changed", _0)]
+    DescriptorNotFound(uuid::Uuid),
+
     #[error("The operation is not supported: {}", _0)]
     NotSupported(String),
 
     #[error("Timed out after {:?}", _0)]
     TimedOut(Duration),
 
+    #[error("Not permitted: {}", _0)]
+    NotPermitted(String),
+
+    #[error("Not authorized: {}", _0)]
+    NotAuthorized(String),
+
+    #[error("Operation already in progress: {}", _0)]
+    InProgress(String),
+
+    #[error("Not ready: {}", _0)]
+    NotReady(String),
+
+    #[error("Operation failed: {}", _0)]
+    Failed(String),
+
+    #[error("Invalid value length: {}", _0)]
+    InvalidValueLength(String),
+
+    #[error("Access denied: {}", _0)]
+    AccessDenied(String),
+
+    #[error("Authentication failed: {}", _0)]
+    AuthenticationFailed(String),
+
+    #[error("Authentication canceled: {}", _0)]
+    AuthenticationCanceled(String),
+
+    #[error("Authentication rejected: {}", _0)]
+    AuthenticationRejected(String),
+
+    #[error("Authentication timed out: {}", _0)]
+    AuthenticationTimeout(String),
+
+    #[error("Connection attempt failed: {}", _0)]
+    ConnectionFailed(String),
+
+    #[error("No reply: {}", _0)]
+    NoReply(String),
+
+    #[error("ATT error: {}", _0)]
+    Att(api::AttErrorCode),
+
     #[error("{}", _0)]
     Other(String),
 }
@@ -0,0 +1,175 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::peripheral::MockPeripheral;
+use crate::{
+    api::{
+        AdapterInfo, AdapterManager, AdapterProperty, AsyncCentral, BDAddr, CentralEvent,
+        EventReceiver, EventSubscription, PeripheralProperties,
+    },
+    Result,
+};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+struct ScanState {
+    scanning: bool,
+    active: bool,
+    filter_duplicates: bool,
+}
+
+/// A scriptable adapter. Clones share the same state.
+#[derive(Clone, Debug)]
+pub struct MockAdapter {
+    manager: AdapterManager<MockPeripheral>,
+    scan: Arc<Mutex<ScanState>>,
+    info: Arc<Mutex<AdapterInfo>>,
+}
+
+impl MockAdapter {
+    pub fn new() -> Self {
+        MockAdapter {
+            manager: AdapterManager::new(),
+            scan: Arc::new(Mutex::new(ScanState {
+                scanning: false,
+                active: true,
+                filter_duplicates: true,
+            })),
+            info: Arc::new(Mutex::new(AdapterInfo {
+                powered: Some(true),
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Sets the configuration returned by `adapter_info`, without emitting any event. The
+    /// `discovering` field always reflects whether the adapter is scanning.
+    pub fn set_info(&self, info: AdapterInfo) {
+        *self.info.lock().unwrap() = info;
+    }
+
+    /// Changes a property of the adapter, and announces it with `AdapterUpdated`.
+    pub fn update_property(&self, property: AdapterProperty) {
+        {
+            let mut info = self.info.lock().unwrap();
+            match property.clone() {
+                AdapterProperty::Name(name) => info.name = Some(name),
+                AdapterProperty::Alias(alias) => info.alias = Some(alias),
+                AdapterProperty::Class(class) => info.class = Some(class),
+                AdapterProperty::Powered(powered) => info.powered = Some(powered),
+                AdapterProperty::Discoverable(discoverable) => {
+                    info.discoverable = Some(discoverable)
+                }
+                AdapterProperty::DiscoverableTimeout(timeout) => {
+                    info.discoverable_timeout = timeout
+                }
+                AdapterProperty::Pairable(pairable) => info.pairable = Some(pairable),
+                AdapterProperty::PairableTimeout(timeout) => info.pairable_timeout = timeout,
+                AdapterProperty::Discovering(discovering) => {
+                    self.scan.lock().unwrap().scanning = discovering
+                }
+                AdapterProperty::Services(services) => info.services = services,
+            }
+        }
+        self.manager.emit(CentralEvent::AdapterUpdated(property));
+    }
+
+    /// Delivers an advertisement from the device at `properties.address`. The first advertisement
+    /// from a device announces it with `DeviceDiscovered`, later ones update its properties and
+    /// emit `DeviceUpdated`. Manufacturer data, service data and services are announced with their
+    /// own events, like the real backends do.
+    ///
+    /// As with a real adapter, advertisements are only received while scanning. Returns the
+    /// peripheral that advertised, or `None` if the adapter isn't scanning.
+    pub fn advertise(&self, properties: PeripheralProperties) -> Option<MockPeripheral> {
+        if !self.is_scanning() {
+            return None;
+        }
+        let address = properties.address;
+        let peripheral = self
+            .manager
+            .peripheral(address)
+            .unwrap_or_else(|| MockPeripheral::new(self.manager.clone(), address));
+        peripheral.update_properties(properties);
+        if self.manager.has_peripheral(&address) {
+            self.manager.emit(CentralEvent::DeviceUpdated(address));
+        } else {
+            self.manager.add_peripheral(address, peripheral.clone());
+            self.manager.emit(CentralEvent::DeviceDiscovered(address));
+        }
+        Some(peripheral)
+    }
+
+    /// Reports the device as gone, emitting `DeviceLost`.
+    pub fn lose(&self, address: BDAddr) {
+        self.manager.emit(CentralEvent::DeviceLost(address));
+    }
+
+    /// Returns true while a scan is running.
+    pub fn is_scanning(&self) -> bool {
+        self.scan.lock().unwrap().scanning
+    }
+
+    /// Returns the last value passed to `active`.
+    pub fn is_active(&self) -> bool {
+        self.scan.lock().unwrap().active
+    }
+
+    /// Returns the last value passed to `filter_duplicates`.
+    pub fn is_filtering_duplicates(&self) -> bool {
+        self.scan.lock().unwrap().filter_duplicates
+    }
+}
+
+impl Default for MockAdapter {
+    fn default() -> Self {
+        MockAdapter::new()
+    }
+}
+
+#[async_trait]
+impl AsyncCentral<MockPeripheral> for MockAdapter {
+    fn subscribe_events(&self, subscription: EventSubscription) -> EventReceiver {
+        self.manager.subscribe_events(subscription)
+    }
+
+    async fn start_scan(&self) -> Result<()> {
+        self.scan.lock().unwrap().scanning = true;
+        Ok(())
+    }
+
+    fn active(&self, enabled: bool) -> Result<()> {
+        self.scan.lock().unwrap().active = enabled;
+        Ok(())
+    }
+
+    fn filter_duplicates(&self, enabled: bool) -> Result<()> {
+        self.scan.lock().unwrap().filter_duplicates = enabled;
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        self.scan.lock().unwrap().scanning = false;
+        Ok(())
+    }
+
+    fn peripherals(&self) -> Vec<MockPeripheral> {
+        self.manager.peripherals()
+    }
+
+    fn peripheral(&self, address: BDAddr) -> Option<MockPeripheral> {
+        self.manager.peripheral(address)
+    }
+
+    async fn adapter_info(&self) -> Result<AdapterInfo> {
+        Ok(AdapterInfo {
+            discovering: Some(self.is_scanning()),
+            ..self.info.lock().unwrap().clone()
+        })
+    }
+}
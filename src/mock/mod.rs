@@ -0,0 +1,50 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! An in-memory backend, for testing code written against the [`Central`](../api/trait.Central.html)
+//! and [`Peripheral`](../api/trait.Peripheral.html) traits without any Bluetooth hardware.
+//!
+//! Tests drive a [`MockAdapter`] by injecting advertisements, and describe each [`MockPeripheral`]
+//! through a GATT table with values. They can then push notifications, simulate disconnections and
+//! inspect what the code under test wrote. Events go through the same `AdapterManager` as the real
+//! backends, so they follow the same rules.
+//!
+//! ```
+//! use btleplug::api::{bleuuid::uuid_from_u16, Central, CharPropFlags, Peripheral, PeripheralProperties, WriteType};
+//! use btleplug::mock::MockAdapter;
+//!
+//! let adapter = MockAdapter::new();
+//! adapter.start_scan().unwrap();
+//! let device = adapter
+//!     .advertise(PeripheralProperties {
+//!         address: "01:02:03:04:05:06".parse().unwrap(),
+//!         local_name: Some("Light".to_string()),
+//!         ..Default::default()
+//!     })
+//!     .unwrap();
+//! let service = device.add_service(uuid_from_u16(0xFFE5));
+//! device.add_characteristic(service, uuid_from_u16(0xFFE9), CharPropFlags::WRITE, &[]);
+//!
+//! // The code under test sees a regular peripheral.
+//! let light = adapter.peripherals().remove(0);
+//! light.connect().unwrap();
+//! light.discover_characteristics().unwrap();
+//! let characteristic = light
+//!     .characteristic(uuid_from_u16(0xFFE5), uuid_from_u16(0xFFE9))
+//!     .unwrap();
+//! light.write(&characteristic, &[0x56, 0xFF], WriteType::WithResponse).unwrap();
+//!
+//! assert_eq!(device.writes()[0].data, vec![0x56, 0xFF]);
+//! ```
+//!
+//! This module is only available with the `mock` feature.
+
+mod adapter;
+mod peripheral;
+
+pub use self::adapter::MockAdapter;
+pub use self::peripheral::{MockPeripheral, MockWrite};
@@ -0,0 +1,774 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+    api::{
+        notifications::NotificationHandlers, AdapterManager, AsyncPeripheral, AttErrorCode, BDAddr,
+        CentralEvent, CharPropFlags, Characteristic, Descriptor, IncludedService,
+        NotificationHandler, NotificationHandlerId, NotificationReceiver, OverflowPolicy,
+        PeripheralProperties, Service, ValueNotification, WriteType,
+    },
+    Error, Result,
+};
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+/// A write received by a [`MockPeripheral`], either to a characteristic or to a descriptor.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MockWrite {
+    /// The handle of the characteristic value or descriptor that was written.
+    pub handle: u16,
+    /// The UUID of the characteristic or descriptor that was written.
+    pub uuid: Uuid,
+    /// The data that was written.
+    pub data: Vec<u8>,
+    /// The type of write. Descriptor writes are always `WithResponse`.
+    pub write_type: WriteType,
+}
+
+#[derive(Debug)]
+struct MockService {
+    handle: u16,
+    uuid: Uuid,
+    primary: bool,
+    included_services: Vec<IncludedService>,
+    characteristics: Vec<Characteristic>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    connected: bool,
+    discovered: bool,
+    services: Vec<MockService>,
+    descriptors: BTreeMap<u16, (Descriptor, u16)>,
+    values: BTreeMap<u16, Vec<u8>>,
+    subscribed: HashSet<u16>,
+    writes: Vec<MockWrite>,
+    next_error: Option<Error>,
+    last_handle: u16,
+}
+
+impl MockState {
+    fn next_handle(&mut self) -> u16 {
+        self.last_handle += 1;
+        self.last_handle
+    }
+
+    fn characteristic(&self, handle: u16) -> Option<&Characteristic> {
+        self.services
+            .iter()
+            .flat_map(|s| s.characteristics.iter())
+            .find(|c| c.value_handle == handle)
+    }
+
+    /// Checks that an operation can be performed, consuming the scripted error if there is one.
+    fn check(&mut self) -> Result<()> {
+        if let Some(error) = self.next_error.take() {
+            return Err(error);
+        }
+        if !self.connected {
+            return Err(Error::NotConnected);
+        }
+        Ok(())
+    }
+}
+
+/// A scriptable peripheral, created by [`MockAdapter::advertise`](struct.MockAdapter.html#method.advertise).
+/// Clones share the same state, so the peripheral returned to the test and the one seen by the code
+/// under test through the `Central` are interchangeable.
+///
+/// Attribute handles are allocated in the order services, characteristics and descriptors are
+/// added.
+#[derive(Clone)]
+pub struct MockPeripheral {
+    manager: AdapterManager<Self>,
+    address: BDAddr,
+    properties: Arc<Mutex<PeripheralProperties>>,
+    state: Arc<Mutex<MockState>>,
+    notification_handlers: Arc<NotificationHandlers>,
+}
+
+impl MockPeripheral {
+    pub(crate) fn new(manager: AdapterManager<Self>, address: BDAddr) -> Self {
+        let properties = PeripheralProperties {
+            address,
+            ..Default::default()
+        };
+        MockPeripheral {
+            manager,
+            address,
+            properties: Arc::new(Mutex::new(properties)),
+            state: Arc::new(Mutex::new(MockState::default())),
+            notification_handlers: Arc::new(NotificationHandlers::default()),
+        }
+    }
+
+    pub(crate) fn update_properties(&self, advertisement: PeripheralProperties) {
+        let mut properties = self.properties.lock().unwrap();
+        properties.discovery_count += 1;
+        properties.address_type = advertisement.address_type;
+        properties.has_scan_response |= advertisement.has_scan_response;
+        if advertisement.local_name.is_some() {
+            properties.local_name = advertisement.local_name;
+        }
+        if advertisement.tx_power_level.is_some() {
+            properties.tx_power_level = advertisement.tx_power_level;
+        }
+        if let Some(rssi) = advertisement.rssi {
+            properties.rssi = Some(rssi);
+            properties.signal.record(rssi);
+        }
+        for (manufacturer_id, data) in advertisement.manufacturer_data {
+            self.manager
+                .emit(CentralEvent::ManufacturerDataAdvertisement {
+                    address: self.address,
+                    manufacturer_id,
+                    data: data.clone(),
+                });
+            properties.manufacturer_data.insert(manufacturer_id, data);
+        }
+        for (service, data) in advertisement.service_data {
+            self.manager.emit(CentralEvent::ServiceDataAdvertisement {
+                address: self.address,
+                service,
+                data: data.clone(),
+            });
+            properties.service_data.insert(service, data);
+        }
+        if !advertisement.services.is_empty() {
+            self.manager.emit(CentralEvent::ServicesAdvertisement {
+                address: self.address,
+                services: advertisement.services.clone(),
+            });
+            properties.services = advertisement.services;
+        }
+    }
+
+    /// Adds a primary service to the GATT table, returning its handle.
+    pub fn add_service(&self, uuid: Uuid) -> u16 {
+        self.add_service_with(uuid, true, Vec::new())
+    }
+
+    /// Adds a service to the GATT table, which may be a secondary service and may include the
+    /// services with the given handles. Returns its handle.
+    ///
+    /// # Panics
+    ///
+    /// If there is no service with one of the included handles.
+    pub fn add_service_with(&self, uuid: Uuid, primary: bool, included_services: Vec<u16>) -> u16 {
+        let mut state = self.state.lock().unwrap();
+        let included_services = included_services
+            .into_iter()
+            .map(|handle| IncludedService {
+                handle,
+                uuid: state
+                    .services
+                    .iter()
+                    .find(|s| s.handle == handle)
+                    .expect("No service with this handle")
+                    .uuid,
+            })
+            .collect();
+        let handle = state.next_handle();
+        state.services.push(MockService {
+            handle,
+            uuid,
+            primary,
+            included_services,
+            characteristics: Vec::new(),
+        });
+        handle
+    }
+
+    /// Adds a characteristic with an initial value to the service with the given handle.
+    ///
+    /// # Panics
+    ///
+    /// If there is no service with this handle.
+    pub fn add_characteristic(
+        &self,
+        service: u16,
+        uuid: Uuid,
+        properties: CharPropFlags,
+        value: &[u8],
+    ) -> Characteristic {
+        let mut state = self.state.lock().unwrap();
+        let handle = state.next_handle();
+        let characteristic = Characteristic {
+            start_handle: handle,
+            end_handle: handle,
+            value_handle: handle,
+            uuid,
+            properties,
+        };
+        state
+            .services
+            .iter_mut()
+            .find(|s| s.handle == service)
+            .expect("No service with this handle")
+            .characteristics
+            .push(characteristic.clone());
+        state.values.insert(handle, value.to_vec());
+        characteristic
+    }
+
+    /// Adds a descriptor with an initial value to a characteristic.
+    ///
+    /// # Panics
+    ///
+    /// If the characteristic wasn't added to this peripheral.
+    pub fn add_descriptor(
+        &self,
+        characteristic: &Characteristic,
+        uuid: Uuid,
+        value: &[u8],
+    ) -> Descriptor {
+        let mut state = self.state.lock().unwrap();
+        let handle = state.next_handle();
+        let owner = state
+            .services
+            .iter_mut()
+            .flat_map(|s| s.characteristics.iter_mut())
+            .find(|c| c.value_handle == characteristic.value_handle)
+            .expect("Unknown characteristic");
+        owner.end_handle = handle;
+        let descriptor = Descriptor {
+            handle,
+            uuid,
+            characteristic_uuid: owner.uuid,
+        };
+        state
+            .descriptors
+            .insert(handle, (descriptor.clone(), characteristic.value_handle));
+        state.values.insert(handle, value.to_vec());
+        descriptor
+    }
+
+    /// Returns the current value of a characteristic.
+    pub fn value(&self, characteristic: &Characteristic) -> Option<Vec<u8>> {
+        self.handle_value(characteristic.value_handle)
+    }
+
+    /// Returns the current value of a descriptor.
+    pub fn descriptor_value(&self, descriptor: &Descriptor) -> Option<Vec<u8>> {
+        self.handle_value(descriptor.handle)
+    }
+
+    fn handle_value(&self, handle: u16) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().values.get(&handle).cloned()
+    }
+
+    /// Changes the value of a characteristic, without notifying anyone.
+    pub fn set_value(&self, characteristic: &Characteristic, value: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .values
+            .insert(characteristic.value_handle, value.to_vec());
+    }
+
+    /// Changes the value of a characteristic and sends a notification for it. As with a real
+    /// device, the notification is only delivered if the characteristic is subscribed to. Returns
+    /// true if it was delivered.
+    pub fn notify(&self, characteristic: &Characteristic, value: &[u8]) -> bool {
+        let subscribed = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .values
+                .insert(characteristic.value_handle, value.to_vec());
+            state.connected && state.subscribed.contains(&characteristic.value_handle)
+        };
+        if subscribed {
+            let notification = ValueNotification {
+                uuid: characteristic.uuid,
+                handle: Some(characteristic.value_handle),
+                value: value.into(),
+            };
+            self.notification_handlers.notify(notification);
+        }
+        subscribed
+    }
+
+    /// Returns true if the characteristic is subscribed to.
+    pub fn is_subscribed(&self, characteristic: &Characteristic) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .subscribed
+            .contains(&characteristic.value_handle)
+    }
+
+    /// Returns the characteristics that are subscribed to.
+    pub fn subscribed_characteristics(&self) -> BTreeSet<Characteristic> {
+        let state = self.state.lock().unwrap();
+        state
+            .subscribed
+            .iter()
+            .filter_map(|handle| state.characteristic(*handle).cloned())
+            .collect()
+    }
+
+    /// Returns every write received so far, in order.
+    pub fn writes(&self) -> Vec<MockWrite> {
+        self.state.lock().unwrap().writes.clone()
+    }
+
+    /// Makes the next connect, read, write, subscribe or unsubscribe fail with `error`.
+    pub fn fail_next(&self, error: Error) {
+        self.state.lock().unwrap().next_error = Some(error);
+    }
+
+    /// Drops the connection from the device side, as if it went out of range. Subscriptions are
+    /// lost and `DeviceDisconnected` is emitted.
+    pub fn simulate_disconnect(&self) {
+        if self.set_disconnected() {
+            self.manager
+                .emit(CentralEvent::DeviceDisconnected(self.address));
+        }
+    }
+
+    fn set_disconnected(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let was_connected = state.connected;
+        state.connected = false;
+        state.subscribed.clear();
+        was_connected
+    }
+}
+
+impl Debug for MockPeripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("MockPeripheral")
+            .field("address", &self.address)
+            .field("properties", &self.properties)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl AsyncPeripheral for MockPeripheral {
+    fn address(&self) -> BDAddr {
+        self.address
+    }
+
+    fn properties(&self) -> PeripheralProperties {
+        self.properties.lock().unwrap().clone()
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.services()
+            .into_iter()
+            .flat_map(|s| s.characteristics.into_iter())
+            .collect()
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        let state = self.state.lock().unwrap();
+        if !state.discovered {
+            return BTreeSet::new();
+        }
+        state
+            .services
+            .iter()
+            .map(|s| Service {
+                handle: s.handle,
+                uuid: s.uuid,
+                primary: s.primary,
+                included_services: s.included_services.clone(),
+                characteristics: s.characteristics.iter().cloned().collect(),
+            })
+            .collect()
+    }
+
+    fn descriptors(&self, characteristic: &Characteristic) -> BTreeSet<Descriptor> {
+        let state = self.state.lock().unwrap();
+        if !state.discovered {
+            return BTreeSet::new();
+        }
+        state
+            .descriptors
+            .values()
+            .filter(|(_d, owner)| *owner == characteristic.value_handle)
+            .map(|(d, _owner)| d.clone())
+            .collect()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().connected
+    }
+
+    async fn connect(&self) -> Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(error) = state.next_error.take() {
+                return Err(error);
+            }
+            if state.connected {
+                return Ok(());
+            }
+            state.connected = true;
+        }
+        self.manager
+            .emit(CentralEvent::DeviceConnected(self.address));
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if self.set_disconnected() {
+            self.manager
+                .emit(CentralEvent::DeviceDisconnected(self.address));
+        }
+        Ok(())
+    }
+
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.connected {
+                return Err(Error::NotConnected);
+            }
+            state.discovered = true;
+        }
+        Ok(self.characteristics().into_iter().collect())
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        let properties = state
+            .characteristic(characteristic.value_handle)
+            .ok_or_else(|| Error::NotSupported("write".to_string()))?
+            .properties;
+        let allowed = match write_type {
+            WriteType::WithResponse => CharPropFlags::WRITE,
+            WriteType::WithoutResponse => CharPropFlags::WRITE_WITHOUT_RESPONSE,
+        };
+        if !properties.contains(allowed) {
+            return Err(Error::Att(AttErrorCode::WriteNotPermitted));
+        }
+        state
+            .values
+            .insert(characteristic.value_handle, data.to_vec());
+        state.writes.push(MockWrite {
+            handle: characteristic.value_handle,
+            uuid: characteristic.uuid,
+            data: data.to_vec(),
+            write_type,
+        });
+        Ok(())
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        let properties = state
+            .characteristic(characteristic.value_handle)
+            .ok_or_else(|| Error::NotSupported("read".to_string()))?
+            .properties;
+        if !properties.contains(CharPropFlags::READ) {
+            return Err(Error::Att(AttErrorCode::ReadNotPermitted));
+        }
+        Ok(state.values[&characteristic.value_handle].clone())
+    }
+
+    async fn read_by_type(&self, _characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
+        Err(Error::NotSupported("read_by_type".into()))
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        if !state.descriptors.contains_key(&descriptor.handle) {
+            return Err(Error::NotSupported("read_descriptor".to_string()));
+        }
+        Ok(state.values[&descriptor.handle].clone())
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        if !state.descriptors.contains_key(&descriptor.handle) {
+            return Err(Error::NotSupported("write_descriptor".to_string()));
+        }
+        state.values.insert(descriptor.handle, data.to_vec());
+        state.writes.push(MockWrite {
+            handle: descriptor.handle,
+            uuid: descriptor.uuid,
+            data: data.to_vec(),
+            write_type: WriteType::WithResponse,
+        });
+        Ok(())
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        let properties = state
+            .characteristic(characteristic.value_handle)
+            .ok_or_else(|| Error::NotSupported("subscribe".to_string()))?
+            .properties;
+        if !properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE) {
+            return Err(Error::NotSupported("subscribe".to_string()));
+        }
+        state.subscribed.insert(characteristic.value_handle);
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check()?;
+        state.subscribed.remove(&characteristic.value_handle);
+        Ok(())
+    }
+
+    fn on_notification(&self, handler: NotificationHandler) -> NotificationHandlerId {
+        self.notification_handlers.add(handler)
+    }
+
+    fn remove_notification_handler(&self, id: NotificationHandlerId) -> bool {
+        self.notification_handlers.remove(id)
+    }
+
+    fn notifications(&self, characteristic: &Characteristic) -> NotificationReceiver {
+        NotificationReceiver::new(&self.notification_handlers, characteristic)
+    }
+
+    fn set_notification_queue(&self, capacity: usize, overflow: OverflowPolicy) -> Result<()> {
+        self.notification_handlers.set_queue(capacity, overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MockAdapter;
+    use crate::api::{
+        bleuuid::uuid_from_u16, AttErrorCode, Central, CentralEvent, CharPropFlags, Peripheral,
+        PeripheralProperties, WriteType,
+    };
+    use crate::Error;
+    use std::{
+        sync::{mpsc::channel, Mutex},
+        time::Duration,
+    };
+
+    fn address() -> crate::api::BDAddr {
+        "0A:0B:0C:0D:0E:0F".parse().unwrap()
+    }
+
+    #[test]
+    fn advertisements_emit_events() {
+        let adapter = MockAdapter::new();
+        let events = adapter.event_receiver();
+        let advertisement = PeripheralProperties {
+            address: address(),
+            local_name: Some("Sensor".to_string()),
+            services: vec![uuid_from_u16(0x180F)],
+            tx_power_level: Some(4),
+            rssi: Some(-50),
+            ..Default::default()
+        };
+        // Nothing is received before scanning.
+        assert!(adapter.advertise(advertisement.clone()).is_none());
+        assert!(adapter.peripherals().is_empty());
+
+        adapter.start_scan().unwrap();
+        adapter.advertise(advertisement.clone()).unwrap();
+        // Taken before the next advertisement, which still updates it.
+        let discovered = adapter.peripheral(address()).unwrap();
+        adapter
+            .advertise(PeripheralProperties {
+                tx_power_level: None,
+                rssi: Some(-70),
+                ..advertisement
+            })
+            .unwrap();
+        let events: Vec<CentralEvent> = events.try_iter().collect();
+        assert!(matches!(
+            events[0],
+            CentralEvent::ServicesAdvertisement { .. }
+        ));
+        assert!(matches!(events[1], CentralEvent::DeviceDiscovered(a) if a == address()));
+        assert!(matches!(events[3], CentralEvent::DeviceUpdated(a) if a == address()));
+
+        for peripheral in vec![discovered, adapter.peripheral(address()).unwrap()] {
+            let properties = peripheral.properties();
+            assert_eq!(properties.local_name.unwrap(), "Sensor");
+            assert_eq!(properties.discovery_count, 2);
+            assert_eq!(properties.tx_power_level, Some(4));
+            assert_eq!(properties.rssi, Some(-70));
+            let signal = properties.signal;
+            assert_eq!(
+                (signal.min, signal.max, signal.count),
+                (Some(-70), Some(-50), 2)
+            );
+        }
+    }
+
+    #[test]
+    fn included_services_are_told_apart() {
+        use crate::api::IncludedService;
+
+        let adapter = MockAdapter::new();
+        adapter.start_scan().unwrap();
+        let device = adapter
+            .advertise(PeripheralProperties {
+                address: address(),
+                ..Default::default()
+            })
+            .unwrap();
+        device.add_service_with(uuid_from_u16(0x1800), false, Vec::new());
+        let included = device.add_service_with(uuid_from_u16(0x1800), false, Vec::new());
+        let including = device.add_service_with(uuid_from_u16(0x180F), true, vec![included]);
+        device.connect().unwrap();
+        device.discover_characteristics().unwrap();
+
+        let service = device
+            .services()
+            .into_iter()
+            .find(|s| s.handle == including)
+            .unwrap();
+        assert_eq!(
+            service.included_services,
+            vec![IncludedService {
+                handle: included,
+                uuid: uuid_from_u16(0x1800)
+            }]
+        );
+    }
+
+    #[test]
+    fn gatt_table_reads_writes_and_notifications() {
+        let adapter = MockAdapter::new();
+        adapter.start_scan().unwrap();
+        let device = adapter
+            .advertise(PeripheralProperties {
+                address: address(),
+                ..Default::default()
+            })
+            .unwrap();
+        let service = device.add_service(uuid_from_u16(0x180F));
+        let level = device.add_characteristic(
+            service,
+            uuid_from_u16(0x2A19),
+            CharPropFlags::READ | CharPropFlags::NOTIFY,
+            &[42],
+        );
+        let description = device.add_descriptor(&level, uuid_from_u16(0x2901), b"Level");
+
+        let peripheral = adapter.peripheral(address()).unwrap();
+        assert!(matches!(peripheral.read(&level), Err(Error::NotConnected)));
+        peripheral.connect().unwrap();
+        assert!(peripheral.characteristics().is_empty());
+        peripheral.discover_characteristics().unwrap();
+        assert_eq!(peripheral.services().len(), 1);
+        assert_eq!(
+            peripheral
+                .descriptors(&level)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![description.clone()]
+        );
+
+        assert_eq!(peripheral.read(&level).unwrap(), vec![42]);
+        assert_eq!(peripheral.read_descriptor(&description).unwrap(), b"Level");
+        assert!(matches!(
+            peripheral.write(&level, &[1], WriteType::WithResponse),
+            Err(Error::Att(AttErrorCode::WriteNotPermitted))
+        ));
+        peripheral
+            .write_descriptor(&description, b"Battery")
+            .unwrap();
+        assert_eq!(device.writes()[0].data, b"Battery".to_vec());
+
+        let (sender, received) = channel();
+        let sender = Mutex::new(sender);
+        let handler = peripheral.on_notification(Box::new(move |n| {
+            let _ = sender.lock().unwrap().send(n.value);
+        }));
+        assert!(!device.notify(&level, &[41]));
+        peripheral.subscribe(&level).unwrap();
+        assert_eq!(
+            device
+                .subscribed_characteristics()
+                .iter()
+                .map(|c| c.value_handle)
+                .collect::<Vec<_>>(),
+            vec![level.value_handle]
+        );
+        let notifications = peripheral.notifications(&level);
+        assert!(device.notify(&level, &[40]));
+        let timeout = Duration::from_secs(5);
+        assert_eq!(*received.recv_timeout(timeout).unwrap(), [40]);
+        assert_eq!(peripheral.read(&level).unwrap(), vec![40]);
+
+        // Handlers are called in order, so the removed one would be called before the receiver.
+        assert!(peripheral.remove_notification_handler(handler));
+        assert!(device.notify(&level, &[40]));
+        assert_eq!(*notifications.recv_timeout(timeout).unwrap().value, [40]);
+        assert_eq!(*notifications.recv_timeout(timeout).unwrap().value, [40]);
+        assert!(received.try_recv().is_err());
+
+        device.fail_next(Error::Att(AttErrorCode::InsufficientAuthentication));
+        assert!(matches!(
+            peripheral.read(&level),
+            Err(Error::Att(AttErrorCode::InsufficientAuthentication))
+        ));
+        assert_eq!(peripheral.read(&level).unwrap(), vec![40]);
+    }
+
+    #[test]
+    fn simulated_disconnect() {
+        let adapter = MockAdapter::new();
+        let events = adapter.event_receiver();
+        adapter.start_scan().unwrap();
+        let device = adapter
+            .advertise(PeripheralProperties {
+                address: address(),
+                ..Default::default()
+            })
+            .unwrap();
+        device.connect().unwrap();
+        device.simulate_disconnect();
+        assert!(!device.is_connected());
+        let events: Vec<CentralEvent> = events.try_iter().collect();
+        assert!(matches!(events[1], CentralEvent::DeviceConnected(_)));
+        assert!(matches!(events[2], CentralEvent::DeviceDisconnected(_)));
+    }
+    #[test]
+    fn adapter_info_and_updates() {
+        use crate::api::{AdapterInfo, AdapterProperty};
+
+        let adapter = MockAdapter::new();
+        let events = adapter.event_receiver();
+        adapter.set_info(AdapterInfo {
+            name: Some("hci0".to_string()),
+            ..Default::default()
+        });
+        adapter.start_scan().unwrap();
+        adapter.update_property(AdapterProperty::Powered(false));
+
+        let info = adapter.adapter_info().unwrap();
+        assert_eq!(info.name.as_deref(), Some("hci0"));
+        assert_eq!(info.powered, Some(false));
+        assert_eq!(info.discovering, Some(true));
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            CentralEvent::AdapterUpdated(AdapterProperty::Powered(false))
+        ));
+    }
+}
@@ -13,10 +13,11 @@
 
 use super::{ble::watcher::BLEWatcher, peripheral::Peripheral, utils};
 use crate::{
-    api::{AdapterManager, BDAddr, Central, CentralEvent},
+    api::{AdapterManager, AsyncCentral, BDAddr, CentralEvent, EventReceiver, EventSubscription},
     Result,
 };
-use std::sync::{mpsc::Receiver, Arc, Mutex};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
 pub struct Adapter {
@@ -32,12 +33,13 @@ impl Adapter {
     }
 }
 
-impl Central<Peripheral> for Adapter {
-    fn event_receiver(&self) -> Option<Receiver<CentralEvent>> {
-        self.manager.event_receiver()
+#[async_trait]
+impl AsyncCentral<Peripheral> for Adapter {
+    fn subscribe_events(&self, subscription: EventSubscription) -> EventReceiver {
+        self.manager.subscribe_events(subscription)
     }
 
-    fn start_scan(&self) -> Result<()> {
+    async fn start_scan(&self) -> Result<()> {
         let watcher = self.watcher.lock().unwrap();
         let manager = self.manager.clone();
         watcher.start(Box::new(move |args| {
@@ -51,13 +53,12 @@ impl Central<Peripheral> for Adapter {
                 manager.add_peripheral(address, peripheral);
                 manager.emit(CentralEvent::DeviceDiscovered(address));
             } else {
-                manager.update_peripheral(address, peripheral);
                 manager.emit(CentralEvent::DeviceUpdated(address));
             }
         }))
     }
 
-    fn stop_scan(&self) -> Result<()> {
+    async fn stop_scan(&self) -> Result<()> {
         let watcher = self.watcher.lock().unwrap();
         watcher.stop().unwrap();
         Ok(())
@@ -71,7 +72,11 @@ impl Central<Peripheral> for Adapter {
         self.manager.peripheral(address)
     }
 
-    fn active(&self, _enabled: bool) {}
+    fn active(&self, _enabled: bool) -> Result<()> {
+        Ok(())
+    }
 
-    fn filter_duplicates(&self, _enabled: bool) {}
+    fn filter_duplicates(&self, _enabled: bool) -> Result<()> {
+        Ok(())
+    }
 }
@@ -18,12 +18,14 @@ use super::{
 use crate::{
     api::{
         bleuuid::{uuid_from_u16, uuid_from_u32},
-        AdapterManager, AddressType, BDAddr, CentralEvent, Characteristic, NotificationHandler,
-        Peripheral as ApiPeripheral, PeripheralProperties, ValueNotification, WriteType,
+        notifications::NotificationHandlers,
+        AdapterManager, AddressType, AsyncPeripheral, BDAddr, CentralEvent, Characteristic,
+        Descriptor, NotificationHandler, NotificationHandlerId, NotificationReceiver,
+        OverflowPolicy, PeripheralProperties, Service, ValueNotification, WriteType,
     },
-    common::util,
     Error, Result,
 };
+use async_trait::async_trait;
 use dashmap::DashMap;
 use std::{
     collections::BTreeSet,
@@ -45,7 +47,7 @@ pub struct Peripheral {
     characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
     connected: Arc<AtomicBool>,
     ble_characteristics: Arc<DashMap<Uuid, BLECharacteristic>>,
-    notification_handlers: Arc<Mutex<Vec<NotificationHandler>>>,
+    notification_handlers: Arc<NotificationHandlers>,
 }
 
 impl Peripheral {
@@ -57,7 +59,7 @@ impl Peripheral {
         let characteristics = Arc::new(Mutex::new(BTreeSet::new()));
         let connected = Arc::new(AtomicBool::new(false));
         let ble_characteristics = Arc::new(DashMap::new());
-        let notification_handlers = Arc::new(Mutex::new(Vec::new()));
+        let notification_handlers = Arc::new(NotificationHandlers::default());
         Peripheral {
             device,
             adapter,
@@ -142,6 +144,18 @@ impl Peripheral {
                 .collect();
         }
 
+        // The TX power level is likewise only available as a raw data section.
+        if let Ok(data_sections) = advertisement.data_sections() {
+            for d in data_sections {
+                if d.data_type().unwrap() == advertisement_data_type::TX_POWER_LEVEL {
+                    let data = utils::to_vec(&d.data().unwrap());
+                    if let Some(&tx_power) = data.first() {
+                        properties.tx_power_level = Some(tx_power as i8);
+                    }
+                }
+            }
+        }
+
         if let Ok(services) = advertisement.service_uuids() {
             properties.services = services
                 .into_iter()
@@ -159,10 +173,10 @@ impl Peripheral {
         properties.address_type = AddressType::default();
         properties.has_scan_response =
             args.advertisement_type().unwrap() == BluetoothLEAdvertisementType::ScanResponse;
-        properties.tx_power_level = args
-            .raw_signal_strength_in_dbm()
-            .ok()
-            .map(|rssi| rssi as i8);
+        if let Ok(rssi) = args.raw_signal_strength_in_dbm() {
+            properties.rssi = Some(rssi);
+            properties.signal.record(rssi);
+        }
     }
 }
 
@@ -204,7 +218,10 @@ impl Debug for Peripheral {
     }
 }
 
-impl ApiPeripheral for Peripheral {
+// TODO: The WinRT calls below still wait for their result, so these futures complete the first
+// time they are polled. They should await the WinRT async operations instead.
+#[async_trait]
+impl AsyncPeripheral for Peripheral {
     /// Returns the address of the peripheral.
     fn address(&self) -> BDAddr {
         self.address.clone()
@@ -224,6 +241,18 @@ impl ApiPeripheral for Peripheral {
         l.clone()
     }
 
+    /// The set of services we've discovered for this device. Characteristics aren't grouped by
+    /// service on this platform yet, so this is always empty.
+    fn services(&self) -> BTreeSet<Service> {
+        BTreeSet::new()
+    }
+
+    /// The set of descriptors we've discovered for the given characteristic. Descriptor discovery
+    /// isn't implemented on this platform yet, so this is always empty.
+    fn descriptors(&self, _characteristic: &Characteristic) -> BTreeSet<Descriptor> {
+        BTreeSet::new()
+    }
+
     /// Returns true iff we are currently connected to the device.
     fn is_connected(&self) -> bool {
         self.connected.load(Ordering::Relaxed)
@@ -232,7 +261,7 @@ impl ApiPeripheral for Peripheral {
     /// Creates a connection to the device. This is a synchronous operation; if this method returns
     /// Ok there has been successful connection. Note that peripherals allow only one connection at
     /// a time. Operations that attempt to communicate with a device will fail until it is connected.
-    fn connect(&self) -> Result<()> {
+    async fn connect(&self) -> Result<()> {
         let connected = self.connected.clone();
         let adapter_clone = self.adapter.clone();
         let address_clone = self.address.clone();
@@ -255,7 +284,7 @@ impl ApiPeripheral for Peripheral {
     }
 
     /// Terminates a connection to the device. This is a synchronous operation.
-    fn disconnect(&self) -> Result<()> {
+    async fn disconnect(&self) -> Result<()> {
         let winrt_error = |e| Error::Other(format!("{:?}", e));
         let mut device = self.device.lock().map_err(winrt_error)?;
         *device = None;
@@ -265,7 +294,7 @@ impl ApiPeripheral for Peripheral {
     }
 
     /// Discovers all characteristics for the device. This is a synchronous operation.
-    fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
         let device = self.device.lock().unwrap();
         if let Some(ref device) = *device {
             let mut characteristics_result = vec![];
@@ -293,7 +322,7 @@ impl ApiPeripheral for Peripheral {
 
     /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
     /// the case of a write-with-response) if the device returns an error.
-    fn write(
+    async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
@@ -310,7 +339,7 @@ impl ApiPeripheral for Peripheral {
     /// characteristic and for the specified declaration UUID. See
     /// [here](https://www.bluetooth.com/specifications/gatt/declarations) for valid UUIDs.
     /// Synchronously returns either an error or the device response.
-    fn read_by_type(&self, characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
+    async fn read_by_type(&self, characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
         if let Some(ble_characteristic) = self.ble_characteristics.get(&characteristic.uuid) {
             return ble_characteristic.read_value();
         } else {
@@ -318,9 +347,19 @@ impl ApiPeripheral for Peripheral {
         }
     }
 
+    /// Sends a read request for the descriptor to the device. Not supported on this platform yet.
+    async fn read_descriptor(&self, _descriptor: &Descriptor) -> Result<Vec<u8>> {
+        Err(Error::NotSupported("read_descriptor".into()))
+    }
+
+    /// Writes some data to the descriptor. Not supported on this platform yet.
+    async fn write_descriptor(&self, _descriptor: &Descriptor, _data: &[u8]) -> Result<()> {
+        Err(Error::NotSupported("write_descriptor".into()))
+    }
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
-    fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
         if let Some(mut ble_characteristic) = self.ble_characteristics.get_mut(&characteristic.uuid)
         {
             let notification_handlers = self.notification_handlers.clone();
@@ -329,9 +368,9 @@ impl ApiPeripheral for Peripheral {
                 let notification = ValueNotification {
                     uuid: uuid,
                     handle: None,
-                    value,
+                    value: value.into(),
                 };
-                util::invoke_handlers(&notification_handlers, &notification);
+                notification_handlers.notify(notification);
             }))
         } else {
             Err(Error::NotSupported("subscribe".into()))
@@ -340,7 +379,7 @@ impl ApiPeripheral for Peripheral {
 
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
-    fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
         if let Some(mut ble_characteristic) = self.ble_characteristics.get_mut(&characteristic.uuid)
         {
             ble_characteristic.unsubscribe()
@@ -352,12 +391,23 @@ impl ApiPeripheral for Peripheral {
     /// Registers a handler that will be called when value notification messages are received from
     /// the device. This method should only be used after a connection has been established. Note
     /// that the handler will be called in a common thread, so it should not block.
-    fn on_notification(&self, handler: NotificationHandler) {
-        let mut list = self.notification_handlers.lock().unwrap();
-        list.push(handler);
+    fn on_notification(&self, handler: NotificationHandler) -> NotificationHandlerId {
+        self.notification_handlers.add(handler)
+    }
+
+    fn remove_notification_handler(&self, id: NotificationHandlerId) -> bool {
+        self.notification_handlers.remove(id)
+    }
+
+    fn notifications(&self, characteristic: &Characteristic) -> NotificationReceiver {
+        NotificationReceiver::new(&self.notification_handlers, characteristic)
+    }
+
+    fn set_notification_queue(&self, capacity: usize, overflow: OverflowPolicy) -> Result<()> {
+        self.notification_handlers.set_queue(capacity, overflow)
     }
 
-    fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
         if let Some(ble_characteristic) = self.ble_characteristics.get(&characteristic.uuid) {
             return ble_characteristic.read_value();
         } else {